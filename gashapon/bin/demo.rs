@@ -1,18 +1,187 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+#[cfg(any(feature = "json", feature = "toml"))]
+use gashapon::config::PrizeTable;
+#[cfg(feature = "csv")]
+use gashapon::csv::{self, CsvColumns};
 use gashapon::{Gashapon, GashaponItem, PrizeItem};
+#[cfg(feature = "json")]
+use gashapon::{Registry, session::SessionLog, store::JsonFileStore};
+
+fn default_machine() -> Gashapon {
+    Gashapon::builder()
+        .add_items(vec![
+            GashaponItem::new(PrizeItem::new("S")).with_quantity(1),
+            GashaponItem::new(PrizeItem::new("A")).with_quantity(2),
+            GashaponItem::new(PrizeItem::new("B")).with_quantity(3),
+            GashaponItem::new(PrizeItem::new("C")).with_quantity(5),
+            GashaponItem::new(PrizeItem::new("D")).with_quantity(12),
+            GashaponItem::new(PrizeItem::new("E")).with_quantity(15),
+            GashaponItem::new(PrizeItem::new("F")).with_quantity(20),
+            GashaponItem::new(PrizeItem::new("G")).with_quantity(22),
+        ])
+        .with_seed(12345)
+        .build()
+        .expect("The default machine is valid")
+}
+
+/// Load a machine from a TOML or JSON prize table, or a CSV inventory.
+#[cfg_attr(
+    not(any(feature = "csv", feature = "json", feature = "toml")),
+    allow(unused_variables)
+)]
+fn load_machine(path: &str) -> Result<Gashapon, String> {
+    let source = fs::read_to_string(path).map_err(|err| format!("{path}: {err}"))?;
+    let gashpon = match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+        #[cfg(feature = "toml")]
+        Some("toml") => PrizeTable::from_toml_str(&source)
+            .map_err(|err| err.to_string())
+            .and_then(|table| table.to_gashapon().map_err(|err| err.to_string())),
+        #[cfg(feature = "json")]
+        Some("json") => PrizeTable::from_json_str(&source)
+            .map_err(|err| err.to_string())
+            .and_then(|table| table.to_gashapon().map_err(|err| err.to_string())),
+        #[cfg(feature = "csv")]
+        Some("csv") => {
+            let mut gashpon = Gashapon::default();
+            gashpon
+                .import_csv(source.as_bytes(), &CsvColumns::default())
+                .map_err(|err| err.to_string())?
+                .with_seed(12345)
+                .map_err(|err| err.to_string())?
+                .try_build()
+                .map_err(|err| err.to_string())?;
+            Ok(gashpon)
+        }
+        _ => Err("unsupported file format".to_string()),
+    };
+    gashpon.map_err(|err| format!("{path}: {err}"))
+}
+
+/// Write the inventory, draw rates and draw history as CSV files into `dir`.
+#[cfg(feature = "csv")]
+fn export_csv(gashpon: &Gashapon, dir: &str) -> Result<(), String> {
+    let dir = Path::new(dir);
+    fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+    let create = |name: &str| fs::File::create(dir.join(name)).map_err(|err| err.to_string());
+    csv::export_inventory(gashpon, create("inventory.csv")?).map_err(|err| err.to_string())?;
+    csv::export_draw_rates(gashpon, create("draw_rates.csv")?).map_err(|err| err.to_string())?;
+    csv::export_history(gashpon, create("history.csv")?).map_err(|err| err.to_string())?;
+    println!("Exported CSV files to {}", dir.display());
+    Ok(())
+}
+
+#[cfg(not(feature = "csv"))]
+fn export_csv(_gashpon: &Gashapon, _dir: &str) -> Result<(), String> {
+    Err("CSV export needs the `csv` feature".to_string())
+}
+
+/// Save the machine and its history into a JSON store, replacing the
+/// machine saved by a previous run.
+#[cfg(feature = "json")]
+fn save_to_store(gashpon: &Gashapon, path: &str) -> Result<(), String> {
+    let mut store = JsonFileStore::open(path).map_err(|err| err.to_string())?;
+    let mut registry = Registry::load(&store).map_err(|err| err.to_string())?;
+    if let Some(id) = registry.find("demo") {
+        registry.delete(id).map_err(|err| err.to_string())?;
+    }
+    registry
+        .create("demo", gashpon.clone())
+        .map_err(|err| err.to_string())?;
+    registry.save(&mut store).map_err(|err| err.to_string())?;
+    println!("Saved the machine to {path}");
+    Ok(())
+}
+
+#[cfg(not(feature = "json"))]
+fn save_to_store(_gashpon: &Gashapon, _path: &str) -> Result<(), String> {
+    Err("Saving to a store needs the `json` feature".to_string())
+}
+
+/// Write the session so far as a JSON log for `demo replay`.
+#[cfg(feature = "json")]
+fn save_session(gashpon: &Gashapon, path: &str) -> Result<(), String> {
+    let log = SessionLog::from_gashapon(gashpon)
+        .map_err(|err| format!("The session cannot be replayed: {err}"))?;
+    let json = log.to_json_string().map_err(|err| err.to_string())?;
+    fs::write(path, json).map_err(|err| format!("{path}: {err}"))?;
+    println!("Saved the session log to {path}");
+    Ok(())
+}
+
+#[cfg(not(feature = "json"))]
+fn save_session(_gashpon: &Gashapon, _path: &str) -> Result<(), String> {
+    Err("Session logs need the `json` feature".to_string())
+}
+
+/// Re-execute a JSON session log and report the first draw that came out
+/// differently.
+#[cfg(feature = "json")]
+fn replay_session(path: &str) -> Result<(), String> {
+    let source = fs::read_to_string(path).map_err(|err| format!("{path}: {err}"))?;
+    let log = SessionLog::from_json_str(&source).map_err(|err| format!("{path}: {err}"))?;
+    let draws = log
+        .replay()
+        .map_err(|divergence| format!("{path}: {divergence}"))?;
+    println!("All {draws} draws match.");
+    Ok(())
+}
+
+#[cfg(not(feature = "json"))]
+fn replay_session(_path: &str) -> Result<(), String> {
+    Err("Replaying a session needs the `json` feature".to_string())
+}
+
+fn exit_with(err: String) -> ! {
+    eprintln!("{err}");
+    process::exit(1);
+}
 
 fn main() {
-    let mut gashpon = Gashapon::default();
-    gashpon.add_items(vec![
-        GashaponItem::new(PrizeItem::new("S")).with_quantity(1),
-        GashaponItem::new(PrizeItem::new("A")).with_quantity(2),
-        GashaponItem::new(PrizeItem::new("B")).with_quantity(3),
-        GashaponItem::new(PrizeItem::new("C")).with_quantity(5),
-        GashaponItem::new(PrizeItem::new("D")).with_quantity(12),
-        GashaponItem::new(PrizeItem::new("E")).with_quantity(15),
-        GashaponItem::new(PrizeItem::new("F")).with_quantity(20),
-        GashaponItem::new(PrizeItem::new("G")).with_quantity(22),
-    ]);
-    gashpon.with_seed(12345).build();
+    if env::args().nth(1).as_deref() == Some("replay") {
+        let path = env::args()
+            .nth(2)
+            .unwrap_or_else(|| exit_with("replay needs a session log".to_string()));
+        replay_session(&path).unwrap_or_else(|err| exit_with(err));
+        return;
+    }
+
+    let mut path = None;
+    let mut export_dir = None;
+    let mut store_path = None;
+    let mut log_path = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--export" => {
+                export_dir = Some(
+                    args.next()
+                        .unwrap_or_else(|| exit_with("--export needs a directory".to_string())),
+                )
+            }
+            "--store" => {
+                store_path = Some(
+                    args.next()
+                        .unwrap_or_else(|| exit_with("--store needs a file".to_string())),
+                )
+            }
+            "--log" => {
+                log_path = Some(
+                    args.next()
+                        .unwrap_or_else(|| exit_with("--log needs a file".to_string())),
+                )
+            }
+            _ => path = Some(arg),
+        }
+    }
+
+    let mut gashpon = match path {
+        Some(path) => load_machine(&path).unwrap_or_else(|err| exit_with(err)),
+        None => default_machine(),
+    };
     // Calculate the draw rate of each item
     let draw_rate = gashpon
         .calculate_draw_rate()
@@ -23,29 +192,38 @@ fn main() {
     let mut draw_rate: Vec<_> = draw_rate.into_iter().collect();
     draw_rate.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
 
-    println!("Original items: {:?}", gashpon.items);
+    println!("Original items: {:?}", gashpon.items());
     println!(
         "Randomly sorted items: {:?}",
-        gashpon.prizes.get_randomized_items()
+        gashpon.prizes().get_randomized_items()
     );
 
-    println!("Randomly sorted indices: {:?}", gashpon.prizes.idx_box);
+    println!("Randomly sorted indices: {:?}", gashpon.prizes().idx_box());
 
-    loop {
+    // A weighted machine, or one with unlimited items, never runs empty,
+    // so stop after as many draws as there are capsules
+    let capsules = gashpon.prizes().quantity();
+    for _ in 0..capsules {
+        if gashpon.is_empty() {
+            break;
+        }
         // Draw a prize item from the random sort items
         let my_prize = gashpon.draw();
 
-        println!("Remaining index: {:?}", gashpon.prizes.idx_box);
+        println!("Remaining index: {:?}", gashpon.prizes().idx_box());
         println!(
             "Remaining items: {:?}",
-            gashpon.prizes.get_randomized_items()
+            gashpon.prizes().get_randomized_items()
         );
         println!("Draw item: {:?}", my_prize);
-        println!("Items: {:?}", gashpon.items);
-        if gashpon.prizes.idx_box.len() == 0 {
-            println!("No more items left to draw.");
-            break;
-        }
+        println!("Items: {:?}", gashpon.items());
+    }
+    match gashpon.is_empty() {
+        true => println!("No more items left to draw."),
+        false => println!("Stopped after {capsules} draws."),
+    }
+    if let Some(path) = log_path {
+        save_session(&gashpon, &path).unwrap_or_else(|err| exit_with(err));
     }
 
     println!(
@@ -57,6 +235,25 @@ fn main() {
     );
 
     // Restore the items to the original state
-    gashpon.restore_items();
-    println!("Restored items: {:?}", gashpon.items);
+    gashpon
+        .restore_items()
+        .unwrap_or_else(|err| exit_with(err.to_string()));
+    println!("Restored items: {:?}", gashpon.items());
+
+    // Keep drawing until the S prize comes out
+    match gashpon
+        .draws()
+        .take(capsules)
+        .position(|prize| prize.name == "S")
+    {
+        Some(n) => println!("Got S after {} draws.", n + 1),
+        None => println!("No S in {capsules} draws."),
+    }
+
+    if let Some(dir) = export_dir {
+        export_csv(&gashpon, &dir).unwrap_or_else(|err| exit_with(err));
+    }
+    if let Some(path) = store_path {
+        save_to_store(&gashpon, &path).unwrap_or_else(|err| exit_with(err));
+    }
 }
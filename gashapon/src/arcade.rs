@@ -0,0 +1,752 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt;
+
+use crate::effect::resolve;
+use crate::{
+    DrawMode, DrawOutcome, EffectError, Gashapon, GashaponItem, GetPrizeItemId, MachineError,
+    MachineEvent, MachineState, PrizeEffect, PrizeId, PrizeItem, Validation,
+};
+
+/// Named machines whose prizes can grant draws on each other, stocked from
+/// one shared warehouse.
+///
+/// Stock moves between the warehouse and the machines, or from machine to
+/// machine, while the machines involved are in draft or paused; drawing
+/// takes it out of the arcade for good. Unlimited items are not counted.
+/// While a machine is in the arcade its stock only moves through it, so it
+/// cannot be restored, cleared or edited item by item, and its reset
+/// policies are ignored.
+#[derive(Debug, Default)]
+pub struct Arcade {
+    machines: BTreeMap<String, Gashapon>,
+    warehouse: HashMap<PrizeId, GashaponItem>,
+}
+
+/// A stock movement the arcade refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StockError {
+    UnknownMachine(String),
+    /// A machine involved cannot be restocked in its current state.
+    Machine(MachineError),
+    /// The prize is unlimited in the machine, so it has no stock to move.
+    Unlimited(PrizeId),
+    /// The source holds fewer than the requested quantity.
+    NotEnoughStock {
+        id: PrizeId,
+        requested: u64,
+        available: u64,
+    },
+    /// The target would hold more of the prize than fits in a `u64`.
+    Overflow(PrizeId),
+    /// The target machine would be invalid with the stock moved in, such as
+    /// holding more capsules than allowed.
+    Invalid(Validation),
+}
+
+impl fmt::Display for StockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StockError::UnknownMachine(name) => write!(f, "no machine named \"{name}\""),
+            StockError::Machine(err) => write!(f, "{err}"),
+            StockError::Unlimited(id) => write!(f, "prize {id} is unlimited in the machine"),
+            StockError::NotEnoughStock {
+                id,
+                requested,
+                available,
+            } => write!(
+                f,
+                "cannot move {requested} of prize {id}, only {available} in stock"
+            ),
+            StockError::Overflow(id) => write!(f, "stock of prize {id} would overflow"),
+            StockError::Invalid(validation) => write!(f, "{validation}"),
+        }
+    }
+}
+
+impl Error for StockError {}
+
+impl From<MachineError> for StockError {
+    fn from(err: MachineError) -> Self {
+        StockError::Machine(err)
+    }
+}
+
+impl Arcade {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a machine, returning the one it replaces. The stock the machine
+    /// holds joins the arcade's.
+    pub fn add_machine<T>(&mut self, name: T, mut gashapon: Gashapon) -> Option<Gashapon>
+    where
+        T: ToString,
+    {
+        gashapon.shared_stock = true;
+        let mut replaced = self.machines.insert(name.to_string(), gashapon)?;
+        replaced.shared_stock = false;
+        Some(replaced)
+    }
+
+    /// Remove a machine, taking its stock out of the arcade with it.
+    pub fn remove_machine(&mut self, name: &str) -> Option<Gashapon> {
+        let mut gashapon = self.machines.remove(name)?;
+        gashapon.shared_stock = false;
+        Some(gashapon)
+    }
+
+    pub fn machine(&self, name: &str) -> Option<&Gashapon> {
+        self.machines.get(name)
+    }
+
+    pub fn machine_mut(&mut self, name: &str) -> Option<&mut Gashapon> {
+        self.machines.get_mut(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.machines.keys().map(String::as_str)
+    }
+
+    /// Add stock to the warehouse, on top of any already there.
+    pub fn add_stock(&mut self, item: GashaponItem) -> Result<&mut Self, StockError> {
+        match self.warehouse.get_mut(&item.get_prize_id()) {
+            Some(stock) => stock.quantity = added(stock.quantity, &item)?,
+            None => {
+                self.warehouse.insert(item.get_prize_id(), item);
+            }
+        }
+        Ok(self)
+    }
+
+    /// Stock of each prize held in the warehouse and not in any machine.
+    pub fn warehouse_stock(&self) -> HashMap<PrizeId, u64> {
+        self.warehouse
+            .iter()
+            .map(|(id, item)| (id.clone(), item.quantity))
+            .collect()
+    }
+
+    /// Stock of each prize left in the named machine.
+    pub fn machine_stock(&self, name: &str) -> Option<HashMap<PrizeId, u64>> {
+        let gashapon = self.machines.get(name)?;
+        Some(
+            gashapon
+                .items()
+                .iter()
+                .filter(|(_, item)| !item.is_unlimited())
+                .map(|(id, item)| (id.clone(), item.quantity))
+                .collect(),
+        )
+    }
+
+    /// Stock of each prize left anywhere in the arcade.
+    pub fn total_stock(&self) -> Result<HashMap<PrizeId, u64>, StockError> {
+        let mut total = self.warehouse_stock();
+        for name in self.machines.keys() {
+            for (id, quantity) in self.machine_stock(name).unwrap_or_default() {
+                let stock = total.entry(id.clone()).or_default();
+                *stock = stock
+                    .checked_add(quantity)
+                    .ok_or(StockError::Overflow(id))?;
+            }
+        }
+        Ok(total)
+    }
+
+    /// Move stock from the warehouse into a machine.
+    pub fn allocate(&mut self, name: &str, id: PrizeId, quantity: u64) -> Result<(), StockError> {
+        let gashapon = self
+            .machines
+            .get(name)
+            .ok_or_else(|| StockError::UnknownMachine(name.to_string()))?;
+        check_restockable(gashapon)?;
+        check_limited(gashapon, &id)?;
+        let held = gashapon
+            .get_item(id.clone())
+            .map_or(0, |item| item.quantity);
+        let stock = self.warehouse.get(&id);
+        let available = stock.map_or(0, |item| item.quantity);
+        let Some(stock) = stock.filter(|_| available >= quantity) else {
+            return Err(StockError::NotEnoughStock {
+                id,
+                requested: quantity,
+                available,
+            });
+        };
+
+        let item = GashaponItem::new(stock.prize.clone()).with_quantity(quantity);
+        let total = added(held, &item)?;
+        check_room(gashapon, &item.prize, total)?;
+        self.warehouse
+            .get_mut(&id)
+            .expect("Stock was checked")
+            .quantity -= quantity;
+        self.machines
+            .get_mut(name)
+            .expect("Machine was checked")
+            .set_stock(item.with_quantity(total));
+        Ok(())
+    }
+
+    /// Move stock from a machine back into the warehouse.
+    pub fn withdraw(&mut self, name: &str, id: PrizeId, quantity: u64) -> Result<(), StockError> {
+        let held = self.warehouse.get(&id).map_or(0, |item| item.quantity);
+        held.checked_add(quantity)
+            .ok_or_else(|| StockError::Overflow(id.clone()))?;
+        let item = self.take_stock(name, &id, quantity)?;
+        self.add_stock(item)?;
+        Ok(())
+    }
+
+    /// Move stock from one machine to another.
+    pub fn transfer(
+        &mut self,
+        from: &str,
+        to: &str,
+        id: PrizeId,
+        quantity: u64,
+    ) -> Result<(), StockError> {
+        let target = self
+            .machines
+            .get(to)
+            .ok_or_else(|| StockError::UnknownMachine(to.to_string()))?;
+        check_restockable(target)?;
+        check_limited(target, &id)?;
+        let held = target.get_item(id.clone()).map_or(0, |item| item.quantity);
+        let total = held
+            .checked_add(quantity)
+            .ok_or_else(|| StockError::Overflow(id.clone()))?;
+        if let Some(item) = self
+            .machines
+            .get(from)
+            .and_then(|source| source.get_item(id.clone()))
+        {
+            check_room(target, &item.prize, total)?;
+        }
+        let item = self.take_stock(from, &id, quantity)?;
+        self.machines
+            .get_mut(to)
+            .expect("Machine was checked")
+            .set_stock(item.with_quantity(total));
+        Ok(())
+    }
+
+    /// Take `quantity` of a prize out of a machine.
+    fn take_stock(
+        &mut self,
+        name: &str,
+        id: &PrizeId,
+        quantity: u64,
+    ) -> Result<GashaponItem, StockError> {
+        let gashapon = self
+            .machines
+            .get_mut(name)
+            .ok_or_else(|| StockError::UnknownMachine(name.to_string()))?;
+        check_restockable(gashapon)?;
+        check_limited(gashapon, id)?;
+        let item = gashapon.get_item(id.clone());
+        // Reserved capsules on a paused board stay where they are
+        let available = match (gashapon.state(), gashapon.mode()) {
+            (MachineState::Paused, DrawMode::Box) => gashapon
+                .prizes
+                .positions_where(|prize| prize.get_id() == *id)
+                .count() as u64,
+            _ => item.map_or(0, |item| item.quantity),
+        };
+        let Some(item) = item.filter(|_| available >= quantity) else {
+            return Err(StockError::NotEnoughStock {
+                id: id.clone(),
+                requested: quantity,
+                available,
+            });
+        };
+
+        let prize = item.prize.clone();
+        let left = item.quantity - quantity;
+        gashapon.set_stock(GashaponItem::new(prize.clone()).with_quantity(left));
+        Ok(GashaponItem::new(prize).with_quantity(quantity))
+    }
+
+    /// Draw on the named machine and resolve every draw its prizes grant,
+    /// here or on other machines. Each chained draw is recorded in its
+    /// machine's history with a [`DrawCause`](crate::DrawCause).
+    ///
+    /// Nothing is drawn if a machine reachable from `name` is missing or
+    /// machines reachable from it grant draws on each other in a loop.
+    pub fn draw(&mut self, name: &str) -> Result<DrawOutcome, EffectError> {
+        self.check_chain(name)?;
+        resolve(Some(name.to_string()), |machine, cause| {
+            let gashapon = self.machines.get_mut(machine?)?;
+            Some(gashapon.draw_caused(cause))
+        })
+    }
+
+    /// Find machines that grant draws on each other in a loop, including a
+    /// machine granting draws on itself.
+    pub fn find_loop(&self) -> Option<Vec<String>> {
+        let mut done = BTreeSet::new();
+        self.machines.keys().find_map(|name| {
+            match self.walk(name, false, &mut Vec::new(), &mut done) {
+                Err(EffectError::Loop(path)) => Some(path),
+                _ => None,
+            }
+        })
+    }
+
+    fn check_chain(&self, name: &str) -> Result<(), EffectError> {
+        self.walk(name, true, &mut Vec::new(), &mut BTreeSet::new())
+    }
+
+    /// Depth-first walk over the machines `name` grants draws on. Unknown
+    /// machines are an error when `strict`, and skipped otherwise.
+    fn walk<'a>(
+        &'a self,
+        name: &'a str,
+        strict: bool,
+        path: &mut Vec<&'a str>,
+        done: &mut BTreeSet<&'a str>,
+    ) -> Result<(), EffectError> {
+        if let Some(start) = path.iter().position(|visited| *visited == name) {
+            let mut cycle = path[start..]
+                .iter()
+                .map(|visited| visited.to_string())
+                .collect::<Vec<_>>();
+            cycle.push(name.to_string());
+            return Err(EffectError::Loop(cycle));
+        }
+        if done.contains(name) {
+            return Ok(());
+        }
+        let Some(gashapon) = self.machines.get(name) else {
+            if strict {
+                return Err(EffectError::UnknownMachine(name.to_string()));
+            }
+            return Ok(());
+        };
+
+        path.push(name);
+        for item in gashapon.items().values() {
+            if let Some(PrizeEffect::DrawOn(target)) = &item.prize.effect {
+                self.walk(target, strict, path, done)?;
+            }
+        }
+        path.pop();
+        done.insert(name);
+        Ok(())
+    }
+}
+
+fn check_restockable(gashapon: &Gashapon) -> Result<(), MachineError> {
+    match gashapon.state() {
+        MachineState::Draft | MachineState::Paused => Ok(()),
+        state => Err(MachineError::NotEditable(state)),
+    }
+}
+
+/// Check that the machine can hold `quantity` of the prize.
+fn check_room(gashapon: &Gashapon, prize: &PrizeItem, quantity: u64) -> Result<(), StockError> {
+    gashapon
+        .check_rebuild(|items| {
+            items.retain(|item| item.get_prize_id() != prize.get_id());
+            items.push(GashaponItem::new(prize.clone()).with_quantity(quantity));
+        })
+        .map_err(StockError::Invalid)
+}
+
+/// `held` with the item's quantity added, if it fits.
+fn added(held: u64, item: &GashaponItem) -> Result<u64, StockError> {
+    held.checked_add(item.quantity)
+        .ok_or_else(|| StockError::Overflow(item.get_prize_id()))
+}
+
+fn check_limited(gashapon: &Gashapon, id: &PrizeId) -> Result<(), StockError> {
+    match gashapon.get_item(id.clone()) {
+        Some(item) if item.is_unlimited() => Err(StockError::Unlimited(id.clone())),
+        _ => Ok(()),
+    }
+}
+
+impl Gashapon {
+    /// Replace an item's stock. A draft machine's box is rebuilt; a paused
+    /// one is restocked in place, so its board and random state carry on.
+    fn set_stock(&mut self, item: GashaponItem) {
+        match self.state {
+            MachineState::Paused => {
+                self.restock(&item);
+                self.record(MachineEvent::Restocked(item));
+            }
+            _ => {
+                self.items.insert(item.get_prize_id(), item.clone());
+                self.record(MachineEvent::ItemAdded(item));
+                self.rebuild();
+            }
+        }
+    }
+
+    /// Bring an item to its new stock without reshuffling. Capsules added
+    /// get new slots, queued after every other; capsules taken out come
+    /// from the unreserved slots queued last, which are left withdrawn.
+    pub(crate) fn restock(&mut self, item: &GashaponItem) {
+        let id = item.get_prize_id();
+        let held = self.items.get(&id).map_or(0, |item| item.quantity);
+        let prizes = &mut self.prizes;
+        match self.mode {
+            DrawMode::Box if item.quantity >= held => {
+                for _ in held..item.quantity {
+                    prizes.items.push(item.prize.clone());
+                    prizes.randomized_items.push(Some(prizes.items.len() - 1));
+                    prizes.opened.push(None);
+                    prizes.idx_box.push(prizes.randomized_items.len() - 1);
+                }
+            }
+            DrawMode::Box => {
+                let mut excess = held - item.quantity;
+                let mut pos = prizes.idx_box.len();
+                while excess > 0 && pos > 0 {
+                    pos -= 1;
+                    let slot = prizes.idx_box[pos];
+                    let is_prize = prizes
+                        .get_item_by_index(prizes.randomized_items[slot])
+                        .is_some_and(|prize| prize.get_id() == id);
+                    if is_prize && !prizes.reserved.contains(&slot) {
+                        prizes.idx_box.remove(pos);
+                        prizes.randomized_items[slot] = None;
+                        excess -= 1;
+                    }
+                }
+            }
+            DrawMode::Weighted if item.quantity >= held => {
+                for _ in held..item.quantity {
+                    prizes.items.push(item.prize.clone());
+                }
+            }
+            DrawMode::Weighted => {
+                for _ in item.quantity..held {
+                    prizes.remove_weighted(&id);
+                }
+            }
+        }
+        self.items.insert(id, item.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        BoardSlot, BuildError, DrawCause, GashaponItem, MachineError, PrizeId, PrizeItem,
+        ResetPolicy,
+    };
+
+    fn machine(items: Vec<GashaponItem>) -> Gashapon {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(items)
+            .unwrap()
+            .with_seed(12345)
+            .unwrap()
+            .build()
+            .unwrap();
+        gashapon
+    }
+
+    #[test]
+    fn test_draw_chains_across_machines() {
+        let mut arcade = Arcade::new();
+        arcade.add_machine(
+            "Main",
+            machine(vec![
+                GashaponItem::new(
+                    PrizeItem::new("Token").with_effect(PrizeEffect::DrawOn("Bonus".to_string())),
+                )
+                .with_quantity(1),
+            ]),
+        );
+        arcade.add_machine(
+            "Bonus",
+            machine(vec![
+                GashaponItem::new(PrizeItem::new("Ticket").with_effect(PrizeEffect::FreeDraws(1)))
+                    .with_quantity(1),
+                GashaponItem::new(PrizeItem::new("Figure")).with_quantity(1),
+            ]),
+        );
+
+        let outcome = arcade.draw("Main").unwrap();
+        let drawn = outcome
+            .draws
+            .iter()
+            .map(|draw| (draw.machine.clone().unwrap(), draw.prize.name.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(drawn[0], ("Main".to_string(), "Token".to_string()));
+        assert_eq!(drawn[1].0, "Bonus");
+        assert!(outcome.unresolved.is_empty());
+
+        let bonus = arcade.machine("Bonus").unwrap();
+        assert_eq!(
+            bonus.history()[0].cause,
+            DrawCause::Effect {
+                machine: "Main".to_string(),
+                prize: PrizeId::new("Token"),
+            }
+        );
+        if drawn[1].1 == "Ticket" {
+            assert_eq!(drawn.len(), 3);
+            assert!(bonus.is_empty());
+            assert_eq!(
+                bonus.history()[1].cause,
+                DrawCause::FreeDraw(PrizeId::new("Ticket"))
+            );
+        } else {
+            assert_eq!(drawn.len(), 2);
+        }
+
+        assert_eq!(
+            arcade.draw("Main").unwrap_err(),
+            EffectError::Machine(MachineError::Empty)
+        );
+        assert_eq!(
+            arcade.draw("Nowhere").unwrap_err(),
+            EffectError::UnknownMachine("Nowhere".to_string())
+        );
+    }
+
+    #[test]
+    fn test_loops_are_refused_before_drawing() {
+        let mut arcade = Arcade::new();
+        arcade.add_machine(
+            "A",
+            machine(vec![
+                GashaponItem::new(
+                    PrizeItem::new("ToB").with_effect(PrizeEffect::DrawOn("B".to_string())),
+                )
+                .with_quantity(1),
+            ]),
+        );
+        arcade.add_machine(
+            "B",
+            machine(vec![
+                GashaponItem::new(
+                    PrizeItem::new("ToA").with_effect(PrizeEffect::DrawOn("A".to_string())),
+                )
+                .with_quantity(1),
+            ]),
+        );
+        arcade.add_machine(
+            "C",
+            machine(vec![
+                GashaponItem::new(
+                    PrizeItem::new("ToD").with_effect(PrizeEffect::DrawOn("D".to_string())),
+                )
+                .with_quantity(1),
+            ]),
+        );
+
+        let path = vec!["A".to_string(), "B".to_string(), "A".to_string()];
+        assert_eq!(arcade.find_loop(), Some(path.clone()));
+        assert_eq!(arcade.draw("A").unwrap_err(), EffectError::Loop(path));
+        assert_eq!(
+            arcade.draw("C").unwrap_err(),
+            EffectError::UnknownMachine("D".to_string())
+        );
+        assert!(arcade.machine("A").unwrap().history().is_empty());
+
+        arcade.remove_machine("B");
+        assert_eq!(arcade.find_loop(), None);
+        assert_eq!(
+            arcade.draw("A").unwrap_err(),
+            EffectError::UnknownMachine("B".to_string())
+        );
+    }
+
+    #[test]
+    fn test_shared_stock() {
+        let figure = PrizeItem::new("Figure");
+        let id = figure.get_id();
+        let mut arcade = Arcade::new();
+        arcade
+            .add_stock(GashaponItem::new(figure.clone()).with_quantity(10))
+            .unwrap();
+        arcade.add_machine("North", Gashapon::default());
+        arcade.add_machine(
+            "South",
+            machine(vec![GashaponItem::new(figure).with_quantity(2)]),
+        );
+        assert_eq!(arcade.total_stock().unwrap()[&id], 12);
+
+        arcade.allocate("North", id.clone(), 6).unwrap();
+        assert_eq!(arcade.warehouse_stock()[&id], 4);
+        assert_eq!(arcade.machine_stock("North").unwrap()[&id], 6);
+        assert_eq!(arcade.machine("North").unwrap().remaining(), 6);
+        assert_eq!(
+            arcade.allocate("North", id.clone(), 5).unwrap_err(),
+            StockError::NotEnoughStock {
+                id: id.clone(),
+                requested: 5,
+                available: 4,
+            }
+        );
+
+        arcade.transfer("North", "South", id.clone(), 3).unwrap();
+        assert_eq!(arcade.machine_stock("North").unwrap()[&id], 3);
+        assert_eq!(arcade.machine_stock("South").unwrap()[&id], 5);
+        assert_eq!(arcade.total_stock().unwrap()[&id], 12);
+
+        arcade.draw("South").unwrap();
+        assert_eq!(arcade.total_stock().unwrap()[&id], 11);
+        assert_eq!(
+            arcade
+                .transfer("North", "South", id.clone(), 1)
+                .unwrap_err(),
+            StockError::Machine(MachineError::NotEditable(MachineState::Live))
+        );
+        arcade.machine_mut("South").unwrap().pause().unwrap();
+        arcade.withdraw("South", id.clone(), 4).unwrap();
+        assert_eq!(arcade.warehouse_stock()[&id], 8);
+        assert_eq!(arcade.machine("South").unwrap().remaining(), 0);
+        assert_eq!(
+            arcade.withdraw("West", id, 1).unwrap_err(),
+            StockError::UnknownMachine("West".to_string())
+        );
+    }
+
+    #[test]
+    fn test_stock_overflow_is_refused() {
+        let figure = PrizeItem::new("Figure");
+        let id = figure.get_id();
+        let mut arcade = Arcade::new();
+        arcade
+            .add_stock(GashaponItem::new(figure.clone()).with_quantity(u64::MAX))
+            .unwrap();
+        assert_eq!(
+            arcade
+                .add_stock(GashaponItem::new(figure.clone()).with_quantity(1))
+                .unwrap_err(),
+            StockError::Overflow(id.clone())
+        );
+        assert_eq!(arcade.warehouse_stock()[&id], u64::MAX);
+
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_item(GashaponItem::new(figure).with_quantity(1))
+            .unwrap();
+        arcade.add_machine("North", gashapon);
+        assert_eq!(
+            arcade.total_stock().unwrap_err(),
+            StockError::Overflow(id.clone())
+        );
+        assert_eq!(
+            arcade.allocate("North", id.clone(), u64::MAX).unwrap_err(),
+            StockError::Overflow(id.clone())
+        );
+        assert!(matches!(
+            arcade
+                .allocate("North", id.clone(), 1_000_000_000_000)
+                .unwrap_err(),
+            StockError::Invalid(_)
+        ));
+        assert_eq!(
+            arcade.withdraw("North", id.clone(), 1).unwrap_err(),
+            StockError::Overflow(id.clone())
+        );
+        assert_eq!(arcade.warehouse_stock()[&id], u64::MAX);
+        assert_eq!(arcade.machine_stock("North").unwrap()[&id], 1);
+    }
+
+    #[test]
+    fn test_paused_machines_are_restocked_in_place() {
+        let figure = PrizeItem::new("Figure");
+        let id = figure.get_id();
+        let mut arcade = Arcade::new();
+        arcade
+            .add_stock(GashaponItem::new(figure.clone()).with_quantity(4))
+            .unwrap();
+        arcade.add_machine(
+            "South",
+            machine(vec![
+                GashaponItem::new(figure).with_quantity(3),
+                GashaponItem::new(PrizeItem::new("Keychain")).with_quantity(3),
+            ]),
+        );
+        let south = arcade.machine_mut("South").unwrap();
+        south.with_journal(0);
+        let opened = south.draw_at(0).unwrap();
+        let reserved = south
+            .prizes()
+            .get_randomized_items()
+            .iter()
+            .position(|prize| prize.is_some_and(|prize| prize.get_id() == id))
+            .unwrap();
+        south.reserve(reserved).unwrap();
+        south.draw();
+        south.pause().unwrap();
+        let board = south.prizes().randomized_items()[..6].to_vec();
+        let state = south.prizes().state;
+        let total = arcade.total_stock().unwrap()[&id];
+
+        arcade.allocate("South", id.clone(), 4).unwrap();
+        let south = arcade.machine("South").unwrap();
+        assert_eq!(&south.prizes().randomized_items()[..6], board);
+        assert_eq!(south.prizes().state, state);
+        assert_eq!(south.board()[0].prize().unwrap().name, opened.name);
+        assert!(matches!(south.board()[reserved], BoardSlot::Reserved));
+        assert_eq!(south.check_invariants(), Ok(()));
+
+        // The reserved figure stays on the board
+        let held = arcade.machine_stock("South").unwrap()[&id];
+        assert_eq!(
+            arcade.withdraw("South", id.clone(), held).unwrap_err(),
+            StockError::NotEnoughStock {
+                id: id.clone(),
+                requested: held,
+                available: held - 1,
+            }
+        );
+        arcade.withdraw("South", id.clone(), held - 1).unwrap();
+        let south = arcade.machine("South").unwrap();
+        assert_eq!(south.prizes().state, state);
+        assert!(matches!(south.board()[reserved], BoardSlot::Reserved));
+        assert!(
+            south
+                .board()
+                .iter()
+                .any(|slot| matches!(slot, BoardSlot::Withdrawn))
+        );
+        assert_eq!(south.check_invariants(), Ok(()));
+        let recovered = south.journal().unwrap().recover().unwrap();
+        assert_eq!(
+            recovered.prizes().randomized_items(),
+            south.prizes().randomized_items()
+        );
+        assert_eq!(recovered.prizes().idx_box(), south.prizes().idx_box());
+        assert_eq!(arcade.total_stock().unwrap()[&id], total);
+    }
+
+    #[test]
+    fn test_shared_stock_cannot_be_refilled() {
+        let mut gashapon = machine(vec![
+            GashaponItem::new(PrizeItem::new("Figure")).with_quantity(3),
+        ]);
+        gashapon
+            .restore_items()
+            .unwrap()
+            .with_reset_policy(ResetPolicy::RemainingBelow(3))
+            .unwrap();
+        let mut arcade = Arcade::new();
+        arcade.add_machine("North", gashapon);
+
+        let north = arcade.machine_mut("North").unwrap();
+        north.draw();
+        assert!(north.resets().is_empty());
+        assert_eq!(north.remaining(), 2);
+        assert_eq!(
+            north.restore_items().unwrap_err(),
+            BuildError::Machine(MachineError::SharedStock)
+        );
+        assert_eq!(north.clear().unwrap_err(), MachineError::SharedStock);
+
+        let mut north = arcade.remove_machine("North").unwrap();
+        north.restore_items().unwrap();
+        assert_eq!(north.remaining(), 3);
+    }
+}
@@ -0,0 +1,149 @@
+use crate::{DrawMode, Gashapon, MachineError, MachineEvent, PrizeItem};
+
+/// What players can see of one slot on a machine's board.
+#[derive(Debug, Clone)]
+pub enum BoardSlot {
+    /// Not opened yet; the prize inside is not revealed.
+    Hidden,
+    /// Held for someone and left out of random draws.
+    Reserved,
+    Opened(PrizeItem),
+    /// Emptied unopened, when its capsule went back to an
+    /// [`Arcade`](crate::Arcade)'s warehouse or another machine.
+    Withdrawn,
+}
+
+impl BoardSlot {
+    pub fn is_hidden(&self) -> bool {
+        matches!(self, BoardSlot::Hidden)
+    }
+
+    pub fn prize(&self) -> Option<&PrizeItem> {
+        match self {
+            BoardSlot::Opened(prize) => Some(prize),
+            _ => None,
+        }
+    }
+}
+
+impl Gashapon {
+    /// The board as players see it, slot by slot. Unlike
+    /// [`Prizes::get_randomized_items`](crate::Prizes::get_randomized_items),
+    /// only opened slots reveal their prize.
+    pub fn board(&self) -> Vec<BoardSlot> {
+        let prizes = &self.prizes;
+        (0..prizes.randomized_items.len())
+            .map(|slot| match prizes.randomized_items[slot] {
+                Some(_) if prizes.reserved.contains(&slot) => BoardSlot::Reserved,
+                Some(_) => BoardSlot::Hidden,
+                None => prizes
+                    .get_item_by_index(prizes.opened[slot])
+                    .cloned()
+                    .map_or(BoardSlot::Withdrawn, BoardSlot::Opened),
+            })
+            .collect()
+    }
+
+    /// Hold an unopened slot so random draws skip it. It can still be
+    /// opened with [`Gashapon::draw_at`]. Rebuilding clears reservations.
+    pub fn reserve(&mut self, slot: usize) -> Result<&mut Self, MachineError> {
+        self.check_drawable()?;
+        self.check_slot(slot)?;
+        if !self.prizes.reserved.insert(slot) {
+            return Err(MachineError::SlotReserved(slot));
+        }
+        self.record(MachineEvent::SlotReserved(slot));
+        Ok(self)
+    }
+
+    /// Returns `false` if the slot was not reserved.
+    pub fn release(&mut self, slot: usize) -> bool {
+        let released = self.prizes.reserved.remove(&slot);
+        if released {
+            self.record(MachineEvent::SlotReleased(slot));
+        }
+        released
+    }
+
+    /// Check that `slot` is an unopened slot on the board.
+    pub(crate) fn check_slot(&self, slot: usize) -> Result<(), MachineError> {
+        if self.mode != DrawMode::Box {
+            return Err(MachineError::NoBoard(self.mode));
+        }
+        match self.prizes.randomized_items.get(slot) {
+            Some(Some(_)) => Ok(()),
+            Some(None) => Err(MachineError::SlotOpened(slot)),
+            None => Err(MachineError::SlotOutOfRange {
+                slot,
+                slots: self.prizes.randomized_items.len(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GashaponItem;
+
+    fn gashapon() -> Gashapon {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("Item1")).with_quantity(2),
+                GashaponItem::new(PrizeItem::new("Item2")).with_quantity(3),
+            ])
+            .unwrap()
+            .with_seed(12345)
+            .unwrap()
+            .build()
+            .unwrap();
+        gashapon
+    }
+
+    #[test]
+    fn test_board_hides_unopened_slots() {
+        let mut gashapon = gashapon();
+        assert!(gashapon.board().iter().all(BoardSlot::is_hidden));
+
+        let prize = gashapon.draw_at(2).unwrap();
+        let drawn = gashapon.draw();
+        let slot = gashapon.history()[1].slot.unwrap();
+
+        let board = gashapon.board();
+        assert_eq!(board.len(), 5);
+        assert_eq!(board[2].prize().unwrap().name, prize.name);
+        assert_eq!(board[slot].prize().unwrap().name, drawn.name);
+        assert_eq!(board.iter().filter(|slot| slot.is_hidden()).count(), 3);
+
+        gashapon.restore_items().unwrap();
+        assert!(gashapon.board().iter().all(BoardSlot::is_hidden));
+    }
+
+    #[test]
+    fn test_reserved_slots_are_skipped() {
+        let mut gashapon = gashapon();
+        gashapon.reserve(0).unwrap().reserve(4).unwrap();
+        assert_eq!(
+            gashapon.reserve(4).unwrap_err(),
+            MachineError::SlotReserved(4)
+        );
+        assert!(matches!(gashapon.board()[0], BoardSlot::Reserved));
+        assert_eq!(gashapon.drain().len(), 3);
+
+        assert!(gashapon.history().iter().all(|record| {
+            let slot = record.slot.unwrap();
+            slot != 0 && slot != 4
+        }));
+        assert_eq!(gashapon.check_invariants(), Ok(()));
+        assert_eq!(gashapon.try_draw().unwrap_err(), MachineError::AllReserved);
+
+        assert!(gashapon.release(0));
+        assert!(!gashapon.release(0));
+        assert_eq!(gashapon.history().len(), 3);
+        gashapon.draw();
+        gashapon.draw_at(4).unwrap();
+        assert!(gashapon.is_empty());
+        assert!(gashapon.board().iter().all(|slot| slot.prize().is_some()));
+    }
+}
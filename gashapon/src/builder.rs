@@ -0,0 +1,563 @@
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fmt;
+
+use crate::{
+    DrawMode, ExchangeRates, Gashapon, GashaponItem, Guarantee, MachineError, Ratio, ResetPolicy,
+};
+
+/// Largest box [`GashaponBuilder`] accepts unless told otherwise. Every
+/// capsule is expanded into its own entry when the machine is built.
+pub const DEFAULT_MAX_CAPSULES: u64 = 1_000_000;
+
+/// A problem found while validating a machine configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issue {
+    /// There are no capsules to draw.
+    EmptyPool,
+    /// The item at this position has a blank name.
+    EmptyName(usize),
+    DuplicateName(String),
+    DuplicateId(String),
+    /// The box would expand into more capsules than allowed.
+    TooManyCapsules {
+        total: u64,
+        limit: u64,
+    },
+    /// The total number of capsules does not fit in a `u64`.
+    QuantityOverflow,
+    ZeroQuantity(String),
+    /// Every item of the tier has zero stock.
+    TierOutOfStock(String),
+    /// The item's odds are below the configured disclosure threshold.
+    RateBelowThreshold {
+        name: String,
+        rate: Ratio,
+        threshold: Ratio,
+    },
+    /// No item in stock belongs to the guaranteed tiers.
+    GuaranteeUncovered,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::EmptyPool => write!(f, "the prize pool is empty"),
+            Issue::EmptyName(idx) => write!(f, "item #{} has an empty name", idx + 1),
+            Issue::DuplicateName(name) => write!(f, "duplicate item name \"{name}\""),
+            Issue::DuplicateId(id) => write!(f, "duplicate prize id {id}"),
+            Issue::TooManyCapsules { total, limit } => {
+                write!(f, "{total} capsules exceed the limit of {limit}")
+            }
+            Issue::QuantityOverflow => write!(f, "total quantity overflows"),
+            Issue::ZeroQuantity(name) => write!(f, "item \"{name}\" has 0 stock"),
+            Issue::TierOutOfStock(tier) => write!(f, "tier {tier} has 0 stock"),
+            Issue::RateBelowThreshold {
+                name,
+                rate,
+                threshold,
+            } => write!(
+                f,
+                "rate of \"{name}\" ({rate}) is below the disclosure threshold ({threshold})"
+            ),
+            Issue::GuaranteeUncovered => {
+                write!(f, "no item in stock can honour the multi-pull guarantee")
+            }
+        }
+    }
+}
+
+/// Outcome of validating a [`GashaponBuilder`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Validation {
+    /// Problems that prevent the machine from being built.
+    pub errors: Vec<Issue>,
+    /// Suspicious settings that still make a working machine.
+    pub warnings: Vec<Issue>,
+}
+
+impl Validation {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+impl fmt::Display for Validation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |issues: &[Issue]| {
+            issues
+                .iter()
+                .map(|issue| issue.to_string())
+                .collect::<Vec<_>>()
+                .join("; ")
+        };
+        match (self.errors.is_empty(), self.warnings.is_empty()) {
+            (false, _) => write!(f, "invalid machine: {}", join(&self.errors)),
+            (true, false) => write!(f, "valid machine with warnings: {}", join(&self.warnings)),
+            (true, true) => write!(f, "valid machine"),
+        }
+    }
+}
+
+impl Error for Validation {}
+
+/// Why [`Gashapon::try_build`] did not build the machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildError {
+    Machine(MachineError),
+    Invalid(Validation),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::Machine(err) => write!(f, "{err}"),
+            BuildError::Invalid(validation) => write!(f, "{validation}"),
+        }
+    }
+}
+
+impl Error for BuildError {}
+
+impl From<MachineError> for BuildError {
+    fn from(err: MachineError) -> Self {
+        BuildError::Machine(err)
+    }
+}
+
+impl From<Validation> for BuildError {
+    fn from(validation: Validation) -> Self {
+        BuildError::Invalid(validation)
+    }
+}
+
+/// Builds a [`Gashapon`] only if its configuration is valid.
+#[derive(Debug, Clone)]
+pub struct GashaponBuilder {
+    items: Vec<GashaponItem>,
+    seed: Option<usize>,
+    unit_price: u64,
+    mode: DrawMode,
+    duplicate_protection: bool,
+    guarantee: Option<Guarantee>,
+    exchange: ExchangeRates,
+    reset_policies: Vec<ResetPolicy>,
+    max_capsules: u64,
+    disclosure_threshold: Option<Ratio>,
+}
+
+impl Default for GashaponBuilder {
+    fn default() -> Self {
+        Self {
+            items: Vec::new(),
+            seed: None,
+            unit_price: u64::default(),
+            mode: DrawMode::default(),
+            duplicate_protection: false,
+            guarantee: None,
+            exchange: ExchangeRates::default(),
+            reset_policies: Vec::new(),
+            max_capsules: DEFAULT_MAX_CAPSULES,
+            disclosure_threshold: None,
+        }
+    }
+}
+
+/// Start from a machine's current items and settings, for example to
+/// validate a change before making it.
+impl From<&Gashapon> for GashaponBuilder {
+    fn from(gashapon: &Gashapon) -> Self {
+        let mut items = gashapon.items.values().cloned().collect::<Vec<_>>();
+        items.sort_by(|a, b| a.prize.name.cmp(&b.prize.name));
+        Self {
+            items,
+            seed: gashapon.prizes.seed,
+            unit_price: gashapon.unit_price,
+            mode: gashapon.mode,
+            duplicate_protection: gashapon.duplicate_protection,
+            guarantee: gashapon.guarantee.clone(),
+            exchange: gashapon.exchange.clone(),
+            reset_policies: gashapon.reset_policies.clone(),
+            ..Self::default()
+        }
+    }
+}
+
+impl GashaponBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_item(mut self, item: GashaponItem) -> Self {
+        self.items.push(item);
+        self
+    }
+
+    pub fn add_items(mut self, items: Vec<GashaponItem>) -> Self {
+        self.items.extend(items);
+        self
+    }
+
+    pub fn with_seed(mut self, seed: usize) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn with_unit_price(mut self, unit_price: u64) -> Self {
+        self.unit_price = unit_price;
+        self
+    }
+
+    pub fn with_mode(mut self, mode: DrawMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// See [`Gashapon::with_duplicate_protection`].
+    pub fn with_duplicate_protection(mut self, enabled: bool) -> Self {
+        self.duplicate_protection = enabled;
+        self
+    }
+
+    pub fn with_guarantee(mut self, guarantee: Guarantee) -> Self {
+        self.guarantee = Some(guarantee);
+        self
+    }
+
+    pub fn with_exchange_rates(mut self, rates: ExchangeRates) -> Self {
+        self.exchange = rates;
+        self
+    }
+
+    /// See [`ResetPolicy`].
+    pub fn with_reset_policy(mut self, policy: ResetPolicy) -> Self {
+        self.reset_policies.push(policy);
+        self
+    }
+
+    pub fn with_max_capsules(mut self, max_capsules: u64) -> Self {
+        self.max_capsules = max_capsules;
+        self
+    }
+
+    /// Warn about items whose odds of being drawn are below `threshold`.
+    pub fn with_disclosure_threshold(mut self, threshold: Ratio) -> Self {
+        self.disclosure_threshold = Some(threshold);
+        self
+    }
+
+    pub fn validate(&self) -> Validation {
+        let mut validation = Validation::default();
+        let mut names = HashSet::new();
+        let mut ids = HashSet::new();
+        let mut tiers = BTreeMap::<&str, u64>::new();
+
+        for (idx, item) in self.items.iter().enumerate() {
+            let name = &item.prize.name;
+            if name.trim().is_empty() {
+                validation.errors.push(Issue::EmptyName(idx));
+            } else if !names.insert(name) {
+                validation.errors.push(Issue::DuplicateName(name.clone()));
+            } else if !ids.insert(item.get_prize_id()) {
+                validation
+                    .errors
+                    .push(Issue::DuplicateId(item.get_prize_id().to_string()));
+            }
+            if item.quantity == 0 {
+                validation.warnings.push(Issue::ZeroQuantity(name.clone()));
+            }
+            if let Some(tier) = &item.prize.tier {
+                *tiers.entry(tier).or_default() += item.quantity.min(1);
+            }
+        }
+
+        let total = self
+            .items
+            .iter()
+            .try_fold(0u64, |total, item| total.checked_add(item.quantity));
+        // Unlimited items are never expanded into capsules
+        let capsules = self
+            .items
+            .iter()
+            .filter(|item| !item.is_unlimited())
+            .fold(0u64, |total, item| total.saturating_add(item.quantity));
+        match total {
+            None => validation.errors.push(Issue::QuantityOverflow),
+            Some(0) => validation.errors.push(Issue::EmptyPool),
+            Some(_) if capsules > self.max_capsules => {
+                validation.errors.push(Issue::TooManyCapsules {
+                    total: capsules,
+                    limit: self.max_capsules,
+                })
+            }
+            Some(total) => {
+                if let Some(threshold) = self.disclosure_threshold {
+                    for item in self.items.iter().filter(|item| item.quantity > 0) {
+                        let rate = Ratio::new(item.quantity as u128, total as u128);
+                        if rate < threshold {
+                            validation.warnings.push(Issue::RateBelowThreshold {
+                                name: item.prize.name.clone(),
+                                rate,
+                                threshold,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(guarantee) = &self.guarantee
+            && !self
+                .items
+                .iter()
+                .any(|item| item.quantity > 0 && guarantee.covers(&item.prize))
+        {
+            validation.warnings.push(Issue::GuaranteeUncovered);
+        }
+
+        for (tier, in_stock) in tiers {
+            if in_stock == 0 {
+                validation
+                    .warnings
+                    .push(Issue::TierOutOfStock(tier.to_string()));
+            }
+        }
+        validation
+    }
+
+    /// Build the machine, or return every error found if it is invalid.
+    pub fn build(self) -> Result<Gashapon, Validation> {
+        let validation = self.validate();
+        if !validation.is_ok() {
+            return Err(validation);
+        }
+
+        Ok(self.assemble().expect("A new machine is editable"))
+    }
+
+    /// Build the machine without validating it.
+    pub(crate) fn assemble(self) -> Result<Gashapon, MachineError> {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(self.items)?
+            .with_unit_price(self.unit_price)?
+            .with_mode(self.mode)?
+            .with_duplicate_protection(self.duplicate_protection)?
+            .with_exchange_rates(self.exchange)?;
+        if let Some(seed) = self.seed {
+            gashapon.with_seed(seed)?;
+        }
+        if let Some(guarantee) = self.guarantee {
+            gashapon.with_guarantee(guarantee)?;
+        }
+        for policy in self.reset_policies {
+            gashapon.with_reset_policy(policy)?;
+        }
+        gashapon.build()?;
+        Ok(gashapon)
+    }
+}
+
+impl Gashapon {
+    pub fn builder() -> GashaponBuilder {
+        GashaponBuilder::new()
+    }
+
+    /// Check the machine's items and settings as [`GashaponBuilder`] would.
+    pub fn validate(&self) -> Validation {
+        GashaponBuilder::from(self).validate()
+    }
+
+    /// Check that the box can be rebuilt once `edit` is made to the items.
+    /// An empty pool is allowed, since a machine may be emptied before it is
+    /// filled again.
+    pub(crate) fn check_rebuild<F>(&self, edit: F) -> Result<(), Validation>
+    where
+        F: FnOnce(&mut Vec<GashaponItem>),
+    {
+        let mut builder = GashaponBuilder::from(self);
+        edit(&mut builder.items);
+        let validation = builder.validate();
+        match validation
+            .errors
+            .iter()
+            .any(|issue| *issue != Issue::EmptyPool)
+        {
+            true => Err(validation),
+            false => Ok(()),
+        }
+    }
+
+    /// Fill and shuffle the box of a draft machine, if its items and
+    /// settings are valid.
+    pub fn try_build(&mut self) -> Result<&mut Self, BuildError> {
+        self.ensure_editable()?;
+        let validation = self.validate();
+        if !validation.is_ok() {
+            return Err(BuildError::Invalid(validation));
+        }
+        Ok(self.build()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PrizeId, PrizeItem};
+
+    #[test]
+    fn test_builder_valid() {
+        let gashapon = Gashapon::builder()
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("Item1")).with_quantity(2),
+                GashaponItem::new(PrizeItem::new("Item2")).with_quantity(3),
+            ])
+            .with_seed(12345)
+            .with_unit_price(100)
+            .build()
+            .unwrap();
+
+        assert_eq!(gashapon.remaining(), 5);
+        assert_eq!(gashapon.unit_price(), 100);
+    }
+
+    #[test]
+    fn test_builder_errors() {
+        let err = Gashapon::builder().build().unwrap_err();
+        assert_eq!(err.errors, vec![Issue::EmptyPool]);
+
+        let err = Gashapon::builder()
+            .add_item(GashaponItem::new(PrizeItem::new("Item1")).with_quantity(2))
+            .add_item(GashaponItem::new(PrizeItem::new("Item1")).with_quantity(3))
+            .add_item(GashaponItem::new(PrizeItem::new(" ")).with_quantity(3))
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            err.errors,
+            vec![
+                Issue::DuplicateName("Item1".to_string()),
+                Issue::EmptyName(2)
+            ]
+        );
+
+        let err = Gashapon::builder()
+            .add_item(GashaponItem::new(PrizeItem::new("Item1")).with_quantity(u64::MAX))
+            .add_item(GashaponItem::new(PrizeItem::new("Item2")).with_quantity(1))
+            .build()
+            .unwrap_err();
+        assert_eq!(err.errors, vec![Issue::QuantityOverflow]);
+
+        let err = Gashapon::builder()
+            .add_item(GashaponItem::new(PrizeItem::new("Item1")).with_quantity(11))
+            .with_max_capsules(10)
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid machine: 11 capsules exceed the limit of 10"
+        );
+    }
+
+    #[test]
+    fn test_builder_warnings() {
+        let builder = Gashapon::builder()
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("Item1").with_tier("S")).with_quantity(0),
+                GashaponItem::new(PrizeItem::new("Item2").with_tier("A")).with_quantity(1),
+                GashaponItem::new(PrizeItem::new("Item3").with_tier("A")).with_quantity(999),
+            ])
+            .with_disclosure_threshold(Ratio::new(1, 100))
+            .with_guarantee(Guarantee::new(10, ["SS"]));
+        let validation = builder.validate();
+
+        assert!(validation.is_ok());
+        assert_eq!(
+            validation.warnings,
+            vec![
+                Issue::ZeroQuantity("Item1".to_string()),
+                Issue::RateBelowThreshold {
+                    name: "Item2".to_string(),
+                    rate: Ratio::new(1, 1000),
+                    threshold: Ratio::new(1, 100),
+                },
+                Issue::GuaranteeUncovered,
+                Issue::TierOutOfStock("S".to_string()),
+            ]
+        );
+        assert_eq!(validation.warnings[3].to_string(), "tier S has 0 stock");
+        assert!(
+            validation
+                .to_string()
+                .starts_with("valid machine with warnings: item \"Item1\" has 0 stock; ")
+        );
+        assert!(builder.build().is_ok());
+        assert_eq!(Validation::default().to_string(), "valid machine");
+    }
+
+    #[test]
+    fn test_try_build() {
+        let mut gashapon = Gashapon::default();
+        assert_eq!(
+            gashapon.try_build().unwrap_err().to_string(),
+            "invalid machine: the prize pool is empty"
+        );
+
+        gashapon
+            .add_item(GashaponItem::new(PrizeItem::new("Item1")).with_quantity(2))
+            .unwrap()
+            .with_seed(12345)
+            .unwrap()
+            .try_build()
+            .unwrap();
+        assert_eq!(gashapon.remaining(), 2);
+        assert_eq!(
+            GashaponBuilder::from(&gashapon)
+                .build()
+                .unwrap()
+                .remaining(),
+            2
+        );
+
+        gashapon.draw();
+        assert_eq!(
+            gashapon.try_build().unwrap_err(),
+            BuildError::Machine(MachineError::NotEditable(crate::MachineState::Live))
+        );
+    }
+
+    #[test]
+    fn test_edits_are_validated_before_rebuilding() {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("Huge")).with_quantity(1_000_000_000_000),
+                GashaponItem::new(PrizeItem::new("Item1")).with_quantity(2),
+            ])
+            .unwrap();
+        let too_many = |err: BuildError| match err {
+            BuildError::Invalid(validation) => validation
+                .errors
+                .iter()
+                .any(|issue| matches!(issue, Issue::TooManyCapsules { .. })),
+            _ => false,
+        };
+        assert!(too_many(
+            gashapon
+                .update_item_quantity(PrizeId::new("Item1"), 3)
+                .unwrap_err()
+        ));
+        assert!(too_many(gashapon.restore_items().unwrap_err()));
+        assert!(too_many(gashapon.reset_to_original().unwrap_err()));
+        assert!(too_many(gashapon.rebuild_with_new_seed(1).unwrap_err()));
+        assert_eq!(
+            gashapon.get_item(PrizeId::new("Item1")).unwrap().quantity,
+            2
+        );
+
+        // Removing the offending item leaves a machine that can be rebuilt
+        gashapon.remove_item(PrizeId::new("Huge")).unwrap();
+        assert_eq!(gashapon.remaining(), 2);
+        gashapon.remove_item(PrizeId::new("Item1")).unwrap();
+        assert!(gashapon.is_empty());
+    }
+}
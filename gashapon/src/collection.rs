@@ -0,0 +1,242 @@
+//! Coupon-collector analytics: how many draws it takes to complete a set.
+//!
+//! [`Gashapon::collect_set`] works out the exact distribution for a finite
+//! box. Weighted machines draw with replacement and are better served by
+//! [`simulate`](crate::simulate::simulate) with
+//! [`Strategy::CompleteSet`](crate::simulate::Strategy::CompleteSet).
+
+use std::collections::HashSet;
+
+use crate::{DrawMode, Gashapon, Player, PrizeId};
+
+/// Distribution of the number of draws needed to complete a set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetCompletion {
+    /// `cdf[n]` is the probability that the set is complete after `n` draws.
+    cdf: Vec<f64>,
+    unit_price: u64,
+}
+
+impl SetCompletion {
+    /// Probability that the set is complete within `draws` draws.
+    pub fn probability_within(&self, draws: u64) -> f64 {
+        let idx = (draws as usize).min(self.cdf.len() - 1);
+        self.cdf[idx]
+    }
+
+    pub fn expected_draws(&self) -> f64 {
+        self.cdf.iter().map(|p| 1.0 - p).sum()
+    }
+
+    pub fn expected_cost(&self) -> f64 {
+        self.expected_draws() * self.unit_price as f64
+    }
+
+    /// Smallest number of draws that completes the set with at least
+    /// `p` percent probability.
+    pub fn percentile_draws(&self, p: f64) -> u64 {
+        let p = p.clamp(0.0, 100.0) / 100.0;
+        // Tolerate rounding error in the last few ulps of the CDF
+        self.cdf
+            .iter()
+            .position(|cdf| *cdf + 1e-9 >= p)
+            .unwrap_or(self.cdf.len() - 1) as u64
+    }
+
+    pub fn percentile_cost(&self, p: f64) -> u64 {
+        self.percentile_draws(p) * self.unit_price
+    }
+}
+
+impl Gashapon {
+    /// Draws needed to collect one of every item still in the box.
+    pub fn collect_all(&self) -> Option<SetCompletion> {
+        let targets = self
+            .items
+            .iter()
+            .filter(|(_, item)| item.quantity > 0)
+            .map(|(id, _)| id.clone())
+            .collect::<Vec<_>>();
+        self.collect_set(&targets)
+    }
+
+    /// Exact distribution of the draws needed to collect one of each of
+    /// `targets` from the current box. A target listed more than once is
+    /// collected once.
+    ///
+    /// Returns `None` in [`DrawMode::Weighted`] or with unlimited items, or
+    /// if a target is unknown or sold out.
+    pub fn collect_set(&self, targets: &[PrizeId]) -> Option<SetCompletion> {
+        if self.mode == DrawMode::Weighted || self.has_unlimited_items() {
+            return None;
+        }
+
+        let mut quantities = Vec::new();
+        let mut seen = HashSet::new();
+        for id in targets.iter().filter(|id| seen.insert(*id)) {
+            match self.items.get(id) {
+                Some(item) if item.quantity > 0 => quantities.push(item.quantity as usize),
+                _ => return None,
+            }
+        }
+        let total = usize::try_from(self.total_quantity()).ok()?;
+        Some(SetCompletion {
+            cdf: completion_cdf(total, &quantities),
+            unit_price: self.unit_price,
+        })
+    }
+
+    /// Percentage of the machine's distinct items the player owns.
+    pub fn completion_percentage(&self, player: &Player) -> f64 {
+        if self.items.is_empty() {
+            return 0.0;
+        }
+        let owned = self
+            .items
+            .keys()
+            .filter(|id| player.owns((*id).clone()))
+            .count();
+        owned as f64 / self.items.len() as f64 * 100.0
+    }
+}
+
+/// `cdf[n]` for `n` in `0..=total`: the probability that the first `n`
+/// capsules of a shuffled box of `total` contain at least one of every
+/// target, where target `i` has `quantities[i]` copies.
+///
+/// Counts the favourable `n`-subsets through the generating function
+/// `prod_i ((1 + x)^K_i - 1)`, working in log space to stay finite.
+fn completion_cdf(total: usize, quantities: &[usize]) -> Vec<f64> {
+    let ln_fact = (0..=total)
+        .scan(0.0, |acc, k| {
+            if k > 0 {
+                *acc += (k as f64).ln();
+            }
+            Some(*acc)
+        })
+        .collect::<Vec<f64>>();
+    let ln_choose = |n: usize, k: usize| ln_fact[n] - ln_fact[k] - ln_fact[n - k];
+
+    // coef[m]: ln of the number of ways to pick m target capsules covering every target
+    let mut coef = vec![0.0];
+    for &quantity in quantities {
+        let mut next = vec![f64::NEG_INFINITY; coef.len() + quantity];
+        for (m, slot) in next.iter_mut().enumerate() {
+            let terms = (1..=quantity.min(m))
+                .filter(|j| m - j < coef.len())
+                .map(|j| coef[m - j] + ln_choose(quantity, j));
+            *slot = log_sum_exp(terms);
+        }
+        coef = next;
+    }
+
+    let rest = total - quantities.iter().sum::<usize>();
+    (0..=total)
+        .map(|n| {
+            let terms = (0..coef.len().min(n + 1))
+                .filter(|m| n - m <= rest)
+                .map(|m| coef[m] + ln_choose(rest, n - m) - ln_choose(total, n));
+            log_sum_exp(terms).exp().min(1.0)
+        })
+        .collect()
+}
+
+fn log_sum_exp(terms: impl Iterator<Item = f64>) -> f64 {
+    let terms = terms.collect::<Vec<_>>();
+    let max = terms.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    if max == f64::NEG_INFINITY {
+        return max;
+    }
+    max + terms.iter().map(|t| (t - max).exp()).sum::<f64>().ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulate::{SimulationConfig, Strategy, simulate};
+    use crate::{GashaponItem, PrizeItem};
+
+    fn gashapon() -> Gashapon {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("Item1")).with_quantity(1),
+                GashaponItem::new(PrizeItem::new("Item2")).with_quantity(2),
+                GashaponItem::new(PrizeItem::new("Item3")).with_quantity(3),
+            ])
+            .unwrap()
+            .with_unit_price(10)
+            .unwrap()
+            .with_seed(12345)
+            .unwrap()
+            .build()
+            .unwrap();
+        gashapon
+    }
+
+    #[test]
+    fn test_collect_single_item() {
+        // A single target matches the hypergeometric (N + 1) / (K + 1)
+        let gashapon = gashapon();
+        let completion = gashapon.collect_set(&[PrizeId::new("Item2")]).unwrap();
+        assert!((completion.expected_draws() - 7.0 / 3.0).abs() < 1e-9);
+        assert_eq!(completion.percentile_draws(100.0), 5);
+    }
+
+    #[test]
+    fn test_collect_all() {
+        let gashapon = gashapon();
+        let completion = gashapon.collect_all().unwrap();
+
+        assert_eq!(completion.probability_within(2), 0.0);
+        assert_eq!(completion.probability_within(6), 1.0);
+        // 1 * 2 * 3 ways to pick one of each as the first 3, out of C(6, 3)
+        assert!((completion.probability_within(3) - 6.0 / 20.0).abs() < 1e-9);
+        assert!(completion.expected_cost() > 30.0);
+        assert_eq!(completion.percentile_cost(100.0), 60);
+    }
+
+    #[test]
+    fn test_collect_set_matches_simulation() {
+        let gashapon = gashapon();
+        let targets = vec![PrizeId::new("Item1"), PrizeId::new("Item3")];
+        let completion = gashapon.collect_set(&targets).unwrap();
+
+        let config = SimulationConfig::new(Strategy::CompleteSet(targets))
+            .with_sessions(4000)
+            .with_seed(1);
+        let simulated = simulate(&gashapon, &config).draws_to_target();
+        assert!((simulated.mean().unwrap() - completion.expected_draws()).abs() < 0.2);
+    }
+
+    #[test]
+    fn test_collect_set_ignores_repeated_targets() {
+        let gashapon = gashapon();
+        let (item2, item3) = (PrizeId::new("Item2"), PrizeId::new("Item3"));
+        assert_eq!(
+            gashapon.collect_set(&[item3.clone(), item2.clone(), item3.clone()]),
+            gashapon.collect_set(&[item3, item2])
+        );
+    }
+
+    #[test]
+    fn test_collect_set_unavailable() {
+        let mut gashapon = gashapon();
+        assert!(gashapon.collect_set(&[PrizeId::new("Unknown")]).is_none());
+
+        gashapon.with_mode(DrawMode::Weighted).unwrap();
+        assert!(gashapon.collect_all().is_none());
+    }
+
+    #[test]
+    fn test_completion_percentage() {
+        let gashapon = gashapon();
+        let mut player = Player::new("Alice");
+        assert_eq!(gashapon.completion_percentage(&player), 0.0);
+
+        player.add_prize(&PrizeItem::new("Item1"));
+        player.add_prize(&PrizeItem::new("Item1"));
+        player.add_prize(&PrizeItem::new("Item3"));
+        assert!((gashapon.completion_percentage(&player) - 200.0 / 3.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,483 @@
+//! Declarative prize tables, loaded from and written to TOML or JSON.
+//!
+//! ```toml
+//! seed = 12345
+//! unit_price = 100
+//! mode = "box"
+//!
+//! [[items]]
+//! name = "S"
+//! quantity = 1
+//! tier = "S"
+//! value = 5000
+//! ```
+
+#[cfg(any(feature = "json", feature = "toml"))]
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    DrawMode, ExchangeRates, Gashapon, GashaponBuilder, GashaponItem, Guarantee, PrizeEffect,
+    PrizeItem, ResetPolicy, Validation,
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PrizeTable {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<usize>,
+    #[serde(default)]
+    pub unit_price: u64,
+    #[serde(default)]
+    pub mode: DrawMode,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub duplicate_protection: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub guarantee: Option<Guarantee>,
+    #[serde(default, skip_serializing_if = "ExchangeRates::is_empty")]
+    pub exchange: ExchangeRates,
+    /// For example `reset_policies = [{ remaining_below = 3 }]`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reset_policies: Vec<ResetPolicy>,
+    #[serde(default)]
+    pub items: Vec<PrizeTableItem>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PrizeTableItem {
+    pub name: String,
+    pub quantity: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tier: Option<String>,
+    #[serde(default)]
+    pub value: u64,
+    /// The quantity is a weight that never runs out.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unlimited: bool,
+    /// For example `effect = { free_draws = 2 }` or `effect = { draw_on = "Bonus" }`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effect: Option<PrizeEffect>,
+}
+
+/// A prize table that failed to parse or validate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    /// 1-based line of the offending input, when known.
+    pub line: Option<usize>,
+    /// 1-based column of the offending input, when known.
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+#[cfg(any(feature = "json", feature = "toml"))]
+impl ConfigError {
+    pub(crate) fn new<T>(message: T) -> Self
+    where
+        T: ToString,
+    {
+        Self {
+            line: None,
+            column: None,
+            message: message.to_string(),
+        }
+    }
+
+    fn at(mut self, line: Option<usize>, column: Option<usize>) -> Self {
+        self.line = line;
+        self.column = column;
+        self
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => {
+                write!(f, "line {line}, column {column}: {}", self.message)
+            }
+            (Some(line), None) => write!(f, "line {line}: {}", self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl PrizeTable {
+    /// Describe a machine by its items' original quantities.
+    pub fn from_gashapon(gashapon: &Gashapon) -> Self {
+        let mut items = gashapon
+            .items
+            .values()
+            .map(|item| PrizeTableItem {
+                name: item.prize.name.clone(),
+                quantity: item.original_quantity(),
+                tier: item.prize.tier.clone(),
+                value: item.prize.value,
+                unlimited: item.is_unlimited(),
+                effect: item.prize.effect.clone(),
+            })
+            .collect::<Vec<_>>();
+        items.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Self {
+            seed: gashapon.prizes.seed,
+            unit_price: gashapon.unit_price,
+            mode: gashapon.mode,
+            duplicate_protection: gashapon.duplicate_protection,
+            guarantee: gashapon.guarantee.clone(),
+            exchange: gashapon.exchange.clone(),
+            reset_policies: gashapon.reset_policies.clone(),
+            items,
+        }
+    }
+
+    /// A builder holding the table's items and settings.
+    pub fn builder(&self) -> GashaponBuilder {
+        let items = self
+            .items
+            .iter()
+            .map(|item| {
+                let mut prize = PrizeItem::new(&item.name).with_value(item.value);
+                if let Some(tier) = &item.tier {
+                    prize = prize.with_tier(tier);
+                }
+                if let Some(effect) = &item.effect {
+                    prize = prize.with_effect(effect.clone());
+                }
+                GashaponItem::new(prize)
+                    .with_quantity(item.quantity)
+                    .with_unlimited(item.unlimited)
+            })
+            .collect();
+        let mut builder = Gashapon::builder()
+            .add_items(items)
+            .with_unit_price(self.unit_price)
+            .with_mode(self.mode)
+            .with_duplicate_protection(self.duplicate_protection)
+            .with_exchange_rates(self.exchange.clone());
+        if let Some(seed) = self.seed {
+            builder = builder.with_seed(seed);
+        }
+        if let Some(guarantee) = &self.guarantee {
+            builder = builder.with_guarantee(guarantee.clone());
+        }
+        for policy in &self.reset_policies {
+            builder = builder.with_reset_policy(policy.clone());
+        }
+        builder
+    }
+
+    /// Build a ready-to-draw machine from the table, or return every error
+    /// found if it is invalid.
+    pub fn to_gashapon(&self) -> Result<Gashapon, Validation> {
+        self.builder().build()
+    }
+
+    /// Check what the format itself cannot express, pointing at the name of
+    /// the offending item. `names` holds the line and column of each item's
+    /// name, as reported by the parser.
+    #[cfg(any(feature = "json", feature = "toml"))]
+    pub(crate) fn validate(&self, names: &[(usize, usize)]) -> Result<(), ConfigError> {
+        let mut seen = HashSet::new();
+        for (idx, item) in self.items.iter().enumerate() {
+            let (line, column) = names.get(idx).copied().unzip();
+            if item.name.trim().is_empty() {
+                return Err(
+                    ConfigError::new(format!("item #{} has an empty name", idx + 1))
+                        .at(line, column),
+                );
+            }
+            if !seen.insert(item.name.as_str()) {
+                return Err(
+                    ConfigError::new(format!("duplicate item name \"{}\"", item.name))
+                        .at(line, column),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn from_toml_str(source: &str) -> Result<Self, ConfigError> {
+        let table = toml::from_str::<Self>(source).map_err(|err| {
+            let (line, column) = err
+                .span()
+                .map(|span| line_column(source, span.start))
+                .unzip();
+            ConfigError::new(err.message()).at(line, column)
+        })?;
+        let names = toml::from_str::<TomlNames>(source)
+            .map(|names| names.locate(source))
+            .unwrap_or_default();
+        table.validate(&names)?;
+        Ok(table)
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn to_toml_string(&self) -> Result<String, ConfigError> {
+        toml::to_string_pretty(self).map_err(ConfigError::new)
+    }
+
+    #[cfg(feature = "json")]
+    pub fn from_json_str(source: &str) -> Result<Self, ConfigError> {
+        let table = serde_json::from_str::<Self>(source).map_err(json_error)?;
+        let names = serde_json::from_str::<JsonNames>(source)
+            .map(|names| names.locate(source))
+            .unwrap_or_default();
+        table.validate(&names)?;
+        Ok(table)
+    }
+
+    #[cfg(feature = "json")]
+    pub fn to_json_string(&self) -> Result<String, ConfigError> {
+        serde_json::to_string_pretty(self).map_err(ConfigError::new)
+    }
+}
+
+/// A JSON parse error, located in its source.
+#[cfg(feature = "json")]
+pub(crate) fn json_error(err: serde_json::Error) -> ConfigError {
+    let message = err.to_string();
+    // serde_json appends " at line L column C" to its messages
+    let message = message
+        .split(" at line ")
+        .next()
+        .unwrap_or(&message)
+        .to_string();
+    ConfigError::new(message).at(Some(err.line()), Some(err.column()))
+}
+
+/// 1-based line and column of a byte offset.
+#[cfg(any(feature = "json", feature = "toml"))]
+fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |idx| idx + 1) + 1;
+    (line, column)
+}
+
+/// The item names of a TOML table, with their spans.
+#[cfg(feature = "toml")]
+#[derive(Deserialize)]
+struct TomlNames {
+    #[serde(default)]
+    items: Vec<TomlName>,
+}
+
+#[cfg(feature = "toml")]
+#[derive(Deserialize)]
+struct TomlName {
+    name: toml::Spanned<String>,
+}
+
+#[cfg(feature = "toml")]
+impl TomlNames {
+    fn locate(&self, source: &str) -> Vec<(usize, usize)> {
+        self.items
+            .iter()
+            .map(|item| line_column(source, item.name.span().start))
+            .collect()
+    }
+}
+
+/// The item names of a JSON table, borrowed from the source they were
+/// parsed from.
+#[cfg(feature = "json")]
+#[derive(Deserialize)]
+pub(crate) struct JsonNames<'a> {
+    #[serde(borrow, default)]
+    items: Vec<JsonName<'a>>,
+}
+
+#[cfg(feature = "json")]
+#[derive(Deserialize)]
+struct JsonName<'a> {
+    #[serde(borrow)]
+    name: &'a serde_json::value::RawValue,
+}
+
+#[cfg(feature = "json")]
+impl JsonNames<'_> {
+    /// Line and column of each name within `source`, which must be the
+    /// string the names were parsed from.
+    pub(crate) fn locate(&self, source: &str) -> Vec<(usize, usize)> {
+        self.items
+            .iter()
+            .map(|item| {
+                let offset = item.name.get().as_ptr() as usize - source.as_ptr() as usize;
+                line_column(source, offset)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "toml")]
+    const TOML: &str = r#"
+seed = 12345
+unit_price = 100
+mode = "box"
+duplicate_protection = true
+
+[[items]]
+name = "S"
+quantity = 1
+tier = "S"
+value = 5000
+
+[[items]]
+name = "A"
+quantity = 4
+"#;
+
+    #[test]
+    fn test_prize_table_round_trip() {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("S").with_tier("S")).with_quantity(1),
+                GashaponItem::new(PrizeItem::new("A")).with_quantity(4),
+            ])
+            .unwrap()
+            .with_unit_price(100)
+            .unwrap()
+            .with_seed(12345)
+            .unwrap()
+            .with_reset_policy(ResetPolicy::RemainingBelow(2))
+            .unwrap()
+            .build()
+            .unwrap();
+        gashapon.draw();
+
+        let table = PrizeTable::from_gashapon(&gashapon);
+        assert_eq!(table.items[0].name, "A");
+        assert_eq!(table.items[1].quantity, 1);
+
+        let rebuilt = table.to_gashapon().unwrap();
+        assert_eq!(rebuilt.remaining(), 5);
+        assert_eq!(rebuilt.reset_policies(), [ResetPolicy::RemainingBelow(2)]);
+        assert_eq!(PrizeTable::from_gashapon(&rebuilt), table);
+
+        let mut empty = table.clone();
+        empty.items.iter_mut().for_each(|item| item.quantity = 0);
+        assert_eq!(
+            empty.to_gashapon().unwrap_err().errors,
+            vec![crate::Issue::EmptyPool]
+        );
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn test_prize_table_toml() {
+        let table = PrizeTable::from_toml_str(TOML).unwrap();
+        assert_eq!(table.seed, Some(12345));
+        assert!(table.duplicate_protection);
+        assert_eq!(table.items.len(), 2);
+        assert_eq!(table.items[0].tier.as_deref(), Some("S"));
+
+        let written = table.to_toml_string().unwrap();
+        assert_eq!(PrizeTable::from_toml_str(&written).unwrap(), table);
+
+        let guaranteed = format!("{TOML}\n[guarantee]\npulls = 10\ntiers = [\"S\"]\n");
+        let table = PrizeTable::from_toml_str(&guaranteed).unwrap();
+        assert_eq!(table.guarantee, Some(Guarantee::new(10, ["S"])));
+        let gashapon = table.to_gashapon().unwrap();
+        assert_eq!(gashapon.guarantee().unwrap().pulls(), 10);
+        assert_eq!(
+            PrizeTable::from_gashapon(&gashapon).guarantee,
+            table.guarantee
+        );
+
+        let err =
+            PrizeTable::from_toml_str(&guaranteed.replace("pulls = 10", "pulls = 0")).unwrap_err();
+        assert_eq!(err.line, Some(17));
+        assert!(err.to_string().contains("at least one pull"));
+
+        let exchange =
+            format!("{TOML}\n[exchange]\npoints = {{ S = 100 }}\ncosts = {{ S = 300 }}\n");
+        let table = PrizeTable::from_toml_str(&exchange).unwrap();
+        let rates = ExchangeRates::new()
+            .with_points("S", 100)
+            .with_cost("S", 300);
+        assert_eq!(table.exchange, rates);
+        assert_eq!(table.to_gashapon().unwrap().exchange_rates(), &rates);
+
+        let resetting = TOML.replacen(
+            "[[items]]",
+            "reset_policies = [{ remaining_below = 3 }]\n\n[[items]]",
+            1,
+        );
+        let table = PrizeTable::from_toml_str(&resetting).unwrap();
+        assert_eq!(table.reset_policies, vec![ResetPolicy::RemainingBelow(3)]);
+        let written = table.to_toml_string().unwrap();
+        assert_eq!(PrizeTable::from_toml_str(&written).unwrap(), table);
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn test_prize_table_toml_errors() {
+        let err = PrizeTable::from_toml_str(&TOML.replace("quantity = 4", "quantity = \"four\""))
+            .unwrap_err();
+        assert_eq!(err.line, Some(15));
+
+        let err =
+            PrizeTable::from_toml_str(&TOML.replace("name = \"A\"", "name = \"S\"")).unwrap_err();
+        assert_eq!(err.line, Some(14));
+        assert!(
+            err.to_string()
+                .starts_with("line 14, column 8: duplicate item name")
+        );
+
+        let err =
+            PrizeTable::from_toml_str(&TOML.replace("name = \"A\"", "name = 'S'")).unwrap_err();
+        assert_eq!((err.line, err.column), (Some(14), Some(8)));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_prize_table_json() {
+        let source = r#"{
+  "unit_price": 50,
+  "mode": "weighted",
+  "items": [
+    { "name": "S", "quantity": 1 },
+    { "name": "S", "quantity": 2 }
+  ]
+}"#;
+        let err = PrizeTable::from_json_str(source).unwrap_err();
+        assert_eq!((err.line, err.column), (Some(6), Some(15)));
+
+        let err = PrizeTable::from_json_str(
+            r#"{"items": [{"name": "S", "quantity": 1}, {"name": "S", "quantity": 2}]}"#,
+        )
+        .unwrap_err();
+        assert_eq!((err.line, err.column), (Some(1), Some(51)));
+
+        let table = PrizeTable::from_json_str(&source.replace(
+            r#"{ "name": "S", "quantity": 2 }"#,
+            r#"{ "name": "A", "quantity": 2, "unlimited": true }"#,
+        ))
+        .unwrap();
+        assert_eq!(table.mode, DrawMode::Weighted);
+        let written = table.to_json_string().unwrap();
+        assert_eq!(PrizeTable::from_json_str(&written).unwrap(), table);
+        let gashapon = table.to_gashapon().unwrap();
+        assert!(
+            gashapon
+                .get_item(crate::PrizeId::new("A"))
+                .unwrap()
+                .is_unlimited()
+        );
+        assert_eq!(gashapon.prizes().quantity(), 1);
+
+        let err = PrizeTable::from_json_str("{\n  \"items\": [\n    { \"name\": 1 }\n  ]\n}")
+            .unwrap_err();
+        assert_eq!(err.line, Some(3));
+    }
+}
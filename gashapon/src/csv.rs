@@ -0,0 +1,482 @@
+//! CSV import of prize inventories and export of inventories, draw rates
+//! and draw history.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{Read, Write};
+
+use ::csv::{ReaderBuilder, StringRecord, Writer};
+
+use crate::{
+    Gashapon, GashaponBuilder, GashaponItem, GetPrizeItemId, MachineError, PrizeId, PrizeItem,
+};
+
+/// Which header names hold each field of an imported inventory.
+///
+/// Only the name and quantity columns are required.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvColumns {
+    pub name: String,
+    pub id: String,
+    pub quantity: String,
+    pub tier: String,
+    pub value: String,
+    /// `true` or `false`; an unlimited item's quantity is its weight.
+    pub unlimited: String,
+}
+
+impl Default for CsvColumns {
+    fn default() -> Self {
+        Self {
+            name: "name".to_string(),
+            id: "id".to_string(),
+            quantity: "quantity".to_string(),
+            tier: "tier".to_string(),
+            value: "value".to_string(),
+            unlimited: "unlimited".to_string(),
+        }
+    }
+}
+
+impl CsvColumns {
+    pub fn with_name<T>(mut self, header: T) -> Self
+    where
+        T: ToString,
+    {
+        self.name = header.to_string();
+        self
+    }
+
+    pub fn with_id<T>(mut self, header: T) -> Self
+    where
+        T: ToString,
+    {
+        self.id = header.to_string();
+        self
+    }
+
+    pub fn with_quantity<T>(mut self, header: T) -> Self
+    where
+        T: ToString,
+    {
+        self.quantity = header.to_string();
+        self
+    }
+
+    pub fn with_tier<T>(mut self, header: T) -> Self
+    where
+        T: ToString,
+    {
+        self.tier = header.to_string();
+        self
+    }
+
+    pub fn with_value<T>(mut self, header: T) -> Self
+    where
+        T: ToString,
+    {
+        self.value = header.to_string();
+        self
+    }
+
+    pub fn with_unlimited<T>(mut self, header: T) -> Self
+    where
+        T: ToString,
+    {
+        self.unlimited = header.to_string();
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvError {
+    /// 1-based line of the offending record, when known.
+    pub line: Option<u64>,
+    pub message: String,
+}
+
+impl CsvError {
+    fn new<T>(line: Option<u64>, message: T) -> Self
+    where
+        T: ToString,
+    {
+        Self {
+            line,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {line}: {}", self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl Error for CsvError {}
+
+impl From<::csv::Error> for CsvError {
+    fn from(err: ::csv::Error) -> Self {
+        let line = err.position().map(|pos| pos.line());
+        Self::new(line, err)
+    }
+}
+
+impl From<MachineError> for CsvError {
+    fn from(err: MachineError) -> Self {
+        Self::new(None, err)
+    }
+}
+
+impl From<std::io::Error> for CsvError {
+    fn from(err: std::io::Error) -> Self {
+        Self::new(None, err)
+    }
+}
+
+/// Read inventory rows into items. Numeric ids are taken as exported
+/// [`PrizeId`]s, any other id (such as a SKU) is hashed into one.
+///
+/// Every row needs a quantity, and no two rows may share an id or a name.
+pub fn import_items<R: Read>(
+    reader: R,
+    columns: &CsvColumns,
+) -> Result<Vec<GashaponItem>, CsvError> {
+    Ok(read_items(reader, columns)?
+        .into_iter()
+        .map(|(_, item)| item)
+        .collect())
+}
+
+/// Read inventory rows into items, each with the line it was read from.
+fn read_items<R: Read>(
+    reader: R,
+    columns: &CsvColumns,
+) -> Result<Vec<(Option<u64>, GashaponItem)>, CsvError> {
+    let mut reader = ReaderBuilder::new()
+        .trim(::csv::Trim::All)
+        .from_reader(reader);
+    let headers = reader.headers()?.clone();
+    let position = |header: &str| headers.iter().position(|h| h == header);
+    let require = |header: &str| {
+        position(header)
+            .ok_or_else(|| CsvError::new(Some(1), format!("missing column \"{header}\"")))
+    };
+    let name_col = require(&columns.name)?;
+    let quantity_col = require(&columns.quantity)?;
+    let id_col = position(&columns.id);
+    let tier_col = position(&columns.tier);
+    let value_col = position(&columns.value);
+    let unlimited_col = position(&columns.unlimited);
+
+    let mut items = Vec::<(Option<u64>, GashaponItem)>::new();
+    let mut record = StringRecord::new();
+    while reader.read_record(&mut record)? {
+        let line = record.position().map(|pos| pos.line());
+        let field = |col: Option<usize>| {
+            col.and_then(|col| record.get(col))
+                .filter(|f| !f.is_empty())
+        };
+
+        let name = field(Some(name_col)).ok_or_else(|| CsvError::new(line, "empty prize name"))?;
+        let quantity = field(Some(quantity_col))
+            .ok_or_else(|| CsvError::new(line, format!("missing quantity for \"{name}\"")))?
+            .parse::<u64>()
+            .map_err(|err| CsvError::new(line, format!("invalid quantity: {err}")))?;
+
+        let mut prize = PrizeItem::new(name);
+        if let Some(id) = field(id_col) {
+            let id = id
+                .parse::<u64>()
+                .map(PrizeId::from)
+                .unwrap_or_else(|_| PrizeId::new(id));
+            prize = prize.with_id(id);
+        }
+        if let Some(tier) = field(tier_col) {
+            prize = prize.with_tier(tier);
+        }
+        if let Some(value) = field(value_col) {
+            let value = value
+                .parse::<u64>()
+                .map_err(|err| CsvError::new(line, format!("invalid value: {err}")))?;
+            prize = prize.with_value(value);
+        }
+        let unlimited = match field(unlimited_col) {
+            Some(unlimited) => unlimited
+                .parse::<bool>()
+                .map_err(|err| CsvError::new(line, format!("invalid unlimited flag: {err}")))?,
+            None => false,
+        };
+        let item = GashaponItem::new(prize)
+            .with_quantity(quantity)
+            .with_unlimited(unlimited);
+        if let Some(clash) = clash(items.iter().map(|(_, item)| item), &item) {
+            return Err(CsvError::new(line, clash));
+        }
+        items.push((line, item));
+    }
+    Ok(items)
+}
+
+/// Why `item` cannot join `items`, if it shares an id or a name with one.
+fn clash<'a, I>(mut items: I, item: &GashaponItem) -> Option<String>
+where
+    I: Iterator<Item = &'a GashaponItem>,
+{
+    items.find_map(|other| {
+        if other.get_prize_id() == item.get_prize_id() {
+            Some(format!("duplicate prize id {}", item.get_prize_id()))
+        } else if other.prize.name == item.prize.name {
+            Some(format!("duplicate prize name \"{}\"", item.prize.name))
+        } else {
+            None
+        }
+    })
+}
+
+impl Gashapon {
+    /// Add every item of a CSV inventory. Call [`Gashapon::try_build`]
+    /// afterwards. Fails without adding anything if the machine is no longer
+    /// editable, if a row clashes with another row or an item already in the
+    /// machine, or if the machine would not
+    /// [validate](crate::GashaponBuilder::validate) with the new items.
+    pub fn import_csv<R: Read>(
+        &mut self,
+        reader: R,
+        columns: &CsvColumns,
+    ) -> Result<&mut Self, CsvError> {
+        self.ensure_editable()?;
+        let items = read_items(reader, columns)?;
+        for (line, item) in &items {
+            if let Some(clash) = clash(self.items.values(), item) {
+                return Err(CsvError::new(*line, clash));
+            }
+        }
+        let items = items.into_iter().map(|(_, item)| item).collect::<Vec<_>>();
+        let validation = GashaponBuilder::from(&*self)
+            .add_items(items.clone())
+            .validate();
+        if !validation.is_ok() {
+            return Err(CsvError::new(None, validation));
+        }
+        Ok(self.add_items(items)?)
+    }
+}
+
+fn sorted_items(gashapon: &Gashapon) -> Vec<&GashaponItem> {
+    let mut items = gashapon.items.values().collect::<Vec<_>>();
+    items.sort_by(|a, b| a.prize.name.cmp(&b.prize.name));
+    items
+}
+
+/// Write the current stock, readable again by [`import_items`].
+pub fn export_inventory<W: Write>(gashapon: &Gashapon, writer: W) -> Result<(), CsvError> {
+    let mut writer = Writer::from_writer(writer);
+    writer.write_record([
+        "id",
+        "name",
+        "tier",
+        "value",
+        "quantity",
+        "original_quantity",
+        "unlimited",
+    ])?;
+    for item in sorted_items(gashapon) {
+        writer.write_record([
+            item.get_prize_id().to_string(),
+            item.prize.name.clone(),
+            item.prize.tier.clone().unwrap_or_default(),
+            item.prize.value.to_string(),
+            item.quantity.to_string(),
+            item.original_quantity().to_string(),
+            item.is_unlimited().to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write the odds of each item on the next draw, as a fraction and a rate.
+pub fn export_draw_rates<W: Write>(gashapon: &Gashapon, writer: W) -> Result<(), CsvError> {
+    let mut rates = gashapon.calculate_exact_draw_rate();
+    rates.sort_by(|(a, _), (b, _)| a.prize.name.cmp(&b.prize.name));
+
+    let mut writer = Writer::from_writer(writer);
+    writer.write_record(["id", "name", "tier", "quantity", "odds", "rate"])?;
+    for (item, rate) in rates {
+        writer.write_record([
+            item.get_prize_id().to_string(),
+            item.prize.name.clone(),
+            item.prize.tier.clone().unwrap_or_default(),
+            item.quantity.to_string(),
+            rate.to_string(),
+            rate.to_f64().to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write every recorded draw, oldest first.
+pub fn export_history<W: Write>(gashapon: &Gashapon, writer: W) -> Result<(), CsvError> {
+    let mut writer = Writer::from_writer(writer);
+    writer.write_record(["sequence", "timestamp", "slot", "id", "name", "tier"])?;
+    for record in gashapon.history() {
+        writer.write_record([
+            record.sequence.to_string(),
+            record.timestamp.to_string(),
+            record.slot.map(|slot| slot.to_string()).unwrap_or_default(),
+            record.prize.get_id().to_string(),
+            record.prize.name.clone(),
+            record.prize.tier.clone().unwrap_or_default(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INVENTORY: &str = "\
+Figure,SKU,Stock,Rarity
+Red,RED-01,2,S
+Blue,,3,
+Green,GRN-01,5,A
+";
+
+    fn columns() -> CsvColumns {
+        CsvColumns::default()
+            .with_name("Figure")
+            .with_id("SKU")
+            .with_quantity("Stock")
+            .with_tier("Rarity")
+    }
+
+    #[test]
+    fn test_import_csv() {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .import_csv(INVENTORY.as_bytes(), &columns())
+            .unwrap()
+            .with_seed(12345)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_eq!(gashapon.remaining(), 10);
+        let red = &gashapon.items[&PrizeId::new("RED-01")];
+        assert_eq!(red.prize.name, "Red");
+        assert_eq!(red.prize.tier.as_deref(), Some("S"));
+        assert!(gashapon.items.contains_key(&PrizeId::new("Blue")));
+    }
+
+    #[test]
+    fn test_import_csv_errors() {
+        let err = import_items(INVENTORY.as_bytes(), &CsvColumns::default()).unwrap_err();
+        assert_eq!(err.to_string(), "line 1: missing column \"name\"");
+
+        let source = INVENTORY.replace("Blue,,3,", "Blue,,three,");
+        let err = import_items(source.as_bytes(), &columns()).unwrap_err();
+        assert_eq!(err.line, Some(3));
+
+        let source = INVENTORY.replace("Blue,,3,", "Blue,,,");
+        let err = import_items(source.as_bytes(), &columns()).unwrap_err();
+        assert_eq!(err.to_string(), "line 3: missing quantity for \"Blue\"");
+
+        let source = INVENTORY.replace("Green,GRN-01", "Green,RED-01");
+        let err = import_items(source.as_bytes(), &columns()).unwrap_err();
+        assert_eq!(err.line, Some(4));
+        assert!(err.message.starts_with("duplicate prize id"));
+
+        let source = INVENTORY.replace("Green,GRN-01", "Red,GRN-01");
+        let err = import_items(source.as_bytes(), &columns()).unwrap_err();
+        assert_eq!(err.to_string(), "line 4: duplicate prize name \"Red\"");
+
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_item(GashaponItem::new(PrizeItem::new("Blue")).with_quantity(1))
+            .unwrap();
+        let err = gashapon
+            .import_csv(INVENTORY.as_bytes(), &columns())
+            .unwrap_err();
+        assert_eq!(err.line, Some(3));
+        assert_eq!(gashapon.items.len(), 1);
+
+        let source = INVENTORY.replace("Blue,,3,", "Blue,,3000000,");
+        let err = Gashapon::default()
+            .import_csv(source.as_bytes(), &columns())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid machine: 3000007 capsules exceed the limit of 1000000"
+        );
+    }
+
+    #[test]
+    fn test_export_round_trip() {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .import_csv(INVENTORY.as_bytes(), &columns())
+            .unwrap()
+            .with_seed(12345)
+            .unwrap()
+            .build()
+            .unwrap();
+        gashapon.draw_with_times(2);
+
+        let mut inventory = Vec::new();
+        export_inventory(&gashapon, &mut inventory).unwrap();
+        let items = import_items(inventory.as_slice(), &CsvColumns::default()).unwrap();
+        assert_eq!(items.len(), 3);
+        assert_eq!(items.iter().map(|item| item.quantity).sum::<u64>(), 8);
+        for item in items {
+            assert!(gashapon.items.contains_key(&item.get_prize_id()));
+        }
+
+        let mut rates = Vec::new();
+        export_draw_rates(&gashapon, &mut rates).unwrap();
+        let rates = String::from_utf8(rates).unwrap();
+        assert_eq!(rates.lines().count(), 4);
+        assert!(rates.starts_with("id,name,tier,quantity,odds,rate\n"));
+
+        let mut history = Vec::new();
+        export_history(&gashapon, &mut history).unwrap();
+        let history = String::from_utf8(history).unwrap();
+        assert_eq!(history.lines().count(), 3);
+        assert!(history.lines().nth(2).unwrap().starts_with("2,"));
+    }
+
+    #[test]
+    fn test_export_round_trip_unlimited() {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("Figure")).with_quantity(3),
+                GashaponItem::new(PrizeItem::new("Sticker"))
+                    .with_quantity(5)
+                    .with_unlimited(true),
+            ])
+            .unwrap();
+
+        let mut inventory = Vec::new();
+        export_inventory(&gashapon, &mut inventory).unwrap();
+        let items = import_items(inventory.as_slice(), &CsvColumns::default()).unwrap();
+        let unlimited = items
+            .iter()
+            .map(|item| (item.prize.name.as_str(), item.is_unlimited()))
+            .collect::<Vec<_>>();
+        assert_eq!(unlimited, vec![("Figure", false), ("Sticker", true)]);
+
+        let source = String::from_utf8(inventory)
+            .unwrap()
+            .replace(",true", ",always");
+        let err = import_items(source.as_bytes(), &CsvColumns::default()).unwrap_err();
+        assert_eq!(err.line, Some(3));
+        assert!(err.message.starts_with("invalid unlimited flag"));
+    }
+}
@@ -0,0 +1,263 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+
+use crate::{DrawCause, Gashapon, GetPrizeItemId, MachineError, PrizeItem};
+
+/// Most draws a single draw may chain into before resolution gives up.
+pub const MAX_CHAINED_DRAWS: usize = 10_000;
+
+/// Something a prize does when it is drawn.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum PrizeEffect {
+    /// Draw this many more times from the same machine, for free.
+    FreeDraws(u64),
+    /// Draw once from the named machine of an [`Arcade`](crate::Arcade).
+    DrawOn(String),
+    Points(u64),
+}
+
+/// One draw made while resolving a chain of prize effects.
+#[derive(Debug, Clone)]
+pub struct ResolvedDraw {
+    /// Machine the prize came from, `None` for the machine drawn from.
+    pub machine: Option<String>,
+    pub prize: PrizeItem,
+    pub cause: DrawCause,
+}
+
+/// Everything a draw led to once every prize effect was resolved.
+#[derive(Debug, Clone, Default)]
+pub struct DrawOutcome {
+    /// The paid draw first, then chained draws in the order they were made.
+    pub draws: Vec<ResolvedDraw>,
+    pub points: u64,
+    /// Granted draws that could not be made, such as on an empty machine.
+    pub forfeited: u64,
+    /// Machines granted draws on that are out of reach, in grant order.
+    pub unresolved: Vec<String>,
+}
+
+impl DrawOutcome {
+    pub fn prizes(&self) -> impl Iterator<Item = &PrizeItem> {
+        self.draws.iter().map(|draw| &draw.prize)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EffectError {
+    /// The paid draw was refused.
+    Machine(MachineError),
+    UnknownMachine(String),
+    /// Machines that grant draws on each other, in order. The first machine
+    /// is repeated at the end.
+    Loop(Vec<String>),
+    /// The chain went past [`MAX_CHAINED_DRAWS`]. Draws made so far stay
+    /// drawn.
+    ChainTooLong,
+}
+
+impl fmt::Display for EffectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EffectError::Machine(err) => write!(f, "{err}"),
+            EffectError::UnknownMachine(name) => write!(f, "no machine named \"{name}\""),
+            EffectError::Loop(path) => {
+                write!(f, "machines grant draws in a loop: {}", path.join(" -> "))
+            }
+            EffectError::ChainTooLong => {
+                write!(
+                    f,
+                    "prize effects chained into more than {MAX_CHAINED_DRAWS} draws"
+                )
+            }
+        }
+    }
+}
+
+impl Error for EffectError {}
+
+impl From<MachineError> for EffectError {
+    fn from(err: MachineError) -> Self {
+        EffectError::Machine(err)
+    }
+}
+
+/// Make a paid draw on `start`, then every draw its prizes grant.
+///
+/// `draw` makes one draw on a machine, or returns `None` if it cannot reach
+/// the machine.
+pub(crate) fn resolve<F>(start: Option<String>, mut draw: F) -> Result<DrawOutcome, EffectError>
+where
+    F: FnMut(Option<&str>, DrawCause) -> Option<Result<PrizeItem, MachineError>>,
+{
+    let mut outcome = DrawOutcome::default();
+    let mut queue = VecDeque::from([(start, DrawCause::Paid)]);
+    while let Some((machine, cause)) = queue.pop_front() {
+        let prize = match draw(machine.as_deref(), cause.clone()) {
+            Some(Ok(prize)) => prize,
+            Some(Err(err)) if cause == DrawCause::Paid => return Err(err.into()),
+            Some(Err(_)) => {
+                outcome.forfeited += 1;
+                continue;
+            }
+            None => {
+                outcome.unresolved.extend(machine);
+                continue;
+            }
+        };
+
+        let granted = match &prize.effect {
+            Some(PrizeEffect::FreeDraws(times)) => *times,
+            Some(PrizeEffect::DrawOn(_)) => 1,
+            _ => 0,
+        };
+        let pending = (outcome.draws.len() + 1 + queue.len()) as u64;
+        if pending.saturating_add(granted) > MAX_CHAINED_DRAWS as u64 {
+            return Err(EffectError::ChainTooLong);
+        }
+
+        match &prize.effect {
+            Some(PrizeEffect::FreeDraws(times)) => {
+                for _ in 0..*times {
+                    queue.push_back((machine.clone(), DrawCause::FreeDraw(prize.get_id())));
+                }
+            }
+            Some(PrizeEffect::DrawOn(target)) => queue.push_back((
+                Some(target.clone()),
+                DrawCause::Effect {
+                    machine: machine.clone().unwrap_or_default(),
+                    prize: prize.get_id(),
+                },
+            )),
+            Some(PrizeEffect::Points(points)) => {
+                outcome.points = outcome.points.saturating_add(*points)
+            }
+            None => {}
+        }
+        outcome.draws.push(ResolvedDraw {
+            machine,
+            prize,
+            cause,
+        });
+    }
+    Ok(outcome)
+}
+
+impl Gashapon {
+    /// Draw one prize and resolve its effects on this machine: free draws
+    /// are made straight away and points are added up. Draws granted on
+    /// other machines are left in [`DrawOutcome::unresolved`]; draw through
+    /// an [`Arcade`](crate::Arcade) to resolve them too.
+    pub fn draw_resolved(&mut self) -> Result<DrawOutcome, EffectError> {
+        resolve(None, |machine, cause| match machine {
+            None => Some(self.draw_caused(cause)),
+            Some(_) => None,
+        })
+    }
+
+    /// Draw one prize, recording why in the history.
+    pub(crate) fn draw_caused(&mut self, cause: DrawCause) -> Result<PrizeItem, MachineError> {
+        self.next_cause = cause;
+        let prize = self.try_draw();
+        self.next_cause = DrawCause::Paid;
+        prize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DrawMode, GashaponItem};
+
+    #[test]
+    fn test_draw_resolved_free_draws_and_points() {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("Ticket").with_effect(PrizeEffect::FreeDraws(2)))
+                    .with_quantity(1),
+                GashaponItem::new(PrizeItem::new("Coin").with_effect(PrizeEffect::Points(10)))
+                    .with_quantity(2),
+                GashaponItem::new(
+                    PrizeItem::new("Token").with_effect(PrizeEffect::DrawOn("Other".to_string())),
+                )
+                .with_quantity(1),
+            ])
+            .unwrap()
+            .with_seed(12345)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let mut outcomes = Vec::new();
+        while !gashapon.is_empty() {
+            outcomes.push(gashapon.draw_resolved().unwrap());
+        }
+        let draws = outcomes
+            .iter()
+            .map(|outcome| outcome.draws.len())
+            .sum::<usize>();
+        assert_eq!(draws, 4);
+        assert_eq!(
+            outcomes.iter().map(|outcome| outcome.points).sum::<u64>(),
+            20
+        );
+        let unresolved = outcomes
+            .iter()
+            .flat_map(|outcome| outcome.unresolved.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(unresolved, vec!["Other".to_string()]);
+
+        let ticket = gashapon
+            .history()
+            .iter()
+            .position(|record| record.prize.name == "Ticket")
+            .unwrap();
+        let free = gashapon
+            .history()
+            .iter()
+            .filter(|record| matches!(record.cause, DrawCause::FreeDraw(_)))
+            .count();
+        let forfeited = outcomes
+            .iter()
+            .map(|outcome| outcome.forfeited)
+            .sum::<u64>();
+        assert_eq!(free as u64 + forfeited, 2);
+        assert!(
+            gashapon.history()[..ticket]
+                .iter()
+                .all(|record| record.cause == DrawCause::Paid)
+        );
+        assert_eq!(
+            gashapon.draw_resolved().unwrap_err(),
+            EffectError::Machine(MachineError::Empty)
+        );
+    }
+
+    #[test]
+    fn test_draw_resolved_chain_limit() {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_item(
+                GashaponItem::new(PrizeItem::new("Ticket").with_effect(PrizeEffect::FreeDraws(1)))
+                    .with_quantity(MAX_CHAINED_DRAWS as u64 * 2),
+            )
+            .unwrap()
+            .with_mode(DrawMode::Weighted)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            gashapon.draw_resolved().unwrap_err(),
+            EffectError::ChainTooLong
+        );
+        assert_eq!(gashapon.history().len(), MAX_CHAINED_DRAWS);
+    }
+}
@@ -0,0 +1,192 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::{MachineState, PrizeId, PrizeItem, ResetPolicy};
+
+/// Something that happened inside a [`Gashapon`](crate::Gashapon).
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// The prize pool was rebuilt and reshuffled.
+    Built,
+    Drawn(PrizeItem),
+    /// The last capsule of an item was drawn.
+    ItemSoldOut(PrizeId),
+    /// The last capsule in the box was drawn.
+    PoolEmpty,
+    /// Every item was restored to its original quantity.
+    Restored,
+    /// A reset policy refilled the box and reshuffled it with a new seed.
+    BoxReset {
+        policy: ResetPolicy,
+        seed: usize,
+    },
+    QuantityChanged {
+        id: PrizeId,
+        from: u64,
+        to: u64,
+    },
+    StateChanged {
+        from: MachineState,
+        to: MachineState,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(usize);
+
+type Callback = Arc<dyn Fn(&Event) + Send + Sync>;
+
+/// Callbacks subscribed to a machine's events.
+///
+/// Cloning a machine shares its subscribers with the copy, but not the
+/// events held back for them.
+#[derive(Default)]
+pub(crate) struct Observers {
+    next_id: usize,
+    callbacks: Vec<(SubscriptionId, Callback)>,
+    /// Events held back until [`Observers::take_deferred`], if deferring.
+    deferred: Option<Vec<Event>>,
+}
+
+impl Clone for Observers {
+    fn clone(&self) -> Self {
+        Self {
+            next_id: self.next_id,
+            callbacks: self.callbacks.clone(),
+            deferred: None,
+        }
+    }
+}
+
+impl Observers {
+    pub(crate) fn subscribe(&mut self, callback: Callback) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id);
+        self.next_id += 1;
+        self.callbacks.push((id, callback));
+        id
+    }
+
+    pub(crate) fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let len = self.callbacks.len();
+        self.callbacks.retain(|(sub, _)| *sub != id);
+        self.callbacks.len() != len
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.callbacks.clear();
+    }
+
+    pub(crate) fn emit(&mut self, event: Event) {
+        match &mut self.deferred {
+            Some(deferred) => deferred.push(event),
+            None => self.notify(&event),
+        }
+    }
+
+    pub(crate) fn notify(&self, event: &Event) {
+        for (_, callback) in &self.callbacks {
+            callback(event);
+        }
+    }
+
+    /// Hold events back instead of calling subscribers.
+    pub(crate) fn defer(&mut self) {
+        self.deferred.get_or_insert_with(Vec::new);
+    }
+
+    /// Stop deferring and hand over the events held back.
+    pub(crate) fn take_deferred(&mut self) -> Vec<Event> {
+        self.deferred.take().unwrap_or_default()
+    }
+}
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Observers")
+            .field("subscribers", &self.callbacks.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{Gashapon, GashaponItem};
+
+    fn recorded(gashapon: &mut Gashapon) -> Arc<Mutex<Vec<Event>>> {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        gashapon.subscribe(move |event| sink.lock().unwrap().push(event.clone()));
+        events
+    }
+
+    #[test]
+    fn test_events_on_draw() {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("Item1")).with_quantity(1),
+                GashaponItem::new(PrizeItem::new("Item2")).with_quantity(1),
+            ])
+            .unwrap();
+        let events = recorded(&mut gashapon);
+        gashapon.with_seed(12345).unwrap().build().unwrap();
+        gashapon.draw_with_times(2);
+
+        let events = events.lock().unwrap();
+        assert!(matches!(events[0], Event::Built));
+        assert!(matches!(
+            events[1],
+            Event::StateChanged {
+                from: MachineState::Draft,
+                to: MachineState::Live
+            }
+        ));
+        assert!(matches!(events[2], Event::Drawn(_)));
+        assert!(matches!(events[3], Event::ItemSoldOut(_)));
+        assert!(matches!(events[4], Event::Drawn(_)));
+        assert!(matches!(events[5], Event::ItemSoldOut(_)));
+        assert!(matches!(events[6], Event::PoolEmpty));
+        assert_eq!(events.len(), 7);
+    }
+
+    #[test]
+    fn test_events_on_update_and_restore() {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_item(GashaponItem::new(PrizeItem::new("Item1")).with_quantity(2))
+            .unwrap()
+            .with_seed(12345)
+            .unwrap()
+            .build()
+            .unwrap();
+        let events = recorded(&mut gashapon);
+        gashapon
+            .update_item_quantity(PrizeId::new("Item1"), 5)
+            .unwrap();
+        gashapon.restore_items().unwrap();
+
+        let events = events.lock().unwrap();
+        assert!(matches!(
+            events[0],
+            Event::QuantityChanged { from: 2, to: 5, .. }
+        ));
+        assert!(matches!(events[1], Event::Built));
+        assert!(matches!(events[2], Event::Built));
+        assert!(matches!(events[3], Event::Restored));
+    }
+
+    #[test]
+    fn test_unsubscribe() {
+        let mut gashapon = Gashapon::default();
+        let events = recorded(&mut gashapon);
+        let id = gashapon.subscribe(|_| panic!("Unsubscribed callback was called"));
+        assert!(gashapon.unsubscribe(id));
+        assert!(!gashapon.unsubscribe(id));
+
+        gashapon.build().unwrap();
+        assert_eq!(events.lock().unwrap().len(), 1);
+    }
+}
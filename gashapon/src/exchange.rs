@@ -0,0 +1,374 @@
+//! Exchanging duplicate prizes for points, and points for chosen prizes.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::{
+    DrawCause, DrawMode, Event, Gashapon, GetPrizeItemId, MachineError, MachineEvent, Player,
+    PrizeId, PrizeItem,
+};
+
+/// What duplicates are worth and what prizes cost, in points, by tier.
+/// Prizes without a tier, or of a tier with no rate, cannot be exchanged.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExchangeRates {
+    /// Points a duplicate copy is worth.
+    #[cfg_attr(feature = "serde", serde(default))]
+    points: BTreeMap<String, u64>,
+    /// Points it takes to redeem a prize.
+    #[cfg_attr(feature = "serde", serde(default))]
+    costs: BTreeMap<String, u64>,
+}
+
+impl ExchangeRates {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pay `points` for every duplicate of the tier.
+    pub fn with_points<T>(mut self, tier: T, points: u64) -> Self
+    where
+        T: ToString,
+    {
+        self.points.insert(tier.to_string(), points);
+        self
+    }
+
+    /// Let prizes of the tier be redeemed for `points`.
+    pub fn with_cost<T>(mut self, tier: T, points: u64) -> Self
+    where
+        T: ToString,
+    {
+        self.costs.insert(tier.to_string(), points);
+        self
+    }
+
+    /// Points a duplicate of `prize` is worth, 0 if it cannot be exchanged.
+    pub fn points_for(&self, prize: &PrizeItem) -> u64 {
+        prize
+            .tier
+            .as_ref()
+            .and_then(|tier| self.points.get(tier))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Points it takes to redeem `prize`, `None` if it cannot be redeemed.
+    pub fn cost_of(&self, prize: &PrizeItem) -> Option<u64> {
+        self.costs.get(prize.tier.as_ref()?).copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty() && self.costs.is_empty()
+    }
+}
+
+/// A redemption the machine refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExchangeError {
+    Machine(MachineError),
+    UnknownPrize(PrizeId),
+    /// The prize's tier has no redemption cost.
+    NotRedeemable(PrizeId),
+    /// No copy of the prize is left to redeem, or every copy is reserved.
+    OutOfStock(PrizeId),
+    NotEnoughPoints {
+        needed: u64,
+        available: u64,
+    },
+    /// The player's points balance would no longer fit.
+    PointsOverflow,
+}
+
+impl fmt::Display for ExchangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExchangeError::Machine(err) => write!(f, "{err}"),
+            ExchangeError::UnknownPrize(id) => write!(f, "prize {id} is not in the machine"),
+            ExchangeError::NotRedeemable(id) => write!(f, "prize {id} cannot be redeemed"),
+            ExchangeError::OutOfStock(id) => write!(f, "no copy of prize {id} is left to redeem"),
+            ExchangeError::NotEnoughPoints { needed, available } => {
+                write!(
+                    f,
+                    "needs {needed} points but only {available} are available"
+                )
+            }
+            ExchangeError::PointsOverflow => write!(f, "the points balance would overflow"),
+        }
+    }
+}
+
+impl Error for ExchangeError {}
+
+impl From<MachineError> for ExchangeError {
+    fn from(err: MachineError) -> Self {
+        ExchangeError::Machine(err)
+    }
+}
+
+impl Gashapon {
+    pub fn with_exchange_rates(&mut self, rates: ExchangeRates) -> Result<&mut Self, MachineError> {
+        self.ensure_editable()?;
+        self.exchange = rates.clone();
+        self.record(MachineEvent::ExchangeRatesSet(rates));
+        Ok(self)
+    }
+
+    pub fn exchange_rates(&self) -> &ExchangeRates {
+        &self.exchange
+    }
+
+    /// Exchange every duplicate the player owns of this machine's prizes
+    /// for points, keeping one copy of each. Returns the points earned.
+    ///
+    /// Stops at the first prize whose points would overflow the balance;
+    /// the prizes exchanged before it stay exchanged.
+    pub fn exchange_duplicates(&self, player: &mut Player) -> Result<u64, ExchangeError> {
+        let duplicates = player.duplicates().collect::<Vec<_>>();
+        let mut earned = 0u64;
+        for (id, copies) in duplicates {
+            let Some(item) = self.items.get(&id) else {
+                continue;
+            };
+            let points = self.exchange.points_for(&item.prize).saturating_mul(copies);
+            if points > 0 {
+                player.convert(id, copies, points)?;
+                earned = earned
+                    .checked_add(points)
+                    .ok_or(ExchangeError::PointsOverflow)?;
+            }
+        }
+        Ok(earned)
+    }
+
+    /// Spend the player's points on a chosen prize. A copy is taken out of
+    /// the stock and the pool, as if drawn, and recorded in the history as
+    /// [`DrawCause::Redeemed`]. Reserved copies are never redeemed.
+    pub fn redeem(&mut self, player: &mut Player, id: PrizeId) -> Result<PrizeItem, ExchangeError> {
+        let cost = self.redemption_cost(&id)?;
+        if player.points() < cost {
+            return Err(ExchangeError::NotEnoughPoints {
+                needed: cost,
+                available: player.points(),
+            });
+        }
+        let prize = self.take_redeemed(id, cost)?;
+        player.redeem(&prize, cost);
+        Ok(prize)
+    }
+
+    /// Points it takes to redeem `id`.
+    pub(crate) fn redemption_cost(&self, id: &PrizeId) -> Result<u64, ExchangeError> {
+        let item = self
+            .items
+            .get(id)
+            .ok_or_else(|| ExchangeError::UnknownPrize(id.clone()))?;
+        self.exchange
+            .cost_of(&item.prize)
+            .ok_or_else(|| ExchangeError::NotRedeemable(id.clone()))
+    }
+
+    /// Take a copy of `id` out for `cost` points, whoever pays them.
+    pub(crate) fn take_redeemed(
+        &mut self,
+        id: PrizeId,
+        cost: u64,
+    ) -> Result<PrizeItem, ExchangeError> {
+        let item = self
+            .items
+            .get(&id)
+            .ok_or_else(|| ExchangeError::UnknownPrize(id.clone()))?;
+        if item.quantity == 0 {
+            return Err(ExchangeError::OutOfStock(id));
+        }
+        self.check_drawable()?;
+        let unlimited = item.is_unlimited();
+        let is_prize = |prize: &PrizeItem| prize.get_id() == id;
+        if self.mode == DrawMode::Box && !unlimited && !self.prizes.has_slot_where(is_prize) {
+            return Err(ExchangeError::OutOfStock(id));
+        }
+
+        self.start_draw()?;
+        self.next_cause = DrawCause::Redeemed(cost);
+        let prize = match self.mode {
+            _ if unlimited => {
+                let prize = self.items[&id].prize.clone();
+                self.record_draw(None, &prize);
+                self.observers.emit(Event::Drawn(prize.clone()));
+                prize
+            }
+            DrawMode::Box => {
+                let (slot, prize) = self
+                    .prizes
+                    .draw_slot_where(is_prize)
+                    .expect("A copy is left to redeem");
+                self.take_from_box(slot, prize)
+            }
+            DrawMode::Weighted => {
+                let prize = self
+                    .prizes
+                    .remove_weighted(&id)
+                    .expect("Every copy in stock is in the pool");
+                self.take_from_weighted(prize)
+            }
+        };
+        Ok(prize)
+    }
+
+    /// Update stock, history and subscribers for a capsule taken out of a
+    /// weighted pool.
+    pub(crate) fn take_from_weighted(&mut self, prize: PrizeItem) -> PrizeItem {
+        let item = self
+            .items
+            .get_mut(&prize.get_id())
+            .expect("Taken prize is not in the machine");
+        item.quantity = item
+            .quantity
+            .checked_sub(1)
+            .expect("Taken prize has no stock left");
+        let sold_out = item.quantity == 0;
+
+        self.record_draw(None, &prize);
+        self.observers.emit(Event::Drawn(prize.clone()));
+        if sold_out {
+            self.observers.emit(Event::ItemSoldOut(prize.get_id()));
+        }
+        if self.prizes.items.is_empty() {
+            self.observers.emit(Event::PoolEmpty);
+        }
+        prize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GashaponItem, PointsEntry};
+
+    fn gashapon(mode: DrawMode) -> Gashapon {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("S").with_tier("S")).with_quantity(1),
+                GashaponItem::new(PrizeItem::new("A").with_tier("A")).with_quantity(3),
+                GashaponItem::new(PrizeItem::new("B")).with_quantity(4),
+            ])
+            .unwrap()
+            .with_seed(12345)
+            .unwrap()
+            .with_mode(mode)
+            .unwrap()
+            .with_exchange_rates(ExchangeRates::new().with_points("A", 10).with_cost("S", 25))
+            .unwrap()
+            .build()
+            .unwrap();
+        gashapon
+    }
+
+    #[test]
+    fn test_exchange_duplicates() {
+        let gashapon = gashapon(DrawMode::Box);
+        let mut player = Player::new("Alice");
+        player.add_prizes(&[
+            PrizeItem::new("A").with_tier("A"),
+            PrizeItem::new("A").with_tier("A"),
+            PrizeItem::new("A").with_tier("A"),
+            PrizeItem::new("B"),
+            PrizeItem::new("B"),
+        ]);
+
+        assert_eq!(gashapon.exchange_duplicates(&mut player), Ok(20));
+        assert_eq!(player.points(), 20);
+        assert_eq!(player.count(PrizeId::new("A")), 1);
+        assert_eq!(player.count(PrizeId::new("B")), 2);
+        assert_eq!(
+            player.ledger(),
+            [PointsEntry::Converted {
+                prize: PrizeId::new("A"),
+                copies: 2,
+                points: 20,
+            }]
+        );
+        assert_eq!(gashapon.exchange_duplicates(&mut player), Ok(0));
+    }
+
+    #[test]
+    fn test_exchange_refuses_overflowing_points() {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_item(GashaponItem::new(PrizeItem::new("A").with_tier("A")).with_quantity(1))
+            .unwrap()
+            .with_exchange_rates(ExchangeRates::new().with_points("A", u64::MAX))
+            .unwrap();
+        let mut player = Player::new("Alice");
+        player.add_prizes(&vec![PrizeItem::new("A").with_tier("A"); 2]);
+        assert_eq!(gashapon.exchange_duplicates(&mut player), Ok(u64::MAX));
+
+        player.add_prize(&PrizeItem::new("A").with_tier("A"));
+        assert_eq!(
+            gashapon.exchange_duplicates(&mut player),
+            Err(ExchangeError::PointsOverflow)
+        );
+        assert_eq!(player.points(), u64::MAX);
+        assert_eq!(player.count(PrizeId::new("A")), 2);
+        assert_eq!(player.ledger().len(), 1);
+    }
+
+    #[test]
+    fn test_redeem_takes_prize_from_stock() {
+        for mode in [DrawMode::Box, DrawMode::Weighted] {
+            let mut gashapon = gashapon(mode);
+            let mut player = Player::new("Alice");
+            player.add_prizes(&vec![PrizeItem::new("A").with_tier("A"); 4]);
+            gashapon.exchange_duplicates(&mut player).unwrap();
+
+            assert_eq!(
+                gashapon.redeem(&mut player, PrizeId::new("A")).unwrap_err(),
+                ExchangeError::NotRedeemable(PrizeId::new("A"))
+            );
+            let prize = gashapon.redeem(&mut player, PrizeId::new("S")).unwrap();
+            assert_eq!(prize.name, "S");
+            assert_eq!(player.points(), 5);
+            assert!(player.owns(PrizeId::new("S")));
+            assert_eq!(gashapon.get_item(PrizeId::new("S")).unwrap().quantity, 0);
+            assert_eq!(
+                gashapon.prizes().quantity(),
+                8 - (mode == DrawMode::Weighted) as usize
+            );
+            assert_eq!(gashapon.history()[0].cause, DrawCause::Redeemed(25));
+            assert_eq!(gashapon.check_invariants(), Ok(()));
+
+            assert_eq!(
+                gashapon.redeem(&mut player, PrizeId::new("S")).unwrap_err(),
+                ExchangeError::NotEnoughPoints {
+                    needed: 25,
+                    available: 5,
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_redeem_skips_reserved_copies() {
+        let mut gashapon = gashapon(DrawMode::Box);
+        let slot = gashapon
+            .prizes()
+            .get_randomized_items()
+            .iter()
+            .position(|prize| prize.is_some_and(|prize| prize.name == "S"))
+            .unwrap();
+        gashapon.reserve(slot).unwrap();
+
+        let mut player = Player::new("Alice");
+        player.add_prizes(&vec![PrizeItem::new("A").with_tier("A"); 4]);
+        gashapon.exchange_duplicates(&mut player).unwrap();
+        assert_eq!(
+            gashapon.redeem(&mut player, PrizeId::new("S")).unwrap_err(),
+            ExchangeError::OutOfStock(PrizeId::new("S"))
+        );
+        assert_eq!(player.points(), 30);
+        assert!(gashapon.history().is_empty());
+    }
+}
@@ -0,0 +1,89 @@
+use std::fmt;
+
+use web_time::{SystemTime, UNIX_EPOCH};
+
+use crate::{PrizeId, PrizeItem, ResetPolicy};
+
+/// Why a draw was made.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum DrawCause {
+    #[default]
+    Paid,
+    /// A free draw granted by a prize from the same machine.
+    FreeDraw(PrizeId),
+    /// A draw granted by a prize from another machine.
+    Effect { machine: String, prize: PrizeId },
+    /// Not drawn at random but redeemed for this many points.
+    Redeemed(u64),
+    /// Not drawn at random: the slot was picked off the board.
+    Picked,
+    /// Drawn among the prizes a [`Guarantee`](crate::Guarantee) covers, on
+    /// the last pull of a batch that had none.
+    Guaranteed,
+}
+
+impl fmt::Display for DrawCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DrawCause::Paid => write!(f, "paid"),
+            DrawCause::FreeDraw(prize) => write!(f, "free draw from {prize}"),
+            DrawCause::Effect { machine, prize } => write!(f, "{prize} from {machine}"),
+            DrawCause::Redeemed(points) => write!(f, "redeemed for {points} points"),
+            DrawCause::Picked => write!(f, "picked"),
+            DrawCause::Guaranteed => write!(f, "guaranteed"),
+        }
+    }
+}
+
+/// One draw from a machine, as recorded in [`Gashapon::history`](crate::Gashapon::history).
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DrawRecord {
+    /// 1-based position of the draw in the machine's history.
+    pub sequence: u64,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// Board slot the capsule was taken from, `None` for weighted draws.
+    pub slot: Option<usize>,
+    pub prize: PrizeItem,
+    pub cause: DrawCause,
+}
+
+impl DrawRecord {
+    pub(crate) fn new(sequence: u64, slot: Option<usize>, prize: PrizeItem) -> Self {
+        Self {
+            sequence,
+            timestamp: now(),
+            slot,
+            prize,
+            cause: DrawCause::default(),
+        }
+    }
+}
+
+/// An automatic refill of the box, as recorded in
+/// [`Gashapon::resets`](crate::Gashapon::resets).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ResetRecord {
+    /// Sequence number of the draw that triggered the reset.
+    pub after_draw: u64,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub policy: ResetPolicy,
+    /// Seed the refilled box was shuffled with.
+    pub seed: usize,
+}
+
+/// Seconds since the Unix epoch.
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,169 @@
+use std::iter::FusedIterator;
+
+use crate::{DrawMode, Gashapon, PrizeItem};
+
+/// Iterator of draws from a [`Gashapon`], created by [`Gashapon::draws`].
+///
+/// Yields prizes until the box is empty or the machine stops accepting
+/// draws. A weighted machine, or one with unlimited items, never runs
+/// empty, so bound the iterator with `take` or similar.
+#[derive(Debug)]
+pub struct Draws<'a> {
+    gashapon: &'a mut Gashapon,
+}
+
+impl<'a> Draws<'a> {
+    pub(crate) fn new(gashapon: &'a mut Gashapon) -> Self {
+        Self { gashapon }
+    }
+}
+
+impl Iterator for Draws<'_> {
+    type Item = PrizeItem;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.gashapon.try_draw().ok()
+    }
+}
+
+impl FusedIterator for Draws<'_> {}
+
+/// Draining iterator created by [`Gashapon::drain`].
+///
+/// Takes every remaining capsule that is not reserved out of the box in
+/// draw order, or out of a weighted pool in pool order. Unlimited items are
+/// left alone. Capsules not consumed by the time it is dropped are still
+/// taken out.
+#[derive(Debug)]
+pub struct Drain<'a> {
+    gashapon: &'a mut Gashapon,
+}
+
+impl<'a> Drain<'a> {
+    pub(crate) fn new(gashapon: &'a mut Gashapon) -> Self {
+        Self { gashapon }
+    }
+
+    fn remaining(&self) -> usize {
+        if !self.gashapon.state.can_draw() {
+            return 0;
+        }
+        match self.gashapon.mode {
+            DrawMode::Box => self.gashapon.prizes.available(),
+            DrawMode::Weighted => self.gashapon.prizes.quantity(),
+        }
+    }
+}
+
+impl Iterator for Drain<'_> {
+    type Item = PrizeItem;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining() == 0 {
+            return None;
+        }
+        self.gashapon.start_draw().ok()?;
+        match self.gashapon.mode {
+            DrawMode::Box => Some(self.gashapon.draw_from_box()),
+            DrawMode::Weighted => {
+                let prize = self.gashapon.prizes.items.pop()?;
+                Some(self.gashapon.take_from_weighted(prize))
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.remaining();
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for Drain<'_> {}
+
+impl FusedIterator for Drain<'_> {}
+
+impl Drop for Drain<'_> {
+    fn drop(&mut self) {
+        self.for_each(drop);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DrawMode, Gashapon, GashaponItem, PrizeItem};
+
+    fn gashapon() -> Gashapon {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("S")).with_quantity(1),
+                GashaponItem::new(PrizeItem::new("A")).with_quantity(4),
+            ])
+            .unwrap()
+            .with_seed(12345)
+            .unwrap()
+            .build()
+            .unwrap();
+        gashapon
+    }
+
+    #[test]
+    fn test_draws_until_empty() {
+        let mut gashapon = gashapon();
+        assert_eq!(gashapon.draws().count(), 5);
+        assert!(gashapon.is_empty());
+        assert!(gashapon.items.values().all(|item| item.quantity == 0));
+        assert!(gashapon.draws().next().is_none());
+    }
+
+    #[test]
+    fn test_draws_until_condition() {
+        let mut gashapon = gashapon();
+        let before_s = gashapon
+            .draws()
+            .take_while(|prize| prize.name != "S")
+            .count();
+        assert_eq!(gashapon.remaining(), 5 - before_s - 1);
+    }
+
+    #[test]
+    fn test_draws_weighted_with_take() {
+        let mut gashapon = gashapon();
+        gashapon
+            .with_mode(DrawMode::Weighted)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(gashapon.draws().take(20).count(), 20);
+        assert!(!gashapon.is_empty());
+    }
+
+    #[test]
+    fn test_drain() {
+        let mut gashapon = gashapon();
+        let mut drain = gashapon.drain();
+        assert_eq!(drain.len(), 5);
+        assert!(drain.next().is_some());
+        drop(drain);
+
+        assert!(gashapon.is_empty());
+        assert!(gashapon.items.values().all(|item| item.quantity == 0));
+        assert_eq!(gashapon.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn test_drain_weighted() {
+        let mut gashapon = gashapon();
+        gashapon
+            .with_mode(DrawMode::Weighted)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(gashapon.drain().count(), 5);
+
+        assert!(gashapon.is_empty());
+        assert!(gashapon.items.values().all(|item| item.quantity == 0));
+        assert_eq!(gashapon.history().len(), 5);
+        assert_eq!(gashapon.check_invariants(), Ok(()));
+    }
+}
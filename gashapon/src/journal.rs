@@ -0,0 +1,543 @@
+//! Event-sourced machines: every change journaled as an event, so a machine
+//! can be rebuilt by replaying them.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::{
+    DrawCause, DrawMode, DrawRecord, ExchangeRates, Gashapon, GashaponItem, GetPrizeItemId,
+    Guarantee, MachineState, PrizeId, ResetPolicy, ResetRecord,
+};
+
+/// A change to a machine, as recorded in its [`Journal`].
+///
+/// Events are facts, not commands: replaying them never rolls the random
+/// number generator, so a replay always lands on the same machine, rules
+/// and settings included, and carries on drawing as it would have.
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum MachineEvent {
+    ItemAdded(GashaponItem),
+    ItemRemoved(PrizeId),
+    QuantityUpdated {
+        id: PrizeId,
+        quantity: u64,
+    },
+    SeedSet(usize),
+    UnitPriceSet(u64),
+    ModeSet(DrawMode),
+    DuplicateProtectionSet(bool),
+    /// The multi-pull guarantee was set, or removed if `None`.
+    GuaranteeSet(Option<Guarantee>),
+    ResetPolicyAdded(ResetPolicy),
+    ResetPoliciesCleared,
+    ExchangeRatesSet(ExchangeRates),
+    /// The pool was rebuilt and shuffled with this seed.
+    Built {
+        seed: usize,
+    },
+    /// A capsule was taken out, from `slot` or, for weighted and unlimited
+    /// draws, from no slot at all.
+    Drawn {
+        slot: Option<usize>,
+        prize: PrizeId,
+        cause: DrawCause,
+        /// Seconds since the Unix epoch.
+        timestamp: u64,
+        /// State of the random number generator after the draw.
+        rng: usize,
+    },
+    SlotReserved(usize),
+    SlotReleased(usize),
+    /// Every item was restocked to its original quantity.
+    Restored,
+    /// An [`Arcade`](crate::Arcade) moved stock in or out of a paused
+    /// machine, leaving the rest of the board as it was.
+    Restocked(GashaponItem),
+    /// Every item was removed.
+    Cleared,
+    HistoryCleared,
+    StateChanged(MachineState),
+    /// A reset policy refilled the box. The restock and rebuild are
+    /// journaled just before it.
+    BoxReset(ResetRecord),
+}
+
+/// The machine as it was after the first `after` events of its journal.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Snapshot {
+    pub after: usize,
+    pub machine: Gashapon,
+}
+
+/// Every event of a machine since journaling started, with periodic
+/// snapshots so it can be recovered without replaying them all.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Journal {
+    events: Vec<MachineEvent>,
+    snapshots: Vec<Snapshot>,
+    snapshot_every: usize,
+}
+
+impl Journal {
+    pub fn events(&self) -> &[MachineEvent] {
+        &self.events
+    }
+
+    /// Snapshots, oldest first. The first is the machine as it was when
+    /// journaling started.
+    pub fn snapshots(&self) -> &[Snapshot] {
+        &self.snapshots
+    }
+
+    /// Events between snapshots, 0 if only the first snapshot is taken.
+    pub fn snapshot_every(&self) -> usize {
+        self.snapshot_every
+    }
+
+    /// Rebuild the machine from the latest snapshot and the events after
+    /// it. The machine keeps journaling into a copy of this journal.
+    pub fn recover(&self) -> Result<Gashapon, ReplayError> {
+        let (mut gashapon, after) = match self.snapshots.last() {
+            Some(snapshot) => (snapshot.machine.clone(), snapshot.after),
+            None => (Gashapon::default(), 0),
+        };
+        for (index, event) in self.events.iter().enumerate().skip(after) {
+            gashapon.apply(index, event)?;
+        }
+        gashapon.journal = Some(self.clone());
+        Ok(gashapon)
+    }
+}
+
+/// An event that cannot be applied to the machine built by the events
+/// before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayError {
+    UnknownPrize {
+        index: usize,
+        id: PrizeId,
+    },
+    /// The slot is not on the board, is already opened or holds another
+    /// prize.
+    InvalidSlot {
+        index: usize,
+        slot: usize,
+    },
+    OutOfStock {
+        index: usize,
+        id: PrizeId,
+    },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::UnknownPrize { index, id } => {
+                write!(f, "event {index}: prize {id} is not in the machine")
+            }
+            ReplayError::InvalidSlot { index, slot } => {
+                write!(f, "event {index}: slot {slot} cannot be opened")
+            }
+            ReplayError::OutOfStock { index, id } => {
+                write!(f, "event {index}: prize {id} has no stock left")
+            }
+        }
+    }
+}
+
+impl Error for ReplayError {}
+
+impl Gashapon {
+    /// Journal every change from now on, taking a snapshot now and then
+    /// every `snapshot_every` events, or never again if it is 0. Replaces
+    /// any journal the machine already had.
+    pub fn with_journal(&mut self, snapshot_every: usize) -> &mut Self {
+        self.journal = None;
+        let snapshot = Snapshot {
+            after: 0,
+            machine: self.snapshot(),
+        };
+        self.journal = Some(Journal {
+            events: Vec::new(),
+            snapshots: vec![snapshot],
+            snapshot_every,
+        });
+        self
+    }
+
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    /// Stop journaling and hand over the journal.
+    pub fn take_journal(&mut self) -> Option<Journal> {
+        self.journal.take()
+    }
+
+    /// Build a machine by applying `events` to an empty one. Only events
+    /// journaled from an empty machine can be replayed this way; use
+    /// [`Journal::recover`] otherwise.
+    pub fn replay<'a, I>(events: I) -> Result<Self, ReplayError>
+    where
+        I: IntoIterator<Item = &'a MachineEvent>,
+    {
+        let mut gashapon = Gashapon::default();
+        for (index, event) in events.into_iter().enumerate() {
+            gashapon.apply(index, event)?;
+        }
+        Ok(gashapon)
+    }
+
+    /// Add an event to the journal, if the machine keeps one. Called once
+    /// the change is made, so snapshots include it.
+    pub(crate) fn record(&mut self, event: MachineEvent) {
+        let Some(mut journal) = self.journal.take() else {
+            return;
+        };
+        journal.events.push(event);
+        let after = journal.events.len();
+        if journal.snapshot_every > 0 && after % journal.snapshot_every == 0 {
+            journal.snapshots.push(Snapshot {
+                after,
+                machine: self.snapshot(),
+            });
+        }
+        self.journal = Some(journal);
+    }
+
+    /// A copy of the machine without subscribers or journal.
+    fn snapshot(&self) -> Gashapon {
+        let mut gashapon = self.clone();
+        gashapon.clear_subscribers();
+        gashapon.journal = None;
+        gashapon
+    }
+
+    fn apply(&mut self, index: usize, event: &MachineEvent) -> Result<(), ReplayError> {
+        match event {
+            MachineEvent::ItemAdded(item) => {
+                self.items.insert(item.get_prize_id(), item.clone());
+            }
+            MachineEvent::ItemRemoved(id) => {
+                self.items.remove(id);
+            }
+            MachineEvent::QuantityUpdated { id, quantity } => {
+                self.items
+                    .get_mut(id)
+                    .ok_or_else(|| ReplayError::UnknownPrize {
+                        index,
+                        id: id.clone(),
+                    })?
+                    .quantity = *quantity;
+            }
+            MachineEvent::SeedSet(seed) => self.prizes.with_seed(*seed),
+            MachineEvent::UnitPriceSet(unit_price) => self.unit_price = *unit_price,
+            MachineEvent::ModeSet(mode) => self.mode = *mode,
+            MachineEvent::DuplicateProtectionSet(enabled) => self.duplicate_protection = *enabled,
+            MachineEvent::GuaranteeSet(guarantee) => self.guarantee = guarantee.clone(),
+            MachineEvent::ResetPolicyAdded(policy) => self.reset_policies.push(policy.clone()),
+            MachineEvent::ResetPoliciesCleared => self.reset_policies.clear(),
+            MachineEvent::ExchangeRatesSet(rates) => self.exchange = rates.clone(),
+            MachineEvent::Built { seed } => self.build_with_seed(*seed),
+            MachineEvent::Drawn {
+                slot,
+                prize,
+                cause,
+                timestamp,
+                rng,
+            } => self.apply_draw(index, *slot, prize, cause, *timestamp, *rng)?,
+            MachineEvent::SlotReserved(slot) => {
+                self.prizes.reserved.insert(*slot);
+            }
+            MachineEvent::SlotReleased(slot) => {
+                self.prizes.reserved.remove(slot);
+            }
+            MachineEvent::Restored => {
+                for item in self.items.values_mut() {
+                    item.restore();
+                }
+            }
+            MachineEvent::Restocked(item) => self.restock(item),
+            MachineEvent::Cleared => self.items.clear(),
+            MachineEvent::HistoryCleared => {
+                self.history.clear();
+                self.resets.clear();
+            }
+            MachineEvent::StateChanged(state) => self.state = *state,
+            MachineEvent::BoxReset(record) => self.resets.push(record.clone()),
+        }
+        Ok(())
+    }
+
+    fn apply_draw(
+        &mut self,
+        index: usize,
+        slot: Option<usize>,
+        id: &PrizeId,
+        cause: &DrawCause,
+        timestamp: u64,
+        rng: usize,
+    ) -> Result<(), ReplayError> {
+        let item = self
+            .items
+            .get(id)
+            .ok_or_else(|| ReplayError::UnknownPrize {
+                index,
+                id: id.clone(),
+            })?;
+        let prize = item.prize.clone();
+        let taken = match slot {
+            Some(slot) => {
+                let opened = self.prizes.draw_at(slot);
+                if opened.is_none_or(|opened| opened.get_id() != *id) {
+                    return Err(ReplayError::InvalidSlot { index, slot });
+                }
+                true
+            }
+            None if item.is_unlimited() => false,
+            // Weighted draws put the capsule back, unless it was redeemed
+            None => {
+                matches!(cause, DrawCause::Redeemed(_)) && self.prizes.remove_weighted(id).is_some()
+            }
+        };
+        if taken {
+            let item = self.items.get_mut(id).expect("Prize was looked up");
+            item.quantity =
+                item.quantity
+                    .checked_sub(1)
+                    .ok_or_else(|| ReplayError::OutOfStock {
+                        index,
+                        id: id.clone(),
+                    })?;
+        }
+        self.prizes.state = rng;
+        self.history.push(DrawRecord {
+            sequence: self.history.len() as u64 + 1,
+            timestamp,
+            slot,
+            prize,
+            cause: cause.clone(),
+        });
+        self.opened_at.get_or_insert(timestamp);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Guarantee, PrizeItem, ResetPolicy};
+
+    fn journaled(mode: DrawMode, snapshot_every: usize) -> Gashapon {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .with_journal(snapshot_every)
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("S").with_tier("S")).with_quantity(1),
+                GashaponItem::new(PrizeItem::new("A")).with_quantity(4),
+                GashaponItem::new(PrizeItem::new("B")).with_quantity(6),
+                GashaponItem::new(PrizeItem::new("Bonus"))
+                    .with_quantity(2)
+                    .with_unlimited(true),
+            ])
+            .unwrap()
+            .with_seed(12345)
+            .unwrap()
+            .with_mode(mode)
+            .unwrap()
+            .with_unit_price(100)
+            .unwrap()
+            .build()
+            .unwrap();
+        gashapon
+    }
+
+    fn assert_same(replayed: &Gashapon, original: &Gashapon) {
+        let summary = |gashapon: &Gashapon| {
+            let mut items = gashapon
+                .items()
+                .values()
+                .map(|item| (item.prize.name.clone(), item.quantity))
+                .collect::<Vec<_>>();
+            items.sort();
+            let history = gashapon
+                .history()
+                .iter()
+                .map(|record| (record.sequence, record.slot, record.prize.get_id()))
+                .collect::<Vec<_>>();
+            (
+                items,
+                history,
+                format!("{:?}", gashapon.board()),
+                gashapon.prizes().idx_box().to_vec(),
+                gashapon.prizes().quantity(),
+                gashapon.state(),
+                gashapon.unit_price(),
+                gashapon.resets().to_vec(),
+                gashapon.guarantee().cloned(),
+                gashapon.reset_policies().to_vec(),
+                gashapon.exchange_rates().clone(),
+                gashapon.duplicate_protection(),
+            )
+        };
+        assert_eq!(summary(replayed), summary(original));
+        assert_eq!(replayed.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn test_replay_rebuilds_machine() {
+        for mode in [DrawMode::Box, DrawMode::Weighted] {
+            let mut gashapon = journaled(mode, 0);
+            gashapon.draw_with_times(3);
+            if mode == DrawMode::Box {
+                gashapon.reserve(gashapon.prizes().idx_box()[0]).unwrap();
+                let slot = gashapon.prizes().idx_box()[1];
+                gashapon.draw_at(slot).unwrap();
+            }
+            gashapon.draw_resolved().unwrap();
+
+            let events = gashapon.journal().unwrap().events();
+            assert!(matches!(events[0], MachineEvent::ItemAdded(_)));
+            let mut replayed = Gashapon::replay(events).unwrap();
+            assert_same(&replayed, &gashapon);
+
+            // The random number generator carries on where it left off
+            assert_eq!(
+                replayed
+                    .draw_with_times(4)
+                    .into_iter()
+                    .map(|prize| prize.name)
+                    .collect::<Vec<_>>(),
+                gashapon
+                    .draw_with_times(4)
+                    .into_iter()
+                    .map(|prize| prize.name)
+                    .collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn test_replay_resets_restores_and_clears() {
+        let mut gashapon = journaled(DrawMode::Box, 0);
+        gashapon
+            .with_reset_policy(ResetPolicy::RemainingBelow(8))
+            .unwrap()
+            .with_guarantee(Guarantee::new(2, ["S"]))
+            .unwrap();
+        gashapon.draw_with_times(6);
+        assert_eq!(gashapon.resets().len(), 1);
+        gashapon.pause().unwrap().reset_to_original().unwrap();
+        gashapon
+            .update_item_quantity(PrizeId::new("A"), 2)
+            .unwrap()
+            .remove_item(PrizeId::new("B"))
+            .unwrap();
+        gashapon.draw();
+        assert_same(
+            &Gashapon::replay(gashapon.journal().unwrap().events()).unwrap(),
+            &gashapon,
+        );
+
+        gashapon.clear().unwrap();
+        assert_same(
+            &Gashapon::replay(gashapon.journal().unwrap().events()).unwrap(),
+            &gashapon,
+        );
+    }
+
+    #[test]
+    fn test_recover_from_snapshots() {
+        let mut gashapon = journaled(DrawMode::Box, 5);
+        gashapon.draw_with_times(7);
+        gashapon.pause().unwrap();
+
+        let journal = gashapon.take_journal().unwrap();
+        let events = journal.events().len();
+        let snapshots = journal.snapshots();
+        assert_eq!(snapshots.len(), 1 + events / 5);
+        assert_eq!(snapshots.last().unwrap().after, events / 5 * 5);
+
+        let mut recovered = journal.recover().unwrap();
+        assert_same(&recovered, &gashapon);
+        assert_eq!(recovered.state(), MachineState::Paused);
+
+        // The recovered machine keeps journaling
+        recovered.resume().unwrap();
+        assert_eq!(recovered.journal().unwrap().events().len(), events + 1);
+    }
+
+    #[test]
+    fn test_recovered_machine_draws_like_the_original() {
+        let mut gashapon = journaled(DrawMode::Box, 0);
+        gashapon
+            .with_guarantee(Guarantee::new(3, ["S"]))
+            .unwrap()
+            .with_reset_policy(ResetPolicy::RemainingBelow(4))
+            .unwrap()
+            .with_duplicate_protection(true)
+            .unwrap()
+            .with_exchange_rates(ExchangeRates::new().with_cost("S", 10))
+            .unwrap();
+        gashapon.draw_with_times(3);
+
+        let mut recovered = gashapon.journal().unwrap().recover().unwrap();
+        assert_same(&recovered, &gashapon);
+        let drawn = |gashapon: &mut Gashapon| {
+            gashapon
+                .draw_with_times(9)
+                .into_iter()
+                .map(|prize| prize.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(drawn(&mut recovered), drawn(&mut gashapon));
+        let seeds = |gashapon: &Gashapon| {
+            gashapon
+                .resets()
+                .iter()
+                .map(|reset| (reset.after_draw, reset.seed))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(seeds(&recovered), seeds(&gashapon));
+        assert!(!gashapon.resets().is_empty());
+    }
+
+    #[test]
+    fn test_replay_rejects_inconsistent_events() {
+        let mut gashapon = journaled(DrawMode::Box, 0);
+        gashapon.draw();
+        let mut events = gashapon.take_journal().unwrap().events().to_vec();
+        let drawn = events.len() - 1;
+        let MachineEvent::Drawn { slot, .. } = &events[drawn] else {
+            panic!("Last event is not a draw");
+        };
+        let opened = slot.unwrap();
+        events.push(events[drawn].clone());
+        assert_eq!(
+            Gashapon::replay(&events).unwrap_err(),
+            ReplayError::InvalidSlot {
+                index: drawn + 1,
+                slot: opened,
+            }
+        );
+
+        let update = MachineEvent::QuantityUpdated {
+            id: PrizeId::new("S"),
+            quantity: 1,
+        };
+        assert_eq!(
+            Gashapon::replay(&[update]).unwrap_err(),
+            ReplayError::UnknownPrize {
+                index: 0,
+                id: PrizeId::new("S"),
+            }
+        );
+    }
+}
@@ -1,12 +1,71 @@
+mod arcade;
+mod board;
+mod builder;
+pub mod collection;
+#[cfg(feature = "serde")]
+pub mod config;
+#[cfg(feature = "csv")]
+pub mod csv;
+mod effect;
+mod event;
+mod exchange;
+mod history;
+mod iter;
+mod journal;
+mod odds;
+mod player;
+mod registry;
+mod reset;
+mod rules;
+#[cfg(feature = "serde")]
+pub mod session;
+mod shared;
+pub mod simulate;
+mod state;
+#[cfg(feature = "serde")]
+pub mod store;
 mod utils;
 
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
 use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Arc;
 use web_time::{self, UNIX_EPOCH};
 
-use utils::{randomize, rng};
+pub use arcade::{Arcade, StockError};
+pub use board::BoardSlot;
+pub use builder::{BuildError, DEFAULT_MAX_CAPSULES, GashaponBuilder, Issue, Validation};
+pub use effect::{DrawOutcome, EffectError, MAX_CHAINED_DRAWS, PrizeEffect, ResolvedDraw};
+use event::Observers;
+pub use event::{Event, SubscriptionId};
+pub use exchange::{ExchangeError, ExchangeRates};
+pub use history::{DrawCause, DrawRecord, ResetRecord};
+pub use iter::{Drain, Draws};
+pub use journal::{Journal, MachineEvent, ReplayError, Snapshot};
+pub use odds::Ratio;
+pub use player::{Player, PointsEntry};
+pub use registry::{MachineId, Registry, RegistryError};
+pub use reset::ResetPolicy;
+pub use rules::Guarantee;
+pub use shared::SharedGashapon;
+pub use state::{MachineError, MachineState};
+use utils::{randomize, rng_below};
+
+/// A seed from the current time, fine enough that two machines shuffled
+/// one after the other get different ones.
+fn time_seed() -> usize {
+    web_time::SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as usize
+}
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
 pub struct PrizeId(u64);
 
 impl PrizeId {
@@ -25,17 +84,27 @@ impl PrizeId {
     pub fn get_id(&self) -> u64 {
         self.0
     }
+}
+
+impl From<u64> for PrizeId {
+    fn from(id: u64) -> Self {
+        Self(id)
+    }
+}
 
-    pub fn to_string(&self) -> String {
-        self.0.to_string()
+impl fmt::Display for PrizeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
     }
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GashaponItem {
     pub prize: PrizeItem,
     pub quantity: u64,
     original_quantity: u64,
+    unlimited: bool,
 }
 
 impl GashaponItem {
@@ -44,6 +113,7 @@ impl GashaponItem {
             prize,
             quantity: u64::default(),
             original_quantity: u64::default(),
+            unlimited: false,
         }
     }
 
@@ -53,6 +123,22 @@ impl GashaponItem {
         self
     }
 
+    /// Never run out, like a consolation prize. The quantity is then a
+    /// weight against the capsules left in the box and is never drawn
+    /// down, so the item is not expanded into capsules either.
+    pub fn with_unlimited(mut self, unlimited: bool) -> Self {
+        self.unlimited = unlimited;
+        self
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.unlimited
+    }
+
+    pub fn original_quantity(&self) -> u64 {
+        self.original_quantity
+    }
+
     pub fn get_prize_id(&self) -> PrizeId {
         self.prize.get_id()
     }
@@ -67,9 +153,15 @@ pub trait GetPrizeItemId {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PrizeItem {
     id: PrizeId,
     pub name: String,
+    pub tier: Option<String>,
+    pub value: u64,
+    /// What the prize does when drawn through [`Gashapon::draw_resolved`]
+    /// or an [`Arcade`].
+    pub effect: Option<PrizeEffect>,
 }
 
 impl PrizeItem {
@@ -80,8 +172,35 @@ impl PrizeItem {
         Self {
             id: PrizeId::new(name.clone()),
             name: name.to_string(),
+            tier: None,
+            value: u64::default(),
+            effect: None,
         }
     }
+
+    /// Use an explicit id, such as a warehouse SKU, instead of one derived from the name.
+    pub fn with_id(mut self, id: PrizeId) -> Self {
+        self.id = id;
+        self
+    }
+
+    pub fn with_tier<T>(mut self, tier: T) -> Self
+    where
+        T: ToString,
+    {
+        self.tier = Some(tier.to_string());
+        self
+    }
+
+    pub fn with_value(mut self, value: u64) -> Self {
+        self.value = value;
+        self
+    }
+
+    pub fn with_effect(mut self, effect: PrizeEffect) -> Self {
+        self.effect = Some(effect);
+        self
+    }
 }
 
 impl GetPrizeItemId for PrizeItem {
@@ -91,11 +210,19 @@ impl GetPrizeItemId for PrizeItem {
 }
 
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Prizes {
-    pub items: Vec<PrizeItem>,
-    pub idx_box: Vec<usize>,
-    pub randomized_items: Vec<Option<usize>>,
+    items: Vec<PrizeItem>,
+    idx_box: Vec<usize>,
+    randomized_items: Vec<Option<usize>>,
+    /// Pool index of the capsule each opened slot held.
+    opened: Vec<Option<usize>>,
+    /// Slots held back from random draws.
+    reserved: BTreeSet<usize>,
+    /// Unlimited prizes and their weights, kept out of the pool.
+    unlimited: Vec<(PrizeItem, u64)>,
     seed: Option<usize>,
+    state: usize,
 }
 
 impl Prizes {
@@ -104,33 +231,63 @@ impl Prizes {
             items: Vec::new(),
             idx_box: Vec::new(),
             randomized_items: Vec::new(),
+            opened: Vec::new(),
+            reserved: BTreeSet::new(),
+            unlimited: Vec::new(),
             seed: None,
+            state: 0,
         }
     }
 
-    fn randomnize_items(&mut self) {
+    fn randomnize_items(&mut self, mut seed: usize) {
         let items = self
             .items
             .iter()
             .enumerate()
             .map(|(idx, _)| Some(idx))
             .collect::<Vec<Option<usize>>>();
-        let mut seed = self.get_seed();
         self.randomized_items = randomize(items, &mut seed);
+        self.opened = vec![None; self.randomized_items.len()];
+        self.reserved.clear();
     }
 
-    fn update_idx_box(&mut self) {
+    fn update_idx_box(&mut self, mut seed: usize) {
         let idx_box = self
             .items
             .iter()
             .enumerate()
             .map(|(idx, _)| idx)
             .collect::<Vec<usize>>();
-        let mut seed = self.get_seed();
         self.idx_box = randomize(idx_box, &mut seed);
+        // Draws carry on from the shuffle instead of repeating its numbers
+        self.state = seed;
+    }
+
+    /// Every capsule in the pool, drawn or not, in pool order.
+    pub fn items(&self) -> &[PrizeItem] {
+        &self.items
+    }
+
+    /// Board slots still to be drawn, in the order they were shuffled.
+    pub fn idx_box(&self) -> &[usize] {
+        &self.idx_box
+    }
+
+    /// The board: each slot holds the pool index of its capsule, or `None`
+    /// once it has been drawn.
+    pub fn randomized_items(&self) -> &[Option<usize>] {
+        &self.randomized_items
     }
 
     pub fn with_items(&mut self, items: Vec<&GashaponItem>) {
+        let (unlimited, items) = items
+            .into_iter()
+            .partition::<Vec<_>, _>(|item| item.is_unlimited());
+        self.unlimited = unlimited
+            .into_iter()
+            .filter(|item| item.quantity > 0)
+            .map(|item| (item.prize.clone(), item.quantity))
+            .collect();
         let items = {
             let mut items_vec = Vec::new();
             for item in items {
@@ -149,61 +306,209 @@ impl Prizes {
 
     /// Get the seed value, or generate a new one based on the current time if not set.
     pub fn get_seed(&self) -> usize {
-        self.seed.unwrap_or_else(|| {
-            web_time::SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs() as usize
-        })
+        self.seed.unwrap_or_else(time_seed)
     }
 
     pub fn build(&mut self) {
-        self.randomnize_items();
-        self.update_idx_box();
+        self.build_with_seed(self.get_seed());
+    }
+
+    /// Shuffle the pool with `seed`, whatever seed is set.
+    pub(crate) fn build_with_seed(&mut self, seed: usize) {
+        self.randomnize_items(seed);
+        self.update_idx_box(seed);
     }
 
     pub fn draw(&mut self) -> PrizeItem {
-        if self.idx_box.is_empty() {
+        self.draw_slot().1
+    }
+
+    /// Draw a capsule, also returning the board slot it was taken from.
+    /// Reserved slots are skipped.
+    pub fn draw_slot(&mut self) -> (usize, PrizeItem) {
+        let available = self.available();
+        if available == 0 {
             panic!("No more items to draw!");
         }
 
-        // random select
-        let r = if self.idx_box.len() - 1 > 0 {
-            let mut seed = self.get_seed();
-            rng(&mut seed) % (self.idx_box.len() - 1)
-        } else {
-            0
+        let r = self.pick(available);
+        let r = match self.reserved.is_empty() {
+            true => r,
+            false => (0..self.idx_box.len())
+                .filter(|pos| !self.reserved.contains(&self.idx_box[*pos]))
+                .nth(r)
+                .expect("Fewer open slots than available"),
         };
+        self.open_position(r)
+    }
+
+    /// Draw a capsule from the slots whose prize is accepted, or `None` if
+    /// there is no such slot.
+    pub(crate) fn draw_slot_where<F>(&mut self, accept: F) -> Option<(usize, PrizeItem)>
+    where
+        F: Fn(&PrizeItem) -> bool,
+    {
+        let candidates = self.positions_where(accept).collect::<Vec<_>>();
+        if candidates.is_empty() {
+            return None;
+        }
+        let r = self.pick(candidates.len());
+        Some(self.open_position(candidates[r]))
+    }
+
+    /// Whether an unreserved slot holds a prize that is accepted.
+    pub(crate) fn has_slot_where<F>(&self, accept: F) -> bool
+    where
+        F: Fn(&PrizeItem) -> bool,
+    {
+        self.positions_where(accept).next().is_some()
+    }
+
+    /// Positions in the box of the unreserved slots whose prize is accepted.
+    fn positions_where<'a, F>(&'a self, accept: F) -> impl Iterator<Item = usize> + 'a
+    where
+        F: Fn(&PrizeItem) -> bool + 'a,
+    {
+        (0..self.idx_box.len()).filter(move |pos| {
+            let slot = self.idx_box[*pos];
+            !self.reserved.contains(&slot)
+                && self
+                    .get_item_by_index(self.randomized_items[slot])
+                    .is_some_and(&accept)
+        })
+    }
+
+    /// Random index below `len`, advancing the random number generator.
+    fn pick(&mut self, len: usize) -> usize {
+        rng_below(&mut self.state, len as u64) as usize
+    }
+
+    /// Open the slot at position `r` of the box.
+    fn open_position(&mut self, r: usize) -> (usize, PrizeItem) {
         let idx = self.idx_box[r];
-        let item_idx = self.randomized_items[idx].clone();
+        let item_idx = self.randomized_items[idx];
 
         // Mark as drawn
         self.randomized_items[idx] = None;
+        self.opened[idx] = item_idx;
         self.idx_box.remove(r);
-        self.get_item_by_index(item_idx)
+        let prize = self
+            .get_item_by_index(item_idx)
             .cloned()
-            .expect("Item already drawn")
+            .expect("Item already drawn");
+        (idx, prize)
+    }
+
+    /// Number of unopened slots that are not reserved.
+    pub fn available(&self) -> usize {
+        self.idx_box.len() - self.reserved.len()
+    }
+
+    /// Open a specific board slot, or `None` if there is no such slot or it
+    /// has already been drawn.
+    pub fn draw_at(&mut self, slot: usize) -> Option<PrizeItem> {
+        let item_idx = self.randomized_items.get_mut(slot)?.take()?;
+        self.opened[slot] = Some(item_idx);
+        self.reserved.remove(&slot);
+        self.idx_box.retain(|idx| *idx != slot);
+        self.get_item_by_index(Some(item_idx)).cloned()
     }
 
     pub fn draw_with_times(&mut self, times: u64) -> Vec<PrizeItem> {
         let mut drawn_items = Vec::new();
-        (0..(times.min(self.idx_box.len() as u64)))
-            .into_iter()
-            .for_each(|_| {
-                let item = self.draw();
-                drawn_items.push(item);
-            });
+        (0..(times.min(self.idx_box.len() as u64))).for_each(|_| {
+            let item = self.draw();
+            drawn_items.push(item);
+        });
         drawn_items
     }
 
-    pub fn get_item_by_index(&self, index: Option<usize>) -> Option<&PrizeItem> {
-        let Some(idx) = index else {
+    /// Draw with replacement, using each capsule in the box as a weight.
+    pub fn draw_weighted(&mut self) -> PrizeItem {
+        if self.items.is_empty() {
+            panic!("No more items to draw!");
+        }
+
+        let r = rng_below(&mut self.state, self.items.len() as u64) as usize;
+        self.items[r].clone()
+    }
+
+    /// Draw with replacement among the accepted capsules, or `None` if no
+    /// capsule is accepted.
+    pub(crate) fn draw_weighted_where<F>(&mut self, accept: F) -> Option<PrizeItem>
+    where
+        F: Fn(&PrizeItem) -> bool,
+    {
+        let candidates = self
+            .items
+            .iter()
+            .filter(|prize| accept(prize))
+            .collect::<Vec<_>>();
+        if candidates.is_empty() {
             return None;
-        };
+        }
 
-        self.items.get(idx)
+        let r = rng_below(&mut self.state, candidates.len() as u64) as usize;
+        Some(candidates[r].clone())
     }
 
+    /// Unlimited prizes and their weights. They are never part of the
+    /// pool or the board.
+    pub fn unlimited(&self) -> &[(PrizeItem, u64)] {
+        &self.unlimited
+    }
+
+    /// Total weight of the accepted unlimited prizes.
+    fn unlimited_weight<F>(&self, accept: F) -> u64
+    where
+        F: Fn(&PrizeItem) -> bool,
+    {
+        self.unlimited
+            .iter()
+            .filter(|(prize, _)| accept(prize))
+            .map(|(_, weight)| *weight)
+            .sum()
+    }
+
+    /// Roll between `finite` capsules and the accepted unlimited prizes,
+    /// returning the unlimited prize drawn, or `None` if a capsule should
+    /// be drawn instead.
+    pub(crate) fn roll_unlimited<F>(&mut self, finite: usize, accept: F) -> Option<PrizeItem>
+    where
+        F: Fn(&PrizeItem) -> bool,
+    {
+        let weight = self.unlimited_weight(&accept);
+        if weight == 0 {
+            return None;
+        }
+        let r = rng_below(&mut self.state, finite as u64 + weight);
+        let mut r = r.checked_sub(finite as u64)?;
+        self.unlimited
+            .iter()
+            .filter(|(prize, _)| accept(prize))
+            .find(|(_, weight)| match r.checked_sub(*weight) {
+                Some(rest) => {
+                    r = rest;
+                    false
+                }
+                None => true,
+            })
+            .map(|(prize, _)| prize.clone())
+    }
+
+    /// Take one capsule of a prize out of a weighted pool. Box pools must
+    /// open a slot instead, since the board points into the pool.
+    pub(crate) fn remove_weighted(&mut self, id: &PrizeId) -> Option<PrizeItem> {
+        let pos = self.items.iter().position(|prize| prize.get_id() == *id)?;
+        Some(self.items.remove(pos))
+    }
+
+    pub fn get_item_by_index(&self, index: Option<usize>) -> Option<&PrizeItem> {
+        self.items.get(index?)
+    }
+
+    /// The prize behind every unopened slot. Only meant for operators; use
+    /// [`Gashapon::board`] for anything players can see.
     pub fn get_randomized_items(&self) -> Vec<Option<&PrizeItem>> {
         self.randomized_items
             .iter()
@@ -216,10 +521,63 @@ impl Prizes {
     }
 }
 
+/// How capsules are taken out of the machine.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum DrawMode {
+    /// Capsules are removed from a finite box until it is empty.
+    #[default]
+    Box,
+    /// Capsules are drawn with replacement; quantities only act as weights.
+    Weighted,
+}
+
+impl fmt::Display for DrawMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DrawMode::Box => write!(f, "box"),
+            DrawMode::Weighted => write!(f, "weighted"),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Gashapon {
-    pub items: HashMap<PrizeId, GashaponItem>,
-    pub prizes: Prizes,
+    items: HashMap<PrizeId, GashaponItem>,
+    prizes: Prizes,
+    unit_price: u64,
+    mode: DrawMode,
+    state: MachineState,
+    duplicate_protection: bool,
+    guarantee: Option<Guarantee>,
+    exchange: ExchangeRates,
+    reset_policies: Vec<ResetPolicy>,
+    history: Vec<DrawRecord>,
+    resets: Vec<ResetRecord>,
+    /// When the first capsule was drawn since the box was last built.
+    opened_at: Option<u64>,
+    /// The seed the box was last shuffled with.
+    #[cfg_attr(feature = "serde", serde(default))]
+    shuffled_with: Option<usize>,
+    /// The seed the box was first shuffled with, which a machine without a
+    /// seed of its own is put back to.
+    #[cfg_attr(feature = "serde", serde(default))]
+    first_seed: Option<usize>,
+    /// Whether the stock belongs to an [`Arcade`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    shared_stock: bool,
+    /// Why the next draw is made, recorded with it in the history.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    next_cause: DrawCause,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    journal: Option<Journal>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    observers: Observers,
 }
 
 impl Default for Gashapon {
@@ -227,95 +585,597 @@ impl Default for Gashapon {
         Self {
             items: HashMap::new(),
             prizes: Prizes::new(),
+            unit_price: u64::default(),
+            mode: DrawMode::default(),
+            state: MachineState::default(),
+            duplicate_protection: false,
+            guarantee: None,
+            exchange: ExchangeRates::default(),
+            reset_policies: Vec::new(),
+            history: Vec::new(),
+            resets: Vec::new(),
+            opened_at: None,
+            shuffled_with: None,
+            first_seed: None,
+            shared_stock: false,
+            next_cause: DrawCause::default(),
+            journal: None,
+            observers: Observers::default(),
         }
     }
 }
 
 impl Gashapon {
-    pub fn add_item(&mut self, item: GashaponItem) -> &mut Self {
-        self.items.insert(item.get_prize_id(), item);
-        self
+    pub fn items(&self) -> &HashMap<PrizeId, GashaponItem> {
+        &self.items
+    }
+
+    pub fn get_item(&self, id: PrizeId) -> Option<&GashaponItem> {
+        self.items.get(&id)
+    }
+
+    pub fn prizes(&self) -> &Prizes {
+        &self.prizes
+    }
+
+    pub fn unit_price(&self) -> u64 {
+        self.unit_price
     }
 
-    pub fn add_items(&mut self, items: Vec<GashaponItem>) -> &mut Self {
+    pub fn mode(&self) -> DrawMode {
+        self.mode
+    }
+
+    /// Sum of every item's quantity, counting the weight of unlimited
+    /// items. Wide enough that it cannot overflow.
+    pub(crate) fn total_quantity(&self) -> u128 {
+        self.items.values().map(|item| item.quantity as u128).sum()
+    }
+
+    /// Number of capsules in stock, leaving out unlimited items.
+    pub(crate) fn capsule_count(&self) -> u128 {
+        self.items
+            .values()
+            .filter(|item| !item.is_unlimited())
+            .map(|item| item.quantity as u128)
+            .sum()
+    }
+
+    pub fn add_item(&mut self, item: GashaponItem) -> Result<&mut Self, MachineError> {
+        self.ensure_editable()?;
+        self.ensure_own_stock()?;
+        self.items.insert(item.get_prize_id(), item.clone());
+        self.record(MachineEvent::ItemAdded(item));
+        Ok(self)
+    }
+
+    pub fn add_items(&mut self, items: Vec<GashaponItem>) -> Result<&mut Self, MachineError> {
+        self.ensure_editable()?;
+        self.ensure_own_stock()?;
         for item in items {
-            self.items.insert(item.get_prize_id(), item);
+            self.items.insert(item.get_prize_id(), item.clone());
+            self.record(MachineEvent::ItemAdded(item));
         }
-        self
+        Ok(self)
+    }
+
+    /// Restock every item and refill the box, keeping the draw history.
+    /// The machine goes back to [`MachineState::Draft`]. Fails without
+    /// changing anything if the restocked machine would be invalid.
+    /// A machine without a seed is shuffled with a new one.
+    pub fn restore_items(&mut self) -> Result<&mut Self, BuildError> {
+        self.ensure_not_closed()?;
+        self.ensure_own_stock()?;
+        self.check_rebuild(|items| items.iter_mut().for_each(GashaponItem::restore))?;
+        let seed = self.prizes.seed.unwrap_or_else(time_seed);
+        Ok(self.refill(seed))
     }
 
-    pub fn restore_items(&mut self) -> &mut Self {
+    /// Put the machine back as it was first built: every item restocked,
+    /// the box refilled with the same seed and the history cleared. A
+    /// machine built without a seed is shuffled with the one it was first
+    /// shuffled with. A live machine must be paused first.
+    pub fn reset_to_original(&mut self) -> Result<&mut Self, BuildError> {
+        if let state @ (MachineState::Live | MachineState::Closed) = self.state {
+            return Err(MachineError::NotEditable(state).into());
+        }
+        self.ensure_own_stock()?;
+        self.check_rebuild(|items| items.iter_mut().for_each(GashaponItem::restore))?;
+        let seed = self
+            .prizes
+            .seed
+            .or(self.first_seed)
+            .unwrap_or_else(time_seed);
+        self.erase_history();
+        Ok(self.refill(seed))
+    }
+
+    /// Restock every item and shuffle the box with `seed`, back in draft.
+    fn refill(&mut self, seed: usize) -> &mut Self {
         for item in self.items.values_mut() {
             item.restore();
         }
-        self.build()
+        self.record(MachineEvent::Restored);
+        self.rebuild_with_seed(seed);
+        self.set_state(MachineState::Draft);
+        self.observers.emit(Event::Restored);
+        self
     }
 
-    pub fn with_seed(&mut self, seed: usize) -> &mut Self {
-        self.prizes.with_seed(seed);
+    /// Reshuffle the capsules still in stock with a new seed. Only for
+    /// draft machines, since the history points into the board.
+    pub fn rebuild_with_new_seed(&mut self, seed: usize) -> Result<&mut Self, BuildError> {
+        self.ensure_editable()?;
+        self.check_rebuild(|_| ())?;
+        Ok(self.set_seed(seed).rebuild())
+    }
+
+    /// Remove every item and empty the box and the history. The unit price,
+    /// mode, seed and subscribers are kept, and the machine goes back to
+    /// [`MachineState::Draft`].
+    pub fn clear(&mut self) -> Result<&mut Self, MachineError> {
+        self.ensure_not_closed()?;
+        self.ensure_own_stock()?;
+        self.items.clear();
+        self.record(MachineEvent::Cleared);
+        self.erase_history();
+        self.set_state(MachineState::Draft);
+        Ok(self.rebuild())
+    }
+
+    fn ensure_not_closed(&self) -> Result<(), MachineError> {
+        match self.state {
+            MachineState::Closed => Err(MachineError::NotEditable(MachineState::Closed)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Call `callback` with every [`Event`] the machine emits from now on.
+    pub fn subscribe<F>(&mut self, callback: F) -> SubscriptionId
+    where
+        F: Fn(&Event) + Send + Sync + 'static,
+    {
+        self.observers.subscribe(Arc::new(callback))
+    }
+
+    /// Returns `false` if there was no such subscription.
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        self.observers.unsubscribe(id)
+    }
+
+    pub fn clear_subscribers(&mut self) -> &mut Self {
+        self.observers.clear();
         self
     }
 
-    pub fn remove_item(&mut self, id: PrizeId) -> &mut Self {
-        self.items.remove(&id);
+    pub fn with_seed(&mut self, seed: usize) -> Result<&mut Self, MachineError> {
+        self.ensure_editable()?;
+        Ok(self.set_seed(seed))
+    }
+
+    fn set_seed(&mut self, seed: usize) -> &mut Self {
+        self.prizes.with_seed(seed);
+        self.record(MachineEvent::SeedSet(seed));
         self
     }
 
-    pub fn update_item_quantity(&mut self, id: PrizeId, quantity: u64) -> &mut Self {
+    pub fn with_unit_price(&mut self, unit_price: u64) -> Result<&mut Self, MachineError> {
+        self.ensure_editable()?;
+        self.unit_price = unit_price;
+        self.record(MachineEvent::UnitPriceSet(unit_price));
+        Ok(self)
+    }
+
+    pub fn with_mode(&mut self, mode: DrawMode) -> Result<&mut Self, MachineError> {
+        self.ensure_editable()?;
+        self.mode = mode;
+        self.record(MachineEvent::ModeSet(mode));
+        Ok(self)
+    }
+
+    /// Remove an item and rebuild the box. Fails without changing anything
+    /// if the machine left would be invalid.
+    pub fn remove_item(&mut self, id: PrizeId) -> Result<&mut Self, BuildError> {
+        self.ensure_editable()?;
+        self.ensure_own_stock()?;
+        self.check_rebuild(|items| items.retain(|item| item.get_prize_id() != id))?;
+        match self.items.remove(&id) {
+            Some(_) => {
+                self.record(MachineEvent::ItemRemoved(id));
+                Ok(self.rebuild())
+            }
+            None => Ok(self),
+        }
+    }
+
+    /// Set an item's stock and rebuild the box. Fails without changing
+    /// anything if the machine would be invalid.
+    pub fn update_item_quantity(
+        &mut self,
+        id: PrizeId,
+        quantity: u64,
+    ) -> Result<&mut Self, BuildError> {
+        self.ensure_editable()?;
+        self.ensure_own_stock()?;
+        self.check_rebuild(|items| {
+            for item in items.iter_mut().filter(|item| item.get_prize_id() == id) {
+                item.quantity = quantity;
+            }
+        })?;
         match self.items.get_mut(&id) {
             Some(item) => {
+                let from = item.quantity;
                 item.quantity = quantity;
-                self.build()
+                self.record(MachineEvent::QuantityUpdated {
+                    id: id.clone(),
+                    quantity,
+                });
+                self.observers.emit(Event::QuantityChanged {
+                    id,
+                    from,
+                    to: quantity,
+                });
+                Ok(self.rebuild())
             }
-            _ => self,
+            _ => Ok(self),
         }
     }
 
-    pub fn build(&mut self) -> &mut Self {
-        self.prizes
-            .with_items(self.items.iter().map(|(_, item)| item).collect());
-        self.prizes.build();
+    /// Fill and shuffle the box of a draft machine without validating it.
+    /// Outside the crate, machines are built with [`GashaponBuilder`] or
+    /// [`Gashapon::try_build`].
+    pub(crate) fn build(&mut self) -> Result<&mut Self, MachineError> {
+        self.ensure_editable()?;
+        Ok(self.rebuild())
+    }
+
+    /// Fill and shuffle the box whatever the machine's state.
+    /// A machine without a seed is shuffled with a new one every time.
+    fn rebuild(&mut self) -> &mut Self {
+        let seed = self.prizes.get_seed();
+        self.rebuild_with_seed(seed)
+    }
+
+    fn rebuild_with_seed(&mut self, seed: usize) -> &mut Self {
+        self.build_with_seed(seed);
+        self.record(MachineEvent::Built { seed });
         self
     }
 
+    fn build_with_seed(&mut self, seed: usize) {
+        self.shuffled_with = Some(seed);
+        self.first_seed.get_or_insert(seed);
+        // Sort so the same seed always yields the same shuffle
+        let mut items = self.items.values().collect::<Vec<_>>();
+        items.sort_by(|a, b| a.prize.name.cmp(&b.prize.name));
+        self.prizes.with_items(items);
+        self.prizes.build_with_seed(seed);
+        self.opened_at = None;
+        self.observers.emit(Event::Built);
+    }
+
+    /// Whether there is nothing left to draw.
+    pub fn is_empty(&self) -> bool {
+        let finite = match self.mode {
+            DrawMode::Box => self.prizes.idx_box.is_empty(),
+            DrawMode::Weighted => self.prizes.items.is_empty(),
+        };
+        finite && self.prizes.unlimited.is_empty()
+    }
+
+    /// Whether any item is [unlimited](GashaponItem::with_unlimited).
+    pub fn has_unlimited_items(&self) -> bool {
+        self.items.values().any(GashaponItem::is_unlimited)
+    }
+
+    /// Number of capsules left in the box.
+    pub fn remaining(&self) -> usize {
+        self.prizes.idx_box.len()
+    }
+
+    /// Draw one prize.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the machine is empty, paused or closed. See
+    /// [`Gashapon::try_draw`].
     pub fn draw(&mut self) -> PrizeItem {
-        let prize = self.prizes.draw();
-        let item = self.items.get_mut(&prize.get_id()).unwrap();
-        item.quantity -= 1;
+        self.try_draw().unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Draw one prize. The first draw from a draft machine puts it live.
+    /// The machine's [`ResetPolicy`]s are checked after the draw.
+    pub fn try_draw(&mut self) -> Result<PrizeItem, MachineError> {
+        self.draw_preferring(None::<fn(&PrizeItem) -> bool>)
+    }
+
+    /// Draw among the prizes `prefer` accepts while any are left, and among
+    /// every prize otherwise.
+    pub(crate) fn draw_preferring<F>(
+        &mut self,
+        prefer: Option<F>,
+    ) -> Result<PrizeItem, MachineError>
+    where
+        F: Fn(&PrizeItem) -> bool,
+    {
+        self.check_drawable()?;
+        if self.mode == DrawMode::Box
+            && self.prizes.available() == 0
+            && self.prizes.unlimited.is_empty()
+        {
+            return Err(MachineError::AllReserved);
+        }
+        self.start_draw()?;
+        if let Some(prize) = self.roll_unlimited(prefer.as_ref()) {
+            self.record_draw(None, &prize);
+            self.observers.emit(Event::Drawn(prize.clone()));
+            self.apply_reset_policies(&prize);
+            return Ok(prize);
+        }
+        let prize = match self.mode {
+            DrawMode::Box => {
+                let (slot, prize) = match prefer.and_then(|f| self.prizes.draw_slot_where(f)) {
+                    Some(drawn) => drawn,
+                    None => self.prizes.draw_slot(),
+                };
+                self.take_from_box(slot, prize)
+            }
+            DrawMode::Weighted => {
+                let prize = match prefer.and_then(|f| self.prizes.draw_weighted_where(f)) {
+                    Some(prize) => prize,
+                    None => self.prizes.draw_weighted(),
+                };
+                self.record_draw(None, &prize);
+                self.observers.emit(Event::Drawn(prize.clone()));
+                prize
+            }
+        };
+        self.apply_reset_policies(&prize);
+        Ok(prize)
+    }
+
+    /// Roll whether the next draw is one of the unlimited prizes, among the
+    /// ones `prefer` accepts while any accepted prize is left.
+    fn roll_unlimited<F>(&mut self, prefer: Option<&F>) -> Option<PrizeItem>
+    where
+        F: Fn(&PrizeItem) -> bool,
+    {
+        if self.prizes.unlimited.is_empty() {
+            return None;
+        }
+        if let Some(accept) = prefer {
+            let finite = match self.mode {
+                DrawMode::Box => self
+                    .prizes
+                    .idx_box
+                    .iter()
+                    .filter(|slot| !self.prizes.reserved.contains(slot))
+                    .filter(|slot| {
+                        self.prizes
+                            .get_item_by_index(self.prizes.randomized_items[**slot])
+                            .is_some_and(accept)
+                    })
+                    .count(),
+                DrawMode::Weighted => self.prizes.items.iter().filter(|p| accept(p)).count(),
+            };
+            if finite > 0 || self.prizes.unlimited_weight(accept) > 0 {
+                return self.prizes.roll_unlimited(finite, accept);
+            }
+        }
+        let finite = match self.mode {
+            DrawMode::Box => self.prizes.available(),
+            DrawMode::Weighted => self.prizes.items.len(),
+        };
+        self.prizes.roll_unlimited(finite, |_| true)
+    }
+
+    /// Pick a board slot to open, like a ticket on a kuji board.
+    /// Reserved slots can be opened too. The draw is recorded as
+    /// [`DrawCause::Picked`].
+    pub fn draw_at(&mut self, slot: usize) -> Result<PrizeItem, MachineError> {
+        if self.mode != DrawMode::Box {
+            return Err(MachineError::NoBoard(self.mode));
+        }
+        self.check_drawable()?;
+        self.check_slot(slot)?;
+        self.start_draw()?;
+        let prize = self.prizes.draw_at(slot).expect("Slot was checked");
+        self.next_cause = DrawCause::Picked;
+        let prize = self.take_from_box(slot, prize);
+        self.apply_reset_policies(&prize);
+        Ok(prize)
+    }
+
+    fn draw_from_box(&mut self) -> PrizeItem {
+        let (slot, prize) = self.prizes.draw_slot();
+        self.take_from_box(slot, prize)
+    }
+
+    /// Update stock, history and subscribers for a capsule taken from `slot`.
+    fn take_from_box(&mut self, slot: usize, prize: PrizeItem) -> PrizeItem {
+        let item = self
+            .items
+            .get_mut(&prize.get_id())
+            .expect("Drawn prize is not in the machine");
+        item.quantity = item
+            .quantity
+            .checked_sub(1)
+            .expect("Drawn prize has no stock left");
+        let sold_out = item.quantity == 0;
+
+        self.record_draw(Some(slot), &prize);
+        self.observers.emit(Event::Drawn(prize.clone()));
+        if sold_out {
+            self.observers.emit(Event::ItemSoldOut(prize.get_id()));
+        }
+        if self.prizes.idx_box.is_empty() {
+            self.observers.emit(Event::PoolEmpty);
+        }
         prize
     }
 
+    fn record_draw(&mut self, slot: Option<usize>, prize: &PrizeItem) {
+        let sequence = self.history.len() as u64 + 1;
+        let mut record = DrawRecord::new(sequence, slot, prize.clone());
+        record.cause = std::mem::take(&mut self.next_cause);
+        self.opened_at.get_or_insert(record.timestamp);
+        let event = MachineEvent::Drawn {
+            slot,
+            prize: prize.get_id(),
+            cause: record.cause.clone(),
+            timestamp: record.timestamp,
+            rng: self.prizes.state,
+        };
+        self.history.push(record);
+        self.record(event);
+    }
+
+    /// Every draw since the machine was created or its history was cleared.
+    pub fn history(&self) -> &[DrawRecord] {
+        &self.history
+    }
+
+    pub fn clear_history(&mut self) -> Result<&mut Self, MachineError> {
+        self.ensure_editable()?;
+        Ok(self.erase_history())
+    }
+
+    fn erase_history(&mut self) -> &mut Self {
+        self.history.clear();
+        self.resets.clear();
+        self.record(MachineEvent::HistoryCleared);
+        self
+    }
+
+    /// Draw up to `times` prizes, applying the machine's multi-pull
+    /// [`Guarantee`] to every complete batch.
     pub fn draw_with_times(&mut self, times: u64) -> Vec<PrizeItem> {
-        let prizes = self.prizes.draw_with_times(times);
-        for p in &prizes {
-            if let Some(p) = self.items.get_mut(&p.get_id()) {
-                p.quantity -= 1;
-            }
+        match self.guarantee.clone() {
+            Some(guarantee) => self.draw_guaranteed(times, &guarantee),
+            None => self.draws().take(times as usize).collect(),
         }
-        prizes
     }
 
+    /// Draw prizes lazily until the machine is empty.
+    pub fn draws(&mut self) -> Draws<'_> {
+        Draws::new(self)
+    }
+
+    /// Take every remaining capsule out of the box or weighted pool. Reset
+    /// policies are not checked while draining.
+    pub fn drain(&mut self) -> Drain<'_> {
+        Drain::new(self)
+    }
+
+    /// Odds of each item on the next draw. Unlimited items count with their
+    /// weight, which never goes down, so their odds rise as the box empties.
     pub fn calculate_draw_rate(&self) -> Vec<(GashaponItem, f64)> {
         let mut draw_rate = Vec::new();
         // Calculate the total quantity of items
-        let total_quantity = self
-            .items
-            .iter()
-            .map(|(_, item)| item.quantity)
-            .sum::<u64>() as f64;
+        let total_quantity = self.total_quantity() as f64;
         // Calculate the draw rate for each item
         // and store it in the HashMap
         for (_, item) in self.items.clone().into_iter() {
-            let rate = ((item.quantity as f64) / total_quantity).max(0.0);
+            let rate = if total_quantity > 0.0 {
+                (item.quantity as f64) / total_quantity
+            } else {
+                0.0
+            };
             draw_rate.push((item, rate));
         }
         draw_rate
     }
+
+    /// Check that the item stock and the prize pool agree with each other.
+    ///
+    /// Only meaningful once the machine has been built. Meant for tests and
+    /// debug assertions; returns a description of the first broken invariant.
+    pub fn check_invariants(&self) -> Result<(), String> {
+        let prizes = &self.prizes;
+        let mut in_pool = HashMap::<PrizeId, u64>::new();
+        match self.mode {
+            DrawMode::Box => {
+                let mut seen = vec![false; prizes.items.len()];
+                for idx in prizes.randomized_items.iter().flatten() {
+                    let prize = prizes
+                        .items
+                        .get(*idx)
+                        .ok_or_else(|| format!("slot points past the pool at {idx}"))?;
+                    if std::mem::replace(&mut seen[*idx], true) {
+                        return Err(format!("capsule {idx} is in more than one slot"));
+                    }
+                    *in_pool.entry(prize.get_id()).or_default() += 1;
+                }
+
+                let mut open = vec![false; prizes.randomized_items.len()];
+                for slot in &prizes.idx_box {
+                    match prizes.randomized_items.get(*slot) {
+                        Some(Some(_)) if !std::mem::replace(&mut open[*slot], true) => {}
+                        Some(Some(_)) => return Err(format!("slot {slot} is queued twice")),
+                        _ => return Err(format!("slot {slot} is queued but empty")),
+                    }
+                }
+                if let Some(slot) = prizes
+                    .reserved
+                    .iter()
+                    .find(|slot| !open.get(**slot).copied().unwrap_or_default())
+                {
+                    return Err(format!("slot {slot} is reserved but not queued"));
+                }
+                let undrawn = prizes.randomized_items.iter().flatten().count();
+                if undrawn != prizes.idx_box.len() {
+                    return Err(format!(
+                        "{undrawn} undrawn capsules but {} queued slots",
+                        prizes.idx_box.len()
+                    ));
+                }
+            }
+            DrawMode::Weighted => {
+                for prize in &prizes.items {
+                    *in_pool.entry(prize.get_id()).or_default() += 1;
+                }
+            }
+        }
+
+        for (id, count) in &in_pool {
+            if !self.items.contains_key(id) {
+                return Err(format!("prize {id} is in the pool but not in the machine"));
+            }
+            if *count == 0 {
+                return Err(format!("prize {id} has an empty pool entry"));
+            }
+        }
+        for (id, item) in &self.items {
+            if item.is_unlimited() {
+                let weight = prizes
+                    .unlimited
+                    .iter()
+                    .find(|(prize, _)| prize.get_id() == *id)
+                    .map_or(0, |(_, weight)| *weight);
+                if weight != item.quantity || in_pool.contains_key(id) {
+                    return Err(format!(
+                        "unlimited \"{}\" has weight {} but {weight} in the pool",
+                        item.prize.name, item.quantity
+                    ));
+                }
+                continue;
+            }
+            let count = in_pool.get(id).copied().unwrap_or_default();
+            if count != item.quantity {
+                return Err(format!(
+                    "\"{}\" has {} in stock but {count} in the pool",
+                    item.prize.name, item.quantity
+                ));
+            }
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashSet;
 
     #[test]
     fn test_prizes() {
@@ -331,73 +1191,463 @@ mod tests {
         assert!(drawn_item.name == "Item1" || drawn_item.name == "Item2");
     }
 
+    #[test]
+    fn test_prizes_draw_reaches_every_candidate() {
+        let mut prizes = Prizes::new();
+        prizes.with_items(vec![
+            &GashaponItem::new(PrizeItem::new("Item1")).with_quantity(1),
+            &GashaponItem::new(PrizeItem::new("Item2")).with_quantity(1),
+            &GashaponItem::new(PrizeItem::new("Item3")).with_quantity(1),
+        ]);
+        prizes.with_seed(12345);
+        prizes.build();
+
+        let picks = (0..100).map(|_| prizes.pick(3)).collect::<HashSet<_>>();
+        assert_eq!(picks, HashSet::from([0, 1, 2]));
+
+        let mut drawn = HashSet::new();
+        for seed in 0..50 {
+            prizes.build_with_seed(seed);
+            let accept = |prize: &PrizeItem| prize.name != "Item1";
+            drawn.insert(prizes.draw_slot_where(accept).unwrap().1.name);
+        }
+        assert_eq!(
+            drawn,
+            HashSet::from(["Item2".to_string(), "Item3".to_string()])
+        );
+    }
+
     #[test]
     fn test_gashapon() {
         let mut gashapon = Gashapon::default();
         gashapon
             .add_item(GashaponItem::new(PrizeItem::new("Item1")).with_quantity(2))
+            .unwrap()
             .add_item(GashaponItem::new(PrizeItem::new("Item2")).with_quantity(3))
+            .unwrap()
             .with_seed(12345)
-            .build();
+            .unwrap()
+            .build()
+            .unwrap();
 
         let drawn_item = gashapon.draw();
         assert!(drawn_item.name == "Item1" || drawn_item.name == "Item2");
+        assert_eq!(gashapon.check_invariants(), Ok(()));
     }
 
     #[test]
     fn test_gashapon_calculate_draw_rate() {
         let mut gashapon = Gashapon::default();
-        gashapon.add_items(vec![
-            GashaponItem::new(PrizeItem::new("Item1")).with_quantity(2),
-            GashaponItem::new(PrizeItem::new("Item2")).with_quantity(3),
-            GashaponItem::new(PrizeItem::new("Item3")).with_quantity(5),
-        ]);
-        gashapon.with_seed(12345).build();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("Item1")).with_quantity(2),
+                GashaponItem::new(PrizeItem::new("Item2")).with_quantity(3),
+                GashaponItem::new(PrizeItem::new("Item3")).with_quantity(5),
+            ])
+            .unwrap();
+        gashapon.with_seed(12345).unwrap().build().unwrap();
 
         let draw_rate = gashapon.calculate_draw_rate();
         assert_eq!(draw_rate.len(), 3);
         assert!(
             draw_rate
                 .iter()
-                .map(|x| x.clone())
-                .any(|(item, rate)| item.prize.name == "Item1" && rate > 0.0)
+                .any(|(item, rate)| item.prize.name == "Item1" && *rate > 0.0)
         );
     }
 
+    #[test]
+    fn test_gashapon_calculate_draw_rate_empty_box() {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("Item1")).with_quantity(0),
+                GashaponItem::new(PrizeItem::new("Item2")).with_quantity(0),
+            ])
+            .unwrap();
+        gashapon.with_seed(12345).unwrap().build().unwrap();
+
+        let draw_rate = gashapon.calculate_draw_rate();
+        assert!(draw_rate.iter().all(|(_, rate)| *rate == 0.0));
+    }
+
     #[test]
     fn test_gashapon_restore_items() {
         let mut gashapon = Gashapon::default();
-        gashapon.add_items(vec![
-            GashaponItem::new(PrizeItem::new("Item1")).with_quantity(1),
-            GashaponItem::new(PrizeItem::new("Item2")).with_quantity(1),
-        ]);
-        gashapon.with_seed(12345).build();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("Item1")).with_quantity(1),
+                GashaponItem::new(PrizeItem::new("Item2")).with_quantity(1),
+            ])
+            .unwrap();
+        gashapon.with_seed(12345).unwrap().build().unwrap();
 
         let drawn_item = gashapon.draw();
         assert!(drawn_item.name == "Item1" || drawn_item.name == "Item2");
 
-        gashapon.restore_items();
+        gashapon.restore_items().unwrap();
+        assert_eq!(gashapon.check_invariants(), Ok(()));
         let draw_rate = gashapon.calculate_draw_rate();
         assert!(
             draw_rate
                 .iter()
-                .map(|x| x.clone())
-                .any(|(item, rate)| item.quantity > 0 && rate > 0.0)
+                .any(|(item, rate)| item.quantity > 0 && *rate > 0.0)
         );
     }
 
+    #[test]
+    fn test_gashapon_weighted_mode() {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("Item1")).with_quantity(1),
+                GashaponItem::new(PrizeItem::new("Item2")).with_quantity(3),
+            ])
+            .unwrap();
+        gashapon
+            .with_mode(DrawMode::Weighted)
+            .unwrap()
+            .with_seed(12345)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let drawn_items = gashapon.draw_with_times(10);
+        assert_eq!(drawn_items.len(), 10);
+        assert!(drawn_items.iter().any(|item| item.name == "Item2"));
+        assert_eq!(gashapon.items[&PrizeId::new("Item2")].quantity, 3);
+        assert_eq!(gashapon.prizes.idx_box.len(), 4);
+        assert_eq!(gashapon.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn test_gashapon_weighted_mode_large_pool() {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("A")).with_quantity(40000),
+                GashaponItem::new(PrizeItem::new("Z")).with_quantity(40000),
+            ])
+            .unwrap()
+            .with_mode(DrawMode::Weighted)
+            .unwrap()
+            .with_seed(12345)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let z = gashapon
+            .draw_with_times(2000)
+            .iter()
+            .filter(|prize| prize.name == "Z")
+            .count();
+        assert!((800..1200).contains(&z), "{z} Z of 2000 draws");
+    }
+
     #[test]
     fn test_gashapon_draw_with_times() {
         let mut gashapon = Gashapon::default();
-        gashapon.add_items(vec![
-            GashaponItem::new(PrizeItem::new("Item1")).with_quantity(2),
-            GashaponItem::new(PrizeItem::new("Item2")).with_quantity(3),
-        ]);
-        gashapon.with_seed(12345).build();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("Item1")).with_quantity(2),
+                GashaponItem::new(PrizeItem::new("Item2")).with_quantity(3),
+            ])
+            .unwrap();
+        gashapon.with_seed(12345).unwrap().build().unwrap();
 
         let drawn_items = gashapon.draw_with_times(2);
         assert_eq!(drawn_items.len(), 2);
         assert!(drawn_items.iter().any(|item| item.name == "Item1"));
         assert!(drawn_items.iter().any(|item| item.name == "Item2"));
+        assert_eq!(gashapon.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn test_gashapon_check_invariants() {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("Item1")).with_quantity(2),
+                GashaponItem::new(PrizeItem::new("Item2")).with_quantity(3),
+            ])
+            .unwrap();
+        gashapon.with_seed(12345).unwrap().build().unwrap();
+        gashapon.remove_item(PrizeId::new("Item1")).unwrap();
+        assert_eq!(gashapon.check_invariants(), Ok(()));
+        gashapon
+            .update_item_quantity(PrizeId::new("Item2"), 7)
+            .unwrap();
+        gashapon.draw_with_times(2);
+        assert_eq!(gashapon.check_invariants(), Ok(()));
+
+        gashapon
+            .items
+            .get_mut(&PrizeId::new("Item2"))
+            .unwrap()
+            .quantity = 0;
+        assert_eq!(
+            gashapon.check_invariants(),
+            Err("\"Item2\" has 0 in stock but 5 in the pool".to_string())
+        );
+
+        gashapon.restore_items().unwrap();
+        gashapon.prizes.idx_box.clear();
+        assert!(gashapon.check_invariants().is_err());
+    }
+
+    #[test]
+    fn test_gashapon_history() {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("Item1")).with_quantity(2),
+                GashaponItem::new(PrizeItem::new("Item2")).with_quantity(3),
+            ])
+            .unwrap();
+        gashapon.with_seed(12345).unwrap().build().unwrap();
+
+        let drawn_items = gashapon.draw_with_times(3);
+        let history = gashapon.history();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[2].sequence, 3);
+        assert_eq!(history[0].prize.name, drawn_items[0].name);
+        let slot = history[1].slot.unwrap();
+        assert!(gashapon.prizes.randomized_items[slot].is_none());
+
+        assert_eq!(
+            gashapon.clear_history().unwrap_err(),
+            MachineError::NotEditable(MachineState::Live)
+        );
+        gashapon.restore_items().unwrap().clear_history().unwrap();
+        assert!(gashapon.history().is_empty());
+    }
+
+    #[test]
+    fn test_gashapon_reset_without_seed() {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_item(GashaponItem::new(PrizeItem::new("Item1")).with_quantity(20))
+            .unwrap()
+            .build()
+            .unwrap();
+        let seed = gashapon.shuffled_with;
+        assert!(seed.is_some());
+        assert_eq!(gashapon.prizes().seed, None);
+        let board = gashapon.prizes().idx_box().to_vec();
+
+        // Restoring shuffles anew, resetting puts the first shuffle back
+        gashapon.draw_with_times(5);
+        gashapon.restore_items().unwrap();
+        assert_ne!(gashapon.shuffled_with, seed);
+        gashapon.restore_items().unwrap();
+        assert_ne!(gashapon.shuffled_with, seed);
+        gashapon.reset_to_original().unwrap();
+        assert_eq!(gashapon.shuffled_with, seed);
+        assert_eq!(gashapon.prizes().seed, None);
+        assert_eq!(gashapon.prizes().idx_box(), board);
+    }
+
+    #[test]
+    fn test_gashapon_clear_and_reset() {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("Item1")).with_quantity(2),
+                GashaponItem::new(PrizeItem::new("Item2")).with_quantity(3),
+            ])
+            .unwrap();
+        gashapon.with_seed(12345).unwrap().build().unwrap();
+        let board = gashapon.prizes().randomized_items().to_vec();
+
+        gashapon.draw_with_times(2);
+        assert_eq!(
+            gashapon.reset_to_original().unwrap_err(),
+            BuildError::Machine(MachineError::NotEditable(MachineState::Live))
+        );
+        gashapon.pause().unwrap().reset_to_original().unwrap();
+        assert_eq!(gashapon.remaining(), 5);
+        assert!(gashapon.history().is_empty());
+        assert_eq!(gashapon.prizes().randomized_items(), board);
+        assert_eq!(gashapon.check_invariants(), Ok(()));
+
+        gashapon.draw();
+        assert!(gashapon.rebuild_with_new_seed(54321).is_err());
+        gashapon.pause().unwrap();
+        assert!(gashapon.rebuild_with_new_seed(54321).is_err());
+        assert_eq!(
+            gashapon.with_unit_price(100).unwrap_err(),
+            MachineError::NotEditable(MachineState::Paused)
+        );
+        gashapon
+            .restore_items()
+            .unwrap()
+            .rebuild_with_new_seed(54321)
+            .unwrap();
+        assert_eq!(gashapon.remaining(), 5);
+        assert_ne!(gashapon.prizes().randomized_items(), board);
+        assert_eq!(gashapon.history().len(), 1);
+        assert_eq!(gashapon.check_invariants(), Ok(()));
+        gashapon.clear().unwrap().with_unit_price(100).unwrap();
+        assert!(gashapon.items().is_empty());
+        assert!(gashapon.prizes().items().is_empty());
+        assert!(gashapon.prizes().randomized_items().is_empty());
+        assert!(gashapon.prizes().idx_box().is_empty());
+        assert!(gashapon.history().is_empty());
+        assert!(gashapon.is_empty());
+        assert_eq!(gashapon.unit_price(), 100);
+        assert_eq!(gashapon.check_invariants(), Ok(()));
+
+        gashapon
+            .add_item(GashaponItem::new(PrizeItem::new("Item3")).with_quantity(1))
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(gashapon.draw().name, "Item3");
+    }
+
+    #[test]
+    fn test_gashapon_draw_at() {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("Item1")).with_quantity(2),
+                GashaponItem::new(PrizeItem::new("Item2")).with_quantity(3),
+            ])
+            .unwrap()
+            .with_seed(12345)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let expected = gashapon.prizes().get_randomized_items()[3]
+            .cloned()
+            .unwrap();
+        let prize = gashapon.draw_at(3).unwrap();
+        assert_eq!(prize.name, expected.name);
+        assert_eq!(gashapon.remaining(), 4);
+        assert!(!gashapon.prizes().idx_box().contains(&3));
+        assert_eq!(gashapon.history()[0].slot, Some(3));
+        assert_eq!(gashapon.state(), MachineState::Live);
+        assert_eq!(gashapon.check_invariants(), Ok(()));
+
+        assert_eq!(
+            gashapon.draw_at(3).unwrap_err(),
+            MachineError::SlotOpened(3)
+        );
+        assert_eq!(
+            gashapon.draw_at(5).unwrap_err(),
+            MachineError::SlotOutOfRange { slot: 5, slots: 5 }
+        );
+        gashapon.pause().unwrap();
+        assert_eq!(
+            gashapon.draw_at(0).unwrap_err(),
+            MachineError::NotDrawable(MachineState::Paused)
+        );
+
+        assert_eq!(
+            gashapon.with_mode(DrawMode::Weighted).unwrap_err(),
+            MachineError::NotEditable(MachineState::Paused)
+        );
+
+        gashapon
+            .restore_items()
+            .unwrap()
+            .with_mode(DrawMode::Weighted)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(
+            gashapon.draw_at(0).unwrap_err().to_string(),
+            "a weighted machine has no board"
+        );
+    }
+
+    #[test]
+    fn test_gashapon_unlimited_items() {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("Rare")).with_quantity(2),
+                GashaponItem::new(PrizeItem::new("Sticker"))
+                    .with_quantity(6)
+                    .with_unlimited(true),
+            ])
+            .unwrap()
+            .with_seed(12345)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_eq!(gashapon.prizes().quantity(), 2);
+        assert_eq!(gashapon.remaining(), 2);
+        let rate = |gashapon: &Gashapon, name: &str| {
+            gashapon
+                .calculate_draw_rate()
+                .into_iter()
+                .find(|(item, _)| item.prize.name == name)
+                .unwrap()
+                .1
+        };
+        assert_eq!(rate(&gashapon, "Sticker"), 0.75);
+
+        let drawn = gashapon
+            .draws()
+            .take(200)
+            .filter(|prize| prize.name == "Rare")
+            .count();
+        assert_eq!(drawn, 2);
+        assert_eq!(gashapon.remaining(), 0);
+        assert!(!gashapon.is_empty());
+        assert_eq!(
+            gashapon.get_item(PrizeId::new("Sticker")).unwrap().quantity,
+            6
+        );
+        assert_eq!(rate(&gashapon, "Sticker"), 1.0);
+        assert_eq!(gashapon.check_invariants(), Ok(()));
+        assert!(
+            gashapon
+                .history()
+                .iter()
+                .all(|record| { (record.prize.name == "Sticker") == record.slot.is_none() })
+        );
+        assert!(gashapon.collect_all().is_none());
+    }
+
+    #[test]
+    fn test_gashapon_unlimited_items_large_pool() {
+        for mode in [DrawMode::Box, DrawMode::Weighted] {
+            let mut gashapon = Gashapon::default();
+            gashapon
+                .add_items(vec![
+                    GashaponItem::new(PrizeItem::new("Rare")).with_quantity(40000),
+                    GashaponItem::new(PrizeItem::new("Sticker"))
+                        .with_quantity(40000)
+                        .with_unlimited(true),
+                ])
+                .unwrap()
+                .with_mode(mode)
+                .unwrap()
+                .with_seed(12345)
+                .unwrap()
+                .build()
+                .unwrap();
+
+            let advertised = gashapon
+                .calculate_draw_rate()
+                .into_iter()
+                .find(|(item, _)| item.prize.name == "Sticker")
+                .unwrap()
+                .1;
+            let stickers = gashapon
+                .draw_with_times(2000)
+                .iter()
+                .filter(|prize| prize.name == "Sticker")
+                .count();
+            let drawn = stickers as f64 / 2000.0;
+            assert!(
+                (drawn - advertised).abs() < 0.05,
+                "{mode}: drew {drawn} stickers, advertised {advertised}"
+            );
+        }
     }
 }
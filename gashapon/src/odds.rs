@@ -0,0 +1,412 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::utils::gcd;
+use crate::{DrawMode, Gashapon, GashaponItem, GetPrizeItemId, PrizeId};
+
+/// An exact, always reduced, non-negative fraction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Ratio {
+    numer: u128,
+    denom: u128,
+}
+
+impl Ratio {
+    /// Create a reduced ratio. Panics if `denom` is zero.
+    pub fn new(numer: u128, denom: u128) -> Self {
+        if denom == 0 {
+            panic!("Ratio denominator must not be zero!");
+        }
+        let divisor = gcd(numer, denom).max(1);
+        Self {
+            numer: numer / divisor,
+            denom: denom / divisor,
+        }
+    }
+
+    pub fn zero() -> Self {
+        Self { numer: 0, denom: 1 }
+    }
+
+    pub fn one() -> Self {
+        Self { numer: 1, denom: 1 }
+    }
+
+    pub fn numer(&self) -> u128 {
+        self.numer
+    }
+
+    pub fn denom(&self) -> u128 {
+        self.denom
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.numer == 0
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.numer as f64 / self.denom as f64
+    }
+
+    /// `1 - self`, or `None` if the ratio is greater than one.
+    pub fn complement(&self) -> Option<Self> {
+        let numer = self.denom.checked_sub(self.numer)?;
+        Some(Self::new(numer, self.denom))
+    }
+
+    /// Multiply two ratios, cross-reducing first to keep the terms small.
+    /// Returns `None` if the result does not fit in 128 bits.
+    pub fn checked_mul(&self, other: &Self) -> Option<Self> {
+        let g1 = gcd(self.numer, other.denom).max(1);
+        let g2 = gcd(other.numer, self.denom).max(1);
+        let numer = (self.numer / g1).checked_mul(other.numer / g2)?;
+        let denom = (self.denom / g2).checked_mul(other.denom / g1)?;
+        Some(Self::new(numer, denom))
+    }
+
+    /// Raise the ratio to the power `exp`. Returns `None` if the result does
+    /// not fit in 128 bits.
+    pub fn checked_pow(&self, mut exp: u64) -> Option<Self> {
+        let mut base = *self;
+        let mut result = Self::one();
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result = result.checked_mul(&base)?;
+            }
+            exp >>= 1;
+            if exp > 0 {
+                base = base.checked_mul(&base)?;
+            }
+        }
+        Some(result)
+    }
+
+    /// Add two ratios. Returns `None` if the result does not fit in 128 bits.
+    pub fn checked_add(&self, other: &Self) -> Option<Self> {
+        let g = gcd(self.denom, other.denom).max(1);
+        let denom = (self.denom / g).checked_mul(other.denom)?;
+        let numer = self
+            .numer
+            .checked_mul(other.denom / g)?
+            .checked_add(other.numer.checked_mul(self.denom / g)?)?;
+        Some(Self::new(numer, denom))
+    }
+}
+
+impl Ord for Ratio {
+    /// Compares by continued-fraction expansion, so it never overflows.
+    fn cmp(&self, other: &Self) -> Ordering {
+        let (mut a, mut b, mut c, mut d) = (self.numer, self.denom, other.numer, other.denom);
+        let mut reversed = false;
+        loop {
+            let order = (a / b).cmp(&(c / d));
+            let (r1, r2) = (a % b, c % d);
+            let order = match order {
+                Ordering::Equal if r1 == 0 || r2 == 0 => r1.cmp(&r2),
+                Ordering::Equal => {
+                    // r1/b vs r2/d is the reverse of b/r1 vs d/r2
+                    (a, b, c, d) = (b, r1, d, r2);
+                    reversed = !reversed;
+                    continue;
+                }
+                order => order,
+            };
+            return if reversed { order.reverse() } else { order };
+        }
+    }
+}
+
+impl PartialOrd for Ratio {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Default for Ratio {
+    fn default() -> Self {
+        Self::zero()
+    }
+}
+
+impl fmt::Display for Ratio {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.numer, self.denom)
+    }
+}
+
+impl Gashapon {
+    /// Exact odds of drawing each item on the next draw.
+    ///
+    /// Every rate is zero when the box is empty.
+    pub fn calculate_exact_draw_rate(&self) -> Vec<(GashaponItem, Ratio)> {
+        let total = self.total_quantity();
+        self.items
+            .values()
+            .map(|item| {
+                let rate = if total == 0 {
+                    Ratio::zero()
+                } else {
+                    Ratio::new(item.quantity as u128, total)
+                };
+                (item.clone(), rate)
+            })
+            .collect()
+    }
+
+    /// Exact odds of drawing any item of each tier on the next draw, sorted by tier.
+    /// Items without a tier are grouped under `None`.
+    pub fn calculate_tier_draw_rate(&self) -> Vec<(Option<String>, Ratio)> {
+        let total = self.total_quantity();
+        let mut tiers = BTreeMap::<Option<String>, u128>::new();
+        for item in self.items.values() {
+            *tiers.entry(item.prize.tier.clone()).or_default() += item.quantity as u128;
+        }
+        tiers
+            .into_iter()
+            .map(|(tier, quantity)| {
+                let rate = if total == 0 {
+                    Ratio::zero()
+                } else {
+                    Ratio::new(quantity, total)
+                };
+                (tier, rate)
+            })
+            .collect()
+    }
+
+    /// Probability of getting at least one of the given item within `draws`
+    /// random draws: hypergeometric for a box, `1 - (1 - K/N)^n` for a
+    /// weighted machine, which draws with replacement. Reserved slots are
+    /// never drawn at random, so they are left out.
+    ///
+    /// Returns `None` if the item is not in the machine, the machine has
+    /// unlimited items or the exact value does not fit in 128 bits.
+    pub fn probability_within_draws(&self, id: PrizeId, draws: u64) -> Option<Ratio> {
+        let (wanted, total) = self.drawable(&id)?;
+        if wanted == 0 || draws == 0 {
+            return Some(Ratio::zero());
+        }
+        if self.mode == DrawMode::Weighted {
+            let miss = Ratio::new(total - wanted, total);
+            return miss.checked_pow(draws)?.complement();
+        }
+        let draws = (draws as u128).min(total);
+        if total - wanted < draws {
+            return Some(Ratio::one());
+        }
+
+        // P(none) = C(N - n, K) / C(N, K) = prod_{i < K} (N - n - i) / (N - i)
+        let mut none = Ratio::one();
+        for i in 0..wanted {
+            none = none.checked_mul(&Ratio::new(total - draws - i, total - i))?;
+        }
+        none.complement()
+    }
+
+    /// Expected number of random draws until the first copy of the given
+    /// item: `(N + 1) / (K + 1)` for a box, `N / K` for a weighted machine.
+    /// Reserved slots are left out.
+    ///
+    /// Returns `None` if the item is not in the machine, none can be drawn
+    /// or the machine has unlimited items.
+    pub fn expected_draws_until(&self, id: PrizeId) -> Option<Ratio> {
+        let (wanted, total) = self.drawable(&id)?;
+        if wanted == 0 {
+            return None;
+        }
+        match self.mode {
+            DrawMode::Box => Some(Ratio::new(total + 1, wanted + 1)),
+            DrawMode::Weighted => Some(Ratio::new(total, wanted)),
+        }
+    }
+
+    /// Copies of the item random draws can reach, and how many capsules
+    /// they are drawn from, or `None` for an unknown item or a machine with
+    /// unlimited items.
+    fn drawable(&self, id: &PrizeId) -> Option<(u128, u128)> {
+        if self.has_unlimited_items() {
+            return None;
+        }
+        let wanted = self.items.get(id)?.quantity as u128;
+        let total = self.total_quantity();
+        if self.mode == DrawMode::Weighted {
+            return Some((wanted, total));
+        }
+        let prizes = &self.prizes;
+        let reserved = prizes
+            .reserved
+            .iter()
+            .filter_map(|slot| prizes.get_item_by_index(prizes.randomized_items[*slot]))
+            .collect::<Vec<_>>();
+        let reserved_wanted = reserved
+            .iter()
+            .filter(|prize| prize.get_id() == *id)
+            .count() as u128;
+        Some((
+            wanted.saturating_sub(reserved_wanted),
+            total.saturating_sub(reserved.len() as u128),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PrizeItem;
+
+    fn gashapon() -> Gashapon {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("Item1").with_tier("S")).with_quantity(1),
+                GashaponItem::new(PrizeItem::new("Item2").with_tier("A")).with_quantity(3),
+                GashaponItem::new(PrizeItem::new("Item3").with_tier("A")).with_quantity(6),
+            ])
+            .unwrap();
+        gashapon.with_seed(12345).unwrap().build().unwrap();
+        gashapon
+    }
+
+    #[test]
+    fn test_ratio() {
+        let ratio = Ratio::new(6, 8);
+        assert_eq!((ratio.numer(), ratio.denom()), (3, 4));
+        assert_eq!(ratio.to_string(), "3/4");
+        assert_eq!(ratio.complement(), Some(Ratio::new(1, 4)));
+        assert_eq!(ratio.checked_mul(&Ratio::new(2, 3)), Some(Ratio::new(1, 2)));
+        assert_eq!(
+            ratio.checked_add(&Ratio::new(1, 6)),
+            Some(Ratio::new(11, 12))
+        );
+        assert_eq!(ratio.checked_pow(3), Some(Ratio::new(27, 64)));
+        assert_eq!(ratio.checked_pow(0), Some(Ratio::one()));
+        assert_eq!(Ratio::new(1, 3).checked_pow(100), None);
+    }
+
+    #[test]
+    fn test_ratio_ordering() {
+        assert!(Ratio::new(1, 3) < Ratio::new(1, 2));
+        assert!(Ratio::new(7, 4) > Ratio::new(5, 3));
+        assert!(Ratio::new(0, 1) < Ratio::new(1, u128::MAX));
+        assert!(Ratio::new(u128::MAX - 1, u128::MAX) > Ratio::new(u128::MAX - 2, u128::MAX - 1));
+        assert_eq!(Ratio::new(2, 4).cmp(&Ratio::new(1, 2)), Ordering::Equal);
+        assert!(Ratio::new(5, 1) > Ratio::one());
+    }
+
+    #[test]
+    fn test_calculate_exact_draw_rate() {
+        let gashapon = gashapon();
+        let rates = gashapon.calculate_exact_draw_rate();
+        let item2 = rates
+            .iter()
+            .find(|(item, _)| item.prize.name == "Item2")
+            .unwrap();
+        assert_eq!(item2.1, Ratio::new(3, 10));
+
+        let tiers = gashapon.calculate_tier_draw_rate();
+        assert_eq!(
+            tiers,
+            vec![
+                (Some("A".to_string()), Ratio::new(9, 10)),
+                (Some("S".to_string()), Ratio::new(1, 10)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_calculate_exact_draw_rate_empty_box() {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_item(GashaponItem::new(PrizeItem::new("Item1")).with_quantity(0))
+            .unwrap()
+            .build()
+            .unwrap();
+        let rates = gashapon.calculate_exact_draw_rate();
+        assert!(rates.iter().all(|(_, rate)| rate.is_zero()));
+    }
+
+    #[test]
+    fn test_probability_within_draws() {
+        let gashapon = gashapon();
+        let id = PrizeId::new("Item1");
+        assert_eq!(
+            gashapon.probability_within_draws(id.clone(), 0),
+            Some(Ratio::zero())
+        );
+        assert_eq!(
+            gashapon.probability_within_draws(id.clone(), 4),
+            Some(Ratio::new(2, 5))
+        );
+        assert_eq!(
+            gashapon.probability_within_draws(id, 10),
+            Some(Ratio::one())
+        );
+
+        // 1 - C(7, 2) / C(10, 2) = 1 - 21 / 45
+        assert_eq!(
+            gashapon.probability_within_draws(PrizeId::new("Item2"), 2),
+            Some(Ratio::new(8, 15))
+        );
+        assert_eq!(
+            gashapon.probability_within_draws(PrizeId::new("Unknown"), 2),
+            None
+        );
+    }
+
+    #[test]
+    fn test_expected_draws_until() {
+        let gashapon = gashapon();
+        assert_eq!(
+            gashapon.expected_draws_until(PrizeId::new("Item1")),
+            Some(Ratio::new(11, 2))
+        );
+        assert_eq!(
+            gashapon.expected_draws_until(PrizeId::new("Item2")),
+            Some(Ratio::new(11, 4))
+        );
+        assert_eq!(gashapon.expected_draws_until(PrizeId::new("Unknown")), None);
+    }
+
+    #[test]
+    fn test_weighted_odds_draw_with_replacement() {
+        let mut gashapon = gashapon();
+        gashapon.with_mode(DrawMode::Weighted).unwrap();
+        let id = PrizeId::new("Item1");
+        // 1 - (9 / 10)^2
+        assert_eq!(
+            gashapon.probability_within_draws(id.clone(), 2),
+            Some(Ratio::new(19, 100))
+        );
+        assert!(gashapon.probability_within_draws(id.clone(), 10).unwrap() < Ratio::one());
+        assert_eq!(gashapon.expected_draws_until(id), Some(Ratio::new(10, 1)));
+    }
+
+    #[test]
+    fn test_odds_leave_out_reserved_slots() {
+        let mut gashapon = gashapon();
+        let slot_of = |gashapon: &Gashapon, name: &str| {
+            gashapon
+                .prizes()
+                .get_randomized_items()
+                .iter()
+                .position(|prize| prize.is_some_and(|prize| prize.name == name))
+                .unwrap()
+        };
+        let slot = slot_of(&gashapon, "Item3");
+        gashapon.reserve(slot).unwrap();
+        let id = PrizeId::new("Item1");
+        assert_eq!(
+            gashapon.expected_draws_until(id.clone()),
+            Some(Ratio::new(5, 1))
+        );
+
+        let slot = slot_of(&gashapon, "Item1");
+        gashapon.reserve(slot).unwrap();
+        assert_eq!(gashapon.expected_draws_until(id.clone()), None);
+        assert_eq!(
+            gashapon.probability_within_draws(id, 8),
+            Some(Ratio::zero())
+        );
+    }
+}
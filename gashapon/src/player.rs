@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+
+use crate::{ExchangeError, GetPrizeItemId, PrizeId, PrizeItem};
+
+/// A player, the prizes they have collected so far and their points.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Player {
+    pub name: String,
+    inventory: HashMap<PrizeId, u64>,
+    points: u64,
+    ledger: Vec<PointsEntry>,
+}
+
+/// One change to a player's points balance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum PointsEntry {
+    /// Duplicate copies of a prize exchanged for points.
+    Converted {
+        prize: PrizeId,
+        copies: u64,
+        points: u64,
+    },
+    /// Points spent on a chosen prize.
+    Redeemed { prize: PrizeId, points: u64 },
+}
+
+impl Player {
+    pub fn new<T>(name: T) -> Self
+    where
+        T: ToString,
+    {
+        Self {
+            name: name.to_string(),
+            inventory: HashMap::new(),
+            points: 0,
+            ledger: Vec::new(),
+        }
+    }
+
+    pub fn add_prize(&mut self, prize: &PrizeItem) -> &mut Self {
+        *self.inventory.entry(prize.get_id()).or_default() += 1;
+        self
+    }
+
+    pub fn add_prizes(&mut self, prizes: &[PrizeItem]) -> &mut Self {
+        for prize in prizes {
+            self.add_prize(prize);
+        }
+        self
+    }
+
+    /// Number of copies of the given prize the player owns.
+    pub fn count(&self, id: PrizeId) -> u64 {
+        self.inventory.get(&id).copied().unwrap_or_default()
+    }
+
+    pub fn owns(&self, id: PrizeId) -> bool {
+        self.count(id) > 0
+    }
+
+    pub fn inventory(&self) -> &HashMap<PrizeId, u64> {
+        &self.inventory
+    }
+
+    /// Copies owned beyond the first, for every prize the player has more
+    /// than one of.
+    pub fn duplicates(&self) -> impl Iterator<Item = (PrizeId, u64)> + '_ {
+        self.inventory
+            .iter()
+            .filter(|(_, count)| **count > 1)
+            .map(|(id, count)| (id.clone(), count - 1))
+    }
+
+    pub fn points(&self) -> u64 {
+        self.points
+    }
+
+    /// Every change to the points balance, oldest first.
+    pub fn ledger(&self) -> &[PointsEntry] {
+        &self.ledger
+    }
+
+    /// Give up `copies` of a prize for `points`. Nothing changes if the
+    /// balance would overflow.
+    pub(crate) fn convert(
+        &mut self,
+        prize: PrizeId,
+        copies: u64,
+        points: u64,
+    ) -> Result<(), ExchangeError> {
+        let balance = self
+            .points
+            .checked_add(points)
+            .ok_or(ExchangeError::PointsOverflow)?;
+        let count = self
+            .inventory
+            .get_mut(&prize)
+            .expect("Converted prize is owned");
+        *count = count
+            .checked_sub(copies)
+            .expect("Converted more copies than owned");
+        if *count == 0 {
+            self.inventory.remove(&prize);
+        }
+        self.points = balance;
+        self.ledger.push(PointsEntry::Converted {
+            prize,
+            copies,
+            points,
+        });
+        Ok(())
+    }
+
+    /// Spend `points` on `prize` and add it to the inventory.
+    pub(crate) fn redeem(&mut self, prize: &PrizeItem, points: u64) {
+        self.points = self
+            .points
+            .checked_sub(points)
+            .expect("Redeemed with too few points");
+        self.add_prize(prize);
+        self.ledger.push(PointsEntry::Redeemed {
+            prize: prize.get_id(),
+            points,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_player_inventory() {
+        let mut player = Player::new("Alice");
+        player.add_prizes(&[
+            PrizeItem::new("Item1"),
+            PrizeItem::new("Item1"),
+            PrizeItem::new("Item2"),
+        ]);
+
+        assert_eq!(player.count(PrizeId::new("Item1")), 2);
+        assert!(player.owns(PrizeId::new("Item2")));
+        assert!(!player.owns(PrizeId::new("Item3")));
+        assert_eq!(player.inventory().len(), 2);
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::{Gashapon, MachineState, time_seed};
+
+/// Stable id of a machine in a [`Registry`]. A registry never reuses the id
+/// of a machine it deleted, but one [loaded](Registry::load) from a store
+/// only knows the ids that were saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+pub struct MachineId(u64);
+
+impl MachineId {
+    pub fn get_id(&self) -> u64 {
+        self.0
+    }
+}
+
+impl From<u64> for MachineId {
+    fn from(id: u64) -> Self {
+        Self(id)
+    }
+}
+
+impl fmt::Display for MachineId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// An operation the registry refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegistryError {
+    UnknownMachine(MachineId),
+    /// Another machine, archived or not, already has the name.
+    DuplicateName(String),
+    EmptyName,
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryError::UnknownMachine(id) => write!(f, "no machine with id {id}"),
+            RegistryError::DuplicateName(name) => {
+                write!(f, "a machine named \"{name}\" already exists")
+            }
+            RegistryError::EmptyName => write!(f, "a machine needs a name"),
+        }
+    }
+}
+
+impl Error for RegistryError {}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Registered {
+    pub(crate) name: String,
+    pub(crate) archived: bool,
+    pub(crate) gashapon: Gashapon,
+}
+
+/// Every machine on a floor, by id and by name.
+///
+/// Archived machines are closed for good but kept, with their history, until
+/// they are deleted. Names are unique across all of them.
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    pub(crate) machines: BTreeMap<MachineId, Registered>,
+    pub(crate) next_id: u64,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a machine under a new id.
+    pub fn create<T>(&mut self, name: T, gashapon: Gashapon) -> Result<MachineId, RegistryError>
+    where
+        T: ToString,
+    {
+        let name = self.check_name(name.to_string(), None)?;
+        let id = MachineId(self.next_id);
+        self.next_id += 1;
+        self.machines.insert(
+            id,
+            Registered {
+                name,
+                archived: false,
+                gashapon,
+            },
+        );
+        Ok(id)
+    }
+
+    /// Register a fresh copy of a machine: the same prize table and
+    /// settings, fully stocked, in draft and with no history or subscribers.
+    /// The copy is shuffled with a new seed, so its capsules come out in
+    /// another order, and it keeps its own stock even if the original
+    /// shares an arcade's. A journaled machine's copy starts a journal of
+    /// its own.
+    pub fn clone_machine<T>(&mut self, id: MachineId, name: T) -> Result<MachineId, RegistryError>
+    where
+        T: ToString,
+    {
+        let copy = self.copy_of(id, false)?;
+        self.create(name, copy)
+    }
+
+    /// Like [`Registry::clone_machine`], but the copy keeps the original's
+    /// seed and so the same capsule order.
+    pub fn clone_machine_with_same_seed<T>(
+        &mut self,
+        id: MachineId,
+        name: T,
+    ) -> Result<MachineId, RegistryError>
+    where
+        T: ToString,
+    {
+        let copy = self.copy_of(id, true)?;
+        self.create(name, copy)
+    }
+
+    fn copy_of(&self, id: MachineId, same_seed: bool) -> Result<Gashapon, RegistryError> {
+        let mut copy = self.entry(id)?.gashapon.clone();
+        copy.clear_subscribers().erase_history();
+        copy.shared_stock = false;
+        for item in copy.items.values_mut() {
+            item.restore();
+        }
+        copy.state = MachineState::Draft;
+        copy.first_seed = None;
+        if !same_seed && copy.prizes.seed.is_some() {
+            copy.set_seed(time_seed());
+        }
+        copy.rebuild();
+        if let Some(journal) = copy.take_journal() {
+            copy.with_journal(journal.snapshot_every());
+        }
+        Ok(copy)
+    }
+
+    /// Close a machine for good and move it out of [`Registry::active`].
+    pub fn archive(&mut self, id: MachineId) -> Result<(), RegistryError> {
+        let entry = self.entry_mut(id)?;
+        if entry.gashapon.state() != MachineState::Closed {
+            entry
+                .gashapon
+                .close()
+                .expect("An open machine can be closed");
+        }
+        entry.archived = true;
+        Ok(())
+    }
+
+    pub fn delete(&mut self, id: MachineId) -> Result<Gashapon, RegistryError> {
+        self.machines
+            .remove(&id)
+            .map(|entry| entry.gashapon)
+            .ok_or(RegistryError::UnknownMachine(id))
+    }
+
+    pub fn rename<T>(&mut self, id: MachineId, name: T) -> Result<(), RegistryError>
+    where
+        T: ToString,
+    {
+        self.entry(id)?;
+        let name = self.check_name(name.to_string(), Some(id))?;
+        self.entry_mut(id)?.name = name;
+        Ok(())
+    }
+
+    pub fn get(&self, id: MachineId) -> Option<&Gashapon> {
+        self.machines.get(&id).map(|entry| &entry.gashapon)
+    }
+
+    pub fn get_mut(&mut self, id: MachineId) -> Option<&mut Gashapon> {
+        self.machines.get_mut(&id).map(|entry| &mut entry.gashapon)
+    }
+
+    /// Id of the machine with the given name.
+    pub fn find(&self, name: &str) -> Option<MachineId> {
+        self.machines
+            .iter()
+            .find(|(_, entry)| entry.name == name)
+            .map(|(id, _)| *id)
+    }
+
+    pub fn name(&self, id: MachineId) -> Option<&str> {
+        self.machines.get(&id).map(|entry| entry.name.as_str())
+    }
+
+    pub fn is_archived(&self, id: MachineId) -> bool {
+        self.machines.get(&id).is_some_and(|entry| entry.archived)
+    }
+
+    /// Every machine, archived or not, in id order.
+    pub fn machines(&self) -> impl Iterator<Item = (MachineId, &str, &Gashapon)> {
+        self.machines
+            .iter()
+            .map(|(id, entry)| (*id, entry.name.as_str(), &entry.gashapon))
+    }
+
+    /// Machines that are not archived, in id order.
+    pub fn active(&self) -> impl Iterator<Item = (MachineId, &str, &Gashapon)> {
+        self.machines
+            .iter()
+            .filter(|(_, entry)| !entry.archived)
+            .map(|(id, entry)| (*id, entry.name.as_str(), &entry.gashapon))
+    }
+
+    pub fn len(&self) -> usize {
+        self.machines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.machines.is_empty()
+    }
+
+    fn entry(&self, id: MachineId) -> Result<&Registered, RegistryError> {
+        self.machines
+            .get(&id)
+            .ok_or(RegistryError::UnknownMachine(id))
+    }
+
+    fn entry_mut(&mut self, id: MachineId) -> Result<&mut Registered, RegistryError> {
+        self.machines
+            .get_mut(&id)
+            .ok_or(RegistryError::UnknownMachine(id))
+    }
+
+    /// Trim `name` and check that no machine but `except` has it.
+    fn check_name(&self, name: String, except: Option<MachineId>) -> Result<String, RegistryError> {
+        let name = name.trim().to_string();
+        if name.is_empty() {
+            return Err(RegistryError::EmptyName);
+        }
+        match self.find(&name) {
+            Some(id) if Some(id) != except => Err(RegistryError::DuplicateName(name)),
+            _ => Ok(name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GashaponItem, PrizeItem};
+
+    fn gashapon() -> Gashapon {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("Item1")).with_quantity(2),
+                GashaponItem::new(PrizeItem::new("Item2")).with_quantity(3),
+            ])
+            .unwrap()
+            .with_seed(12345)
+            .unwrap()
+            .with_unit_price(100)
+            .unwrap()
+            .build()
+            .unwrap();
+        gashapon
+    }
+
+    #[test]
+    fn test_create_and_lookup() {
+        let mut registry = Registry::new();
+        let north = registry.create("North", gashapon()).unwrap();
+        let south = registry.create(" South ", gashapon()).unwrap();
+        assert_ne!(north, south);
+        assert_eq!(registry.find("South"), Some(south));
+        assert_eq!(registry.name(north), Some("North"));
+        assert_eq!(
+            registry.create("North", gashapon()).unwrap_err(),
+            RegistryError::DuplicateName("North".to_string())
+        );
+        assert_eq!(
+            registry.rename(south, "  ").unwrap_err(),
+            RegistryError::EmptyName
+        );
+
+        registry.get_mut(north).unwrap().draw();
+        registry.rename(north, "East").unwrap();
+        assert_eq!(registry.find("North"), None);
+        assert_eq!(registry.get(north).unwrap().remaining(), 4);
+        assert_eq!(registry.len(), 2);
+    }
+
+    #[test]
+    fn test_clone_archive_delete() {
+        let mut registry = Registry::new();
+        let original = registry.create("Original", gashapon()).unwrap();
+        registry.get_mut(original).unwrap().draw_with_times(2);
+
+        let copy = registry.clone_machine(original, "Copy").unwrap();
+        let gashapon = registry.get(copy).unwrap();
+        assert_eq!(gashapon.remaining(), 5);
+        assert_eq!(gashapon.unit_price(), 100);
+        assert!(gashapon.history().is_empty());
+        assert!(gashapon.is_editable());
+
+        registry.archive(original).unwrap();
+        assert!(registry.is_archived(original));
+        assert_eq!(
+            registry.get(original).unwrap().state(),
+            MachineState::Closed
+        );
+        let active = registry.active().map(|(id, _, _)| id).collect::<Vec<_>>();
+        assert_eq!(active, vec![copy]);
+        assert_eq!(registry.get(original).unwrap().history().len(), 2);
+
+        assert_eq!(registry.delete(original).unwrap().history().len(), 2);
+        assert_eq!(
+            registry.delete(original).unwrap_err(),
+            RegistryError::UnknownMachine(original)
+        );
+        let third = registry.create("Original", Gashapon::default()).unwrap();
+        assert!(third > copy);
+    }
+
+    #[test]
+    fn test_clone_reseeds_and_owns_its_stock() {
+        let mut registry = Registry::new();
+        let original = registry.create("Original", gashapon()).unwrap();
+        registry.entry_mut(original).unwrap().gashapon.shared_stock = true;
+
+        let copy = registry.clone_machine(original, "Copy").unwrap();
+        let same = registry
+            .clone_machine_with_same_seed(original, "Same")
+            .unwrap();
+        let prizes = |id| &registry.get(id).unwrap().prizes;
+        assert_ne!(prizes(copy).seed, prizes(original).seed);
+        assert_eq!(prizes(same).seed, Some(12345));
+        assert_eq!(prizes(same).idx_box, prizes(original).idx_box);
+        assert!(!registry.get(copy).unwrap().shared_stock);
+        assert!(!registry.get(same).unwrap().shared_stock);
+    }
+}
@@ -0,0 +1,221 @@
+use std::time::Duration;
+
+use crate::history::now;
+use crate::utils::rng;
+use crate::{
+    DrawMode, Event, Gashapon, GetPrizeItemId, MachineError, MachineEvent, PrizeId, PrizeItem,
+    ResetRecord,
+};
+
+/// When a box refills itself, as box-gacha games do once the jackpot is
+/// pulled or the box runs low.
+///
+/// Policies are checked after every draw. A reset restocks every item and
+/// reshuffles the box with a new seed derived from the machine's seed, or
+/// from the previous reset's, so a seeded machine stays reproducible. The
+/// machine's own seed is left as configured, and it stays live. Machines
+/// stocked by an [`Arcade`](crate::Arcade) never reset, since a refill
+/// would add stock the arcade does not hold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum ResetPolicy {
+    /// Reset once this prize has been drawn.
+    OnPrize(PrizeId),
+    /// Reset once fewer than this many capsules are left in the box.
+    RemainingBelow(usize),
+    /// Reset once this long has passed since the first draw from the box.
+    After(Duration),
+}
+
+impl Gashapon {
+    /// Add a policy. When several apply after a draw, the first one added
+    /// is recorded.
+    pub fn with_reset_policy(&mut self, policy: ResetPolicy) -> Result<&mut Self, MachineError> {
+        self.ensure_editable()?;
+        self.reset_policies.push(policy.clone());
+        self.record(MachineEvent::ResetPolicyAdded(policy));
+        Ok(self)
+    }
+
+    pub fn clear_reset_policies(&mut self) -> Result<&mut Self, MachineError> {
+        self.ensure_editable()?;
+        self.reset_policies.clear();
+        self.record(MachineEvent::ResetPoliciesCleared);
+        Ok(self)
+    }
+
+    pub fn reset_policies(&self) -> &[ResetPolicy] {
+        &self.reset_policies
+    }
+
+    /// Every automatic reset since the history was last cleared.
+    pub fn resets(&self) -> &[ResetRecord] {
+        &self.resets
+    }
+
+    /// Reset the box if a policy applies after `prize` was drawn.
+    pub(crate) fn apply_reset_policies(&mut self, prize: &PrizeItem) {
+        if self.shared_stock {
+            return;
+        }
+        let now = now();
+        let Some(policy) = self
+            .reset_policies
+            .iter()
+            .find(|policy| match policy {
+                ResetPolicy::OnPrize(id) => prize.get_id() == *id,
+                ResetPolicy::RemainingBelow(remaining) => {
+                    self.mode == DrawMode::Box && self.remaining() < *remaining
+                }
+                ResetPolicy::After(duration) => self
+                    .opened_at
+                    .is_some_and(|opened_at| now.saturating_sub(opened_at) >= duration.as_secs()),
+            })
+            .cloned()
+        else {
+            return;
+        };
+
+        // Chain from the previous reset so every refill is shuffled anew,
+        // leaving the machine's own seed as it was configured
+        let mut seed = self
+            .resets
+            .last()
+            .map(|reset| reset.seed)
+            .or(self.shuffled_with)
+            .unwrap_or_else(|| self.prizes.get_seed());
+        rng(&mut seed);
+        for item in self.items.values_mut() {
+            item.restore();
+        }
+        self.record(MachineEvent::Restored);
+        self.build_with_seed(seed);
+        self.record(MachineEvent::Built { seed });
+        let record = ResetRecord {
+            after_draw: self.history.len() as u64,
+            timestamp: now,
+            policy: policy.clone(),
+            seed,
+        };
+        self.resets.push(record.clone());
+        self.record(MachineEvent::BoxReset(record));
+        self.observers.emit(Event::BoxReset { policy, seed });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::{GashaponItem, MachineState};
+
+    fn gashapon() -> Gashapon {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("Jackpot")).with_quantity(1),
+                GashaponItem::new(PrizeItem::new("Item")).with_quantity(9),
+            ])
+            .unwrap()
+            .with_seed(12345)
+            .unwrap()
+            .build()
+            .unwrap();
+        gashapon
+    }
+
+    #[test]
+    fn test_reset_on_jackpot() {
+        let mut gashapon = gashapon();
+        gashapon
+            .with_reset_policy(ResetPolicy::OnPrize(PrizeId::new("Jackpot")))
+            .unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&events);
+        gashapon.subscribe(move |event| {
+            if let Event::BoxReset { seed, .. } = event {
+                log.lock().unwrap().push(*seed);
+            }
+        });
+
+        let draws = gashapon
+            .draws()
+            .take(30)
+            .position(|prize| prize.name == "Jackpot")
+            .unwrap();
+        assert_eq!(gashapon.remaining(), 10);
+        assert_eq!(gashapon.state(), MachineState::Live);
+        assert_eq!(gashapon.check_invariants(), Ok(()));
+
+        let resets = gashapon.resets();
+        assert_eq!(resets.len(), 1);
+        assert_eq!(resets[0].after_draw, draws as u64 + 1);
+        assert_eq!(
+            resets[0].policy,
+            ResetPolicy::OnPrize(PrizeId::new("Jackpot"))
+        );
+        assert_ne!(resets[0].seed, 12345);
+        assert_eq!(*events.lock().unwrap(), vec![resets[0].seed]);
+
+        assert!(gashapon.clear_history().is_err());
+        gashapon.restore_items().unwrap().clear_history().unwrap();
+        assert!(gashapon.resets().is_empty());
+    }
+
+    #[test]
+    fn test_reset_when_running_low() {
+        let mut gashapon = gashapon();
+        gashapon
+            .with_reset_policy(ResetPolicy::RemainingBelow(3))
+            .unwrap();
+
+        assert_eq!(gashapon.draw_with_times(8).len(), 8);
+        assert_eq!(gashapon.resets().len(), 1);
+        assert_eq!(gashapon.resets()[0].after_draw, 8);
+        assert_eq!(gashapon.remaining(), 10);
+
+        // Each refill is shuffled with a seed of its own, and the machine
+        // keeps the seed it was built with
+        assert_eq!(gashapon.draw_with_times(8).len(), 8);
+        let resets = gashapon.resets();
+        assert_eq!(resets.len(), 2);
+        assert_ne!(resets[0].seed, resets[1].seed);
+        assert_eq!(gashapon.prizes().seed, Some(12345));
+        let mut fresh = self::gashapon();
+        gashapon.restore_items().unwrap();
+        fresh.restore_items().unwrap();
+        assert_eq!(gashapon.prizes().idx_box(), fresh.prizes().idx_box());
+
+        // Draining never resets.
+        assert_eq!(gashapon.drain().len(), 10);
+        assert_eq!(gashapon.resets().len(), 2);
+    }
+
+    #[test]
+    fn test_reset_after_duration() {
+        let mut gashapon = gashapon();
+        gashapon
+            .with_reset_policy(ResetPolicy::After(Duration::from_secs(3600)))
+            .unwrap();
+        gashapon.draw();
+        assert!(gashapon.resets().is_empty());
+        assert_eq!(gashapon.remaining(), 9);
+
+        assert!(gashapon.clear_reset_policies().is_err());
+        gashapon
+            .restore_items()
+            .unwrap()
+            .clear_reset_policies()
+            .unwrap()
+            .with_reset_policy(ResetPolicy::After(Duration::ZERO))
+            .unwrap();
+        gashapon.draw();
+        assert_eq!(gashapon.resets().len(), 1);
+        assert_eq!(gashapon.remaining(), 10);
+    }
+}
@@ -0,0 +1,562 @@
+//! Draw rules: duplicate protection and multi-pull guarantees.
+
+use std::collections::BTreeSet;
+
+use crate::{
+    DrawCause, DrawMode, Gashapon, GashaponItem, GetPrizeItemId, MachineError, MachineEvent,
+    Player, PrizeItem, Ratio,
+};
+
+/// A promise that every batch of `pulls` draws made with
+/// [`Gashapon::draw_with_times`] holds at least one prize of the given tiers,
+/// such as "at least one A-tier or better in every 10-pull".
+///
+/// If none of the first `pulls - 1` draws of a batch qualifies, the last
+/// one is drawn from the qualifying prizes only. Draws beyond the last
+/// complete batch, and batches made when no qualifying prize is left in a
+/// box, carry no guarantee.
+///
+/// The guarantee changes the odds: [`Gashapon::calculate_draw_rate`] still
+/// describes any single draw outside a batch and every draw of a batch but
+/// the last, while the last draw favours the qualifying tiers. Disclose
+/// [`Gashapon::calculate_guaranteed_draw_rate`] for that draw, and
+/// [`Gashapon::calculate_batch_draw_rate`] for the average over a batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "GuaranteeFields"))]
+pub struct Guarantee {
+    pulls: u64,
+    tiers: BTreeSet<String>,
+}
+
+/// A guarantee as written, checked before it becomes a [`Guarantee`].
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct GuaranteeFields {
+    pulls: u64,
+    tiers: BTreeSet<String>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<GuaranteeFields> for Guarantee {
+    type Error = &'static str;
+
+    fn try_from(fields: GuaranteeFields) -> Result<Self, Self::Error> {
+        match fields.pulls {
+            0 => Err("a guarantee needs at least one pull"),
+            pulls => Ok(Self {
+                pulls,
+                tiers: fields.tiers,
+            }),
+        }
+    }
+}
+
+impl Guarantee {
+    /// # Panics
+    ///
+    /// Panics if `pulls` is 0.
+    pub fn new<I, T>(pulls: u64, tiers: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: ToString,
+    {
+        assert!(pulls > 0, "A guarantee needs at least one pull per batch");
+        Self {
+            pulls,
+            tiers: tiers.into_iter().map(|tier| tier.to_string()).collect(),
+        }
+    }
+
+    pub fn pulls(&self) -> u64 {
+        self.pulls
+    }
+
+    pub fn tiers(&self) -> &BTreeSet<String> {
+        &self.tiers
+    }
+
+    /// Whether `prize` satisfies the guarantee.
+    pub fn covers(&self, prize: &PrizeItem) -> bool {
+        prize
+            .tier
+            .as_ref()
+            .is_some_and(|tier| self.tiers.contains(tier))
+    }
+}
+
+impl Gashapon {
+    /// Promise "no duplicate until you own one of each": draws made with
+    /// [`Gashapon::draw_for`] skip prizes the player already owns while any
+    /// they do not own are left in the pool.
+    pub fn with_duplicate_protection(&mut self, enabled: bool) -> Result<&mut Self, MachineError> {
+        self.ensure_editable()?;
+        self.duplicate_protection = enabled;
+        self.record(MachineEvent::DuplicateProtectionSet(enabled));
+        Ok(self)
+    }
+
+    pub fn duplicate_protection(&self) -> bool {
+        self.duplicate_protection
+    }
+
+    /// Draw one prize for `player`, applying the machine's rules, and add it
+    /// to their inventory.
+    pub fn draw_for(&mut self, player: &mut Player) -> Result<PrizeItem, MachineError> {
+        let prize = match self.duplicate_protection {
+            true => self.draw_preferring(Some(|prize: &PrizeItem| !player.owns(prize.get_id()))),
+            false => self.try_draw(),
+        }?;
+        player.add_prize(&prize);
+        Ok(prize)
+    }
+
+    /// Odds of each item on `player`'s next [`Gashapon::draw_for`], taking
+    /// the machine's rules into account.
+    pub fn calculate_draw_rate_for(&self, player: &Player) -> Vec<(GashaponItem, f64)> {
+        let weights = self.effective_weights(player);
+        let total = weights.iter().map(|(_, weight)| *weight).sum::<u128>() as f64;
+        weights
+            .into_iter()
+            .map(|(item, weight)| {
+                let rate = if total > 0.0 {
+                    weight as f64 / total
+                } else {
+                    0.0
+                };
+                (item, rate)
+            })
+            .collect()
+    }
+
+    /// Exact odds of each item on `player`'s next [`Gashapon::draw_for`].
+    pub fn calculate_exact_draw_rate_for(&self, player: &Player) -> Vec<(GashaponItem, Ratio)> {
+        let weights = self.effective_weights(player);
+        let total = weights.iter().map(|(_, weight)| *weight).sum::<u128>();
+        weights
+            .into_iter()
+            .map(|(item, weight)| {
+                let rate = if total == 0 {
+                    Ratio::zero()
+                } else {
+                    Ratio::new(weight, total)
+                };
+                (item, rate)
+            })
+            .collect()
+    }
+
+    pub fn with_guarantee(&mut self, guarantee: Guarantee) -> Result<&mut Self, MachineError> {
+        self.ensure_editable()?;
+        self.guarantee = Some(guarantee.clone());
+        self.record(MachineEvent::GuaranteeSet(Some(guarantee)));
+        Ok(self)
+    }
+
+    pub fn remove_guarantee(&mut self) -> Result<&mut Self, MachineError> {
+        self.ensure_editable()?;
+        self.guarantee = None;
+        self.record(MachineEvent::GuaranteeSet(None));
+        Ok(self)
+    }
+
+    pub fn guarantee(&self) -> Option<&Guarantee> {
+        self.guarantee.as_ref()
+    }
+
+    pub(crate) fn draw_guaranteed(&mut self, times: u64, guarantee: &Guarantee) -> Vec<PrizeItem> {
+        let mut drawn = Vec::new();
+        let mut covered = false;
+        for pull in 1..=times {
+            let last = pull % guarantee.pulls == 0;
+            let prize = match last && !covered {
+                true => self.draw_covered(guarantee),
+                false => self.try_draw(),
+            };
+            let Ok(prize) = prize else {
+                break;
+            };
+            covered = !last && (covered || guarantee.covers(&prize));
+            drawn.push(prize);
+        }
+        drawn
+    }
+
+    /// Draw among the prizes `guarantee` covers while any are left, as on
+    /// the last pull of a batch that had none. The draw is recorded as
+    /// [`DrawCause::Guaranteed`] only if it gives a covered prize; with none
+    /// left it is an ordinary paid one.
+    pub(crate) fn draw_covered(
+        &mut self,
+        guarantee: &Guarantee,
+    ) -> Result<PrizeItem, MachineError> {
+        let covers = |prize: &PrizeItem| guarantee.covers(prize);
+        // A covered prize comes out exactly when one can be drawn
+        if self.can_draw_where(covers) {
+            self.next_cause = DrawCause::Guaranteed;
+        }
+        let prize = self.draw_preferring(Some(covers));
+        self.next_cause = DrawCause::Paid;
+        prize
+    }
+
+    /// Whether a random draw could give a prize that is accepted.
+    fn can_draw_where<F>(&self, accept: F) -> bool
+    where
+        F: Fn(&PrizeItem) -> bool,
+    {
+        let finite = match self.mode {
+            DrawMode::Box => self.prizes.has_slot_where(&accept),
+            DrawMode::Weighted => self.prizes.items.iter().any(&accept),
+        };
+        finite || self.prizes.unlimited_weight(&accept) > 0
+    }
+
+    /// Odds of each item on the last draw of a guaranteed batch, or `None`
+    /// without a guarantee or if the box holds less than a batch.
+    pub fn calculate_guaranteed_draw_rate(&self) -> Option<Vec<(GashaponItem, f64)>> {
+        let guarantee = self.guarantee.as_ref()?;
+        let pulls = guarantee.pulls as f64;
+        let total = self.total_quantity() as f64;
+        let covered = self
+            .items
+            .values()
+            .filter(|item| guarantee.covers(&item.prize))
+            .map(|item| item.quantity as u128)
+            .sum::<u128>() as f64;
+        if total == 0.0 || (self.mode == DrawMode::Box && total < pulls) {
+            return None;
+        }
+        if covered == 0.0 {
+            // Nothing can honour the guarantee, so the last draw is like any other
+            return Some(self.calculate_draw_rate());
+        }
+
+        // Chance that none of the first `pulls - 1` draws qualifies
+        let miss = match self.mode {
+            DrawMode::Box => (0..guarantee.pulls - 1)
+                .map(|i| ((total - covered - i as f64) / (total - i as f64)).max(0.0))
+                .product::<f64>(),
+            DrawMode::Weighted => (1.0 - covered / total).powf(pulls - 1.0),
+        };
+        let rates = self
+            .items
+            .values()
+            .map(|item| {
+                let quantity = item.quantity as f64;
+                let rate = quantity / total;
+                // Odds of the item being drawn last after a miss, were the draw not guaranteed
+                let missed_rate = match self.mode {
+                    DrawMode::Box if guarantee.covers(&item.prize) => {
+                        miss * quantity / (total - pulls + 1.0)
+                    }
+                    DrawMode::Box => {
+                        miss * quantity * (1.0 - (pulls - 1.0) / (total - covered))
+                            / (total - pulls + 1.0)
+                    }
+                    DrawMode::Weighted => miss * rate,
+                };
+                let rate = match guarantee.covers(&item.prize) {
+                    true => rate - missed_rate + miss * quantity / covered,
+                    false => rate - missed_rate,
+                };
+                (item.clone(), rate)
+            })
+            .collect();
+        Some(rates)
+    }
+
+    /// Average odds of each item per draw over a guaranteed batch, or `None`
+    /// without a guarantee or if the box holds less than a batch.
+    pub fn calculate_batch_draw_rate(&self) -> Option<Vec<(GashaponItem, f64)>> {
+        let pulls = self.guarantee.as_ref()?.pulls as f64;
+        let rates = self.calculate_draw_rate();
+        let last = self.calculate_guaranteed_draw_rate()?;
+        Some(
+            last.into_iter()
+                .map(|(item, last)| {
+                    let (_, rate) = rates
+                        .iter()
+                        .find(|(other, _)| other.get_prize_id() == item.get_prize_id())
+                        .expect("Rates cover every item");
+                    (item, ((pulls - 1.0) * rate + last) / pulls)
+                })
+                .collect(),
+        )
+    }
+
+    /// Each item's chance of being drawn for `player`, relative to the others.
+    fn effective_weights(&self, player: &Player) -> Vec<(GashaponItem, u128)> {
+        let unowned = |item: &GashaponItem| !player.owns(item.get_prize_id());
+        let protected = self.duplicate_protection
+            && self
+                .items
+                .values()
+                .any(|item| item.quantity > 0 && unowned(item));
+        self.items
+            .values()
+            .map(|item| {
+                let weight = match !protected || unowned(item) {
+                    true => item.quantity as u128,
+                    false => 0,
+                };
+                (item.clone(), weight)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::{DrawMode, PrizeId};
+
+    fn gashapon() -> Gashapon {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("Item1")).with_quantity(1),
+                GashaponItem::new(PrizeItem::new("Item2")).with_quantity(5),
+                GashaponItem::new(PrizeItem::new("Item3")).with_quantity(20),
+            ])
+            .unwrap()
+            .with_duplicate_protection(true)
+            .unwrap()
+            .with_seed(12345)
+            .unwrap()
+            .build()
+            .unwrap();
+        gashapon
+    }
+
+    #[test]
+    fn test_no_duplicate_until_set_is_complete() {
+        for mode in [DrawMode::Box, DrawMode::Weighted] {
+            for seed in 0..50 {
+                let mut gashapon = gashapon();
+                gashapon
+                    .with_mode(mode)
+                    .unwrap()
+                    .with_seed(seed)
+                    .unwrap()
+                    .build()
+                    .unwrap();
+                let mut player = Player::new("Alice");
+
+                let names = (0..3)
+                    .map(|_| gashapon.draw_for(&mut player).unwrap().name)
+                    .collect::<HashSet<_>>();
+                assert_eq!(names.len(), 3, "{mode} machine, seed {seed}");
+                assert_eq!(player.inventory().len(), 3);
+
+                gashapon.draw_for(&mut player).unwrap();
+                assert_eq!(player.inventory().values().sum::<u64>(), 4);
+            }
+        }
+    }
+
+    #[test]
+    fn test_draw_for_without_protection() {
+        let mut gashapon = gashapon();
+        gashapon.with_duplicate_protection(false).unwrap();
+        let mut player = Player::new("Alice");
+        player.add_prize(&PrizeItem::new("Item3"));
+
+        let rates = gashapon.calculate_draw_rate_for(&player);
+        for (item, rate) in gashapon.calculate_draw_rate() {
+            let (_, effective) = rates
+                .iter()
+                .find(|(other, _)| other.get_prize_id() == item.get_prize_id())
+                .unwrap();
+            assert_eq!(*effective, rate);
+        }
+
+        let drawn = gashapon.draw_with_times(26).len();
+        assert_eq!(drawn, 26);
+        assert!(gashapon.draw_for(&mut player).is_err());
+        assert_eq!(player.count(PrizeId::new("Item3")), 1);
+    }
+
+    #[test]
+    fn test_effective_rates() {
+        let gashapon = gashapon();
+        let mut player = Player::new("Alice");
+        player.add_prize(&PrizeItem::new("Item3"));
+
+        let rate = |player: &Player, name: &str| {
+            gashapon
+                .calculate_exact_draw_rate_for(player)
+                .into_iter()
+                .find(|(item, _)| item.prize.name == name)
+                .map(|(_, rate)| rate)
+                .unwrap()
+        };
+        assert_eq!(rate(&player, "Item1"), Ratio::new(1, 6));
+        assert_eq!(rate(&player, "Item2"), Ratio::new(5, 6));
+        assert_eq!(rate(&player, "Item3"), Ratio::zero());
+
+        player.add_prizes(&[PrizeItem::new("Item1"), PrizeItem::new("Item2")]);
+        assert_eq!(rate(&player, "Item3"), Ratio::new(20, 26));
+        let total = gashapon
+            .calculate_draw_rate_for(&player)
+            .iter()
+            .map(|(_, rate)| rate)
+            .sum::<f64>();
+        assert!((total - 1.0).abs() < 1e-9);
+    }
+
+    fn tiered(mode: DrawMode) -> Gashapon {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("S").with_tier("S")).with_quantity(1),
+                GashaponItem::new(PrizeItem::new("A").with_tier("A")).with_quantity(2),
+                GashaponItem::new(PrizeItem::new("B").with_tier("B")).with_quantity(30),
+                GashaponItem::new(PrizeItem::new("C").with_tier("C")).with_quantity(67),
+            ])
+            .unwrap()
+            .with_mode(mode)
+            .unwrap()
+            .with_guarantee(Guarantee::new(10, ["S", "A"]))
+            .unwrap()
+            .with_seed(12345)
+            .unwrap()
+            .build()
+            .unwrap();
+        gashapon
+    }
+
+    #[test]
+    fn test_every_batch_is_guaranteed() {
+        for mode in [DrawMode::Box, DrawMode::Weighted] {
+            for seed in 0..50 {
+                let mut gashapon = tiered(mode);
+                gashapon.with_seed(seed).unwrap().build().unwrap();
+                let guarantee = gashapon.guarantee().unwrap().clone();
+
+                let drawn = gashapon.draw_with_times(25);
+                assert_eq!(drawn.len(), 25);
+                // A box only holds three qualifying capsules
+                let mut qualifying_left = 3;
+                for batch in drawn.chunks(10).filter(|batch| batch.len() == 10) {
+                    let covered = batch.iter().filter(|prize| guarantee.covers(prize)).count();
+                    assert!(
+                        covered > 0 || (mode == DrawMode::Box && qualifying_left == 0),
+                        "{mode} machine, seed {seed}"
+                    );
+                    if mode == DrawMode::Box {
+                        qualifying_left -= covered;
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_guaranteed_cause_follows_the_prize() {
+        for mode in [DrawMode::Box, DrawMode::Weighted] {
+            let mut gashapon = Gashapon::default();
+            gashapon
+                .add_items(vec![
+                    GashaponItem::new(PrizeItem::new("S").with_tier("S")).with_quantity(1),
+                    GashaponItem::new(PrizeItem::new("A").with_tier("A")).with_quantity(19),
+                ])
+                .unwrap()
+                .with_mode(mode)
+                .unwrap()
+                .with_guarantee(Guarantee::new(2, ["S"]))
+                .unwrap()
+                .with_seed(12345)
+                .unwrap()
+                .build()
+                .unwrap();
+            gashapon.draw_with_times(20);
+
+            for record in gashapon.history() {
+                if record.cause == DrawCause::Guaranteed {
+                    assert_eq!(record.prize.name, "S", "{mode} machine");
+                }
+            }
+            // Once the only S is out of the box, last pulls are paid ones
+            if mode == DrawMode::Box {
+                let paid = gashapon
+                    .history()
+                    .iter()
+                    .filter(|record| record.cause == DrawCause::Paid)
+                    .count();
+                assert!(paid >= 19);
+            }
+        }
+    }
+
+    #[test]
+    fn test_guaranteed_draw_rate() {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("S").with_tier("S")).with_quantity(1),
+                GashaponItem::new(PrizeItem::new("C").with_tier("C")).with_quantity(3),
+            ])
+            .unwrap()
+            .with_guarantee(Guarantee::new(2, ["S"]))
+            .unwrap()
+            .build()
+            .unwrap();
+        let rate = |rates: Vec<(GashaponItem, f64)>, name: &str| {
+            rates
+                .into_iter()
+                .find(|(item, _)| item.prize.name == name)
+                .map(|(_, rate)| rate)
+                .unwrap()
+        };
+
+        // Box: the S capsule is drawn first a quarter of the time, and forced otherwise
+        let last = gashapon.calculate_guaranteed_draw_rate().unwrap();
+        assert!((rate(last, "S") - 0.75).abs() < 1e-9);
+        let batch = gashapon.calculate_batch_draw_rate().unwrap();
+        assert!((rate(batch, "S") - 0.5).abs() < 1e-9);
+
+        // Weighted: a miss on the first draw (3/4) forces S on the second
+        gashapon.with_mode(DrawMode::Weighted).unwrap();
+        let last = gashapon.calculate_guaranteed_draw_rate().unwrap();
+        assert!((rate(last.clone(), "S") - 13.0 / 16.0).abs() < 1e-9);
+        assert!((last.iter().map(|(_, rate)| rate).sum::<f64>() - 1.0).abs() < 1e-9);
+
+        gashapon.remove_guarantee().unwrap();
+        assert!(gashapon.calculate_batch_draw_rate().is_none());
+    }
+
+    #[test]
+    fn test_guaranteed_rates_sum_to_one() {
+        for mode in [DrawMode::Box, DrawMode::Weighted] {
+            let gashapon = tiered(mode);
+            let last = gashapon.calculate_guaranteed_draw_rate().unwrap();
+            let batch = gashapon.calculate_batch_draw_rate().unwrap();
+            assert!((last.iter().map(|(_, rate)| rate).sum::<f64>() - 1.0).abs() < 1e-9);
+            assert!((batch.iter().map(|(_, rate)| rate).sum::<f64>() - 1.0).abs() < 1e-9);
+            assert!(last.iter().all(|(_, rate)| *rate >= 0.0));
+
+            let rate = |rates: &[(GashaponItem, f64)]| {
+                rates
+                    .iter()
+                    .find(|(item, _)| item.prize.name == "S")
+                    .map(|(_, rate)| *rate)
+                    .unwrap()
+            };
+            assert!(rate(&last) > rate(&gashapon.calculate_draw_rate()));
+        }
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_guarantee_needs_a_pull() {
+        let guarantee: Guarantee =
+            serde_json::from_str(r#"{"pulls": 10, "tiers": ["S"]}"#).unwrap();
+        assert_eq!(guarantee, Guarantee::new(10, ["S"]));
+
+        let err = serde_json::from_str::<Guarantee>(r#"{"pulls": 0, "tiers": ["S"]}"#).unwrap_err();
+        assert!(err.to_string().contains("at least one pull"));
+    }
+}
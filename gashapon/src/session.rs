@@ -0,0 +1,391 @@
+//! Recorded sessions, re-executed to check that every draw came out as
+//! logged, for settling disputes with players.
+
+use std::error::Error;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::PrizeTable;
+#[cfg(feature = "json")]
+use crate::config::{ConfigError, JsonNames, json_error};
+use crate::{DrawCause, DrawRecord, Gashapon, GetPrizeItemId, MachineEvent};
+
+/// What it takes to re-execute a session: the prize table the machine was
+/// built from, the seed it was shuffled with and every draw, in order.
+///
+/// Draws are made again the way their [`DrawCause`] says they were made:
+/// picked slots are opened, redeemed prizes taken out, guaranteed pulls
+/// drawn among the covered prizes and every other draw rolled again.
+/// Reset policies are not part of a prize table, and draws made with
+/// duplicate protection depend on the player's inventory, so sessions that
+/// relied on either diverge where they did.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionLog {
+    pub seed: usize,
+    pub table: PrizeTable,
+    pub draws: Vec<DrawRecord>,
+}
+
+impl SessionLog {
+    pub fn new(table: PrizeTable, seed: usize, draws: Vec<DrawRecord>) -> Self {
+        Self { seed, table, draws }
+    }
+
+    /// The session of a machine since its history was last cleared.
+    ///
+    /// Fails if the machine was never built, and so has no seed to shuffle
+    /// it the same way again, or if its box was refilled by a reset policy.
+    /// A journaled machine also fails if its box was rebuilt, restored or
+    /// restocked after the first draw; without a journal that goes unseen
+    /// and the log diverges where it happened.
+    pub fn from_gashapon(gashapon: &Gashapon) -> Result<Self, SessionError> {
+        let seed = gashapon.shuffled_with.ok_or(SessionError::NoSeed)?;
+        if !gashapon.resets.is_empty() {
+            return Err(SessionError::Reset);
+        }
+        if let Some(journal) = gashapon.journal() {
+            let events = journal.events();
+            // Only the events since the history was last cleared matter
+            let start = events
+                .iter()
+                .rposition(|event| matches!(event, MachineEvent::HistoryCleared))
+                .map_or(0, |index| index + 1);
+            let rebuilt = events[start..]
+                .iter()
+                .skip_while(|event| !matches!(event, MachineEvent::Drawn { .. }))
+                .any(|event| {
+                    matches!(
+                        event,
+                        MachineEvent::Built { .. }
+                            | MachineEvent::Restored
+                            | MachineEvent::Restocked(_)
+                    )
+                });
+            if rebuilt {
+                return Err(SessionError::Rebuilt);
+            }
+        }
+        Ok(Self::new(
+            PrizeTable::from_gashapon(gashapon),
+            seed,
+            gashapon.history.clone(),
+        ))
+    }
+
+    /// Make every draw again on a machine built from the table, returning
+    /// how many were made or the first that came out differently.
+    pub fn replay(&self) -> Result<usize, Box<Divergence>> {
+        // A logged session never reset, so neither may its replay
+        let table = PrizeTable {
+            seed: Some(self.seed),
+            reset_policies: Vec::new(),
+            ..self.table.clone()
+        };
+        let Ok(mut gashapon) = table.to_gashapon() else {
+            // A table no machine can be built from replays no draw
+            return match self.draws.first() {
+                Some(logged) => Err(Box::new(Divergence {
+                    index: 0,
+                    logged: logged.clone(),
+                    replayed: None,
+                })),
+                None => Ok(0),
+            };
+        };
+        for (index, logged) in self.draws.iter().enumerate() {
+            let replayed = match gashapon.redraw(logged) {
+                true => gashapon.history.last().cloned(),
+                false => None,
+            };
+            if !replayed
+                .as_ref()
+                .is_some_and(|record| same_draw(record, logged))
+            {
+                return Err(Box::new(Divergence {
+                    index,
+                    logged: logged.clone(),
+                    replayed,
+                }));
+            }
+        }
+        Ok(self.draws.len())
+    }
+
+    #[cfg(feature = "json")]
+    pub fn from_json_str(source: &str) -> Result<Self, ConfigError> {
+        let log = serde_json::from_str::<Self>(source).map_err(json_error)?;
+        let names = serde_json::from_str::<JsonSession>(source)
+            .map(|session| session.table.locate(source))
+            .unwrap_or_default();
+        log.table.validate(&names)?;
+        Ok(log)
+    }
+
+    #[cfg(feature = "json")]
+    pub fn to_json_string(&self) -> Result<String, ConfigError> {
+        serde_json::to_string_pretty(self).map_err(ConfigError::new)
+    }
+}
+
+/// Why a machine's session cannot be logged for replay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionError {
+    /// The machine was never built, so it has no seed.
+    NoSeed,
+    /// A reset policy refilled the box during the session.
+    Reset,
+    /// The box was rebuilt, restored or restocked after the first draw.
+    Rebuilt,
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::NoSeed => write!(f, "the machine was never built, so it has no seed"),
+            SessionError::Reset => write!(f, "the box was reset during the session"),
+            SessionError::Rebuilt => write!(f, "the box was rebuilt after the first draw"),
+        }
+    }
+}
+
+impl Error for SessionError {}
+
+/// The item names of a logged table, borrowed from the log's source.
+#[cfg(feature = "json")]
+#[derive(Deserialize)]
+struct JsonSession<'a> {
+    #[serde(borrow)]
+    table: JsonNames<'a>,
+}
+
+/// Whether two draws took the same prize from the same slot for the same
+/// reason. Timestamps are left out.
+fn same_draw(a: &DrawRecord, b: &DrawRecord) -> bool {
+    a.slot == b.slot && a.prize.get_id() == b.prize.get_id() && a.cause == b.cause
+}
+
+/// The first draw of a [`SessionLog`] that came out differently.
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    /// Position of the draw in the log, from 0.
+    pub index: usize,
+    pub logged: DrawRecord,
+    /// The draw made instead, or `None` if the machine could not make it.
+    pub replayed: Option<DrawRecord>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let describe = |record: &DrawRecord| match record.slot {
+            Some(slot) => format!("{} from slot {slot} ({})", record.prize.name, record.cause),
+            None => format!("{} ({})", record.prize.name, record.cause),
+        };
+        write!(
+            f,
+            "draw {} was logged as {} but ",
+            self.index + 1,
+            describe(&self.logged)
+        )?;
+        match &self.replayed {
+            Some(record) => write!(f, "replayed as {}", describe(record)),
+            None => write!(f, "could not be replayed"),
+        }
+    }
+}
+
+impl Error for Divergence {}
+
+impl Gashapon {
+    /// Make a logged draw again the way it was made, returning whether the
+    /// machine could.
+    fn redraw(&mut self, logged: &DrawRecord) -> bool {
+        match &logged.cause {
+            DrawCause::Picked => logged.slot.is_some_and(|slot| self.draw_at(slot).is_ok()),
+            DrawCause::Redeemed(_) => {
+                let id = logged.prize.get_id();
+                self.redemption_cost(&id)
+                    .and_then(|cost| self.take_redeemed(id, cost))
+                    .is_ok()
+            }
+            DrawCause::Guaranteed => match self.guarantee.clone() {
+                Some(guarantee) => self.draw_covered(&guarantee).is_ok(),
+                None => false,
+            },
+            cause => self.draw_caused(cause.clone()).is_ok(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        DrawMode, ExchangeRates, GashaponItem, Guarantee, Player, PrizeEffect, PrizeId, PrizeItem,
+        ResetPolicy,
+    };
+
+    fn gashapon(mode: DrawMode) -> Gashapon {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("S").with_tier("S")).with_quantity(2),
+                GashaponItem::new(PrizeItem::new("A").with_tier("A")).with_quantity(6),
+                GashaponItem::new(
+                    PrizeItem::new("Ticket")
+                        .with_tier("B")
+                        .with_effect(PrizeEffect::FreeDraws(1)),
+                )
+                .with_quantity(8),
+                GashaponItem::new(PrizeItem::new("Bonus"))
+                    .with_quantity(1)
+                    .with_unlimited(true),
+            ])
+            .unwrap()
+            .with_seed(2025)
+            .unwrap()
+            .with_mode(mode)
+            .unwrap()
+            .with_guarantee(Guarantee::new(5, ["S"]))
+            .unwrap()
+            .with_exchange_rates(ExchangeRates::new().with_cost("A", 10))
+            .unwrap()
+            .build()
+            .unwrap();
+        gashapon
+    }
+
+    fn play(gashapon: &mut Gashapon) {
+        if gashapon.mode() == DrawMode::Box {
+            gashapon.draw_at(3).unwrap();
+        }
+        gashapon.draw_resolved().unwrap();
+        gashapon.draw_with_times(5);
+        let mut player = Player::new("Alice");
+        player.add_prize(&PrizeItem::new("Old"));
+        player.convert(PrizeId::new("Old"), 1, 10).unwrap();
+        gashapon.redeem(&mut player, PrizeId::new("A")).unwrap();
+        gashapon.draw_resolved().unwrap();
+    }
+
+    #[test]
+    fn test_replay_matches_every_draw() {
+        for mode in [DrawMode::Box, DrawMode::Weighted] {
+            let mut gashapon = gashapon(mode);
+            play(&mut gashapon);
+            let log = SessionLog::from_gashapon(&gashapon).unwrap();
+            assert_eq!(log.replay().unwrap(), gashapon.history().len());
+
+            let causes = log
+                .draws
+                .iter()
+                .map(|record| record.cause.clone())
+                .collect::<Vec<_>>();
+            assert!(causes.contains(&DrawCause::Redeemed(10)));
+            if mode == DrawMode::Box {
+                assert_eq!(causes[0], DrawCause::Picked);
+                assert!(causes.contains(&DrawCause::Guaranteed));
+            }
+        }
+    }
+
+    #[test]
+    fn test_replay_reports_first_divergence() {
+        let mut gashapon = gashapon(DrawMode::Box);
+        play(&mut gashapon);
+        let log = SessionLog::from_gashapon(&gashapon).unwrap();
+
+        let mut tampered = log.clone();
+        let other = match tampered.draws[2].prize.name.as_str() {
+            "S" => PrizeItem::new("A"),
+            _ => PrizeItem::new("S"),
+        };
+        tampered.draws[2].prize = other;
+        let divergence = tampered.replay().unwrap_err();
+        assert_eq!(divergence.index, 2);
+        assert_eq!(
+            divergence.replayed.unwrap().prize.get_id(),
+            log.draws[2].prize.get_id()
+        );
+
+        let mut tampered = log.clone();
+        tampered.draws[1].cause = DrawCause::Picked;
+        tampered.draws[1].slot = Some(3);
+        let divergence = tampered.replay().unwrap_err();
+        assert_eq!(divergence.index, 1);
+        assert!(divergence.replayed.is_none());
+
+        let mut tampered = log;
+        tampered.seed += 1;
+        assert!(tampered.replay().is_err());
+    }
+
+    #[test]
+    fn test_session_needs_a_seed() {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_item(GashaponItem::new(PrizeItem::new("A")).with_quantity(2))
+            .unwrap();
+        assert_eq!(
+            SessionLog::from_gashapon(&gashapon).unwrap_err(),
+            SessionError::NoSeed
+        );
+
+        // Building keeps the seed the box was shuffled with
+        gashapon.build().unwrap();
+        gashapon.draw();
+        let log = SessionLog::from_gashapon(&gashapon).unwrap();
+        assert_eq!(log.replay().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_session_must_not_be_rebuilt() {
+        let mut gashapon = self::gashapon(DrawMode::Box);
+        gashapon
+            .with_reset_policy(ResetPolicy::RemainingBelow(14))
+            .unwrap();
+        gashapon.draw_with_times(3);
+        assert_eq!(
+            SessionLog::from_gashapon(&gashapon).unwrap_err(),
+            SessionError::Reset
+        );
+
+        let mut gashapon = self::gashapon(DrawMode::Box);
+        gashapon.with_journal(0);
+        gashapon.draw();
+        assert!(SessionLog::from_gashapon(&gashapon).is_ok());
+        gashapon
+            .restore_items()
+            .unwrap()
+            .rebuild_with_new_seed(7)
+            .unwrap();
+        gashapon.draw();
+        assert_eq!(
+            SessionLog::from_gashapon(&gashapon).unwrap_err(),
+            SessionError::Rebuilt
+        );
+
+        // A session starts over once the history is cleared
+        gashapon.restore_items().unwrap().clear_history().unwrap();
+        gashapon.build().unwrap();
+        gashapon.draw();
+        let log = SessionLog::from_gashapon(&gashapon).unwrap();
+        assert_eq!(log.replay().unwrap(), 1);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json_round_trip() {
+        let mut gashapon = gashapon(DrawMode::Box);
+        play(&mut gashapon);
+        let json = SessionLog::from_gashapon(&gashapon)
+            .unwrap()
+            .to_json_string()
+            .unwrap();
+        let log = SessionLog::from_json_str(&json).unwrap();
+        assert_eq!(log.replay().unwrap(), gashapon.history().len());
+
+        let err = SessionLog::from_json_str("{ \"seed\": 1 }").unwrap_err();
+        assert!(err.message.contains("table"));
+    }
+}
@@ -0,0 +1,235 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::{Gashapon, MachineError, PrizeItem};
+
+/// A [`Gashapon`] that can be cloned across threads and drawn from
+/// concurrently.
+///
+/// Every operation holds the machine's lock for its whole duration, so a
+/// capsule is never handed out twice and a batch draw is never interleaved
+/// with other players' draws. Subscribers are called once the lock is
+/// released, so they may use the machine themselves.
+#[derive(Debug, Clone, Default)]
+pub struct SharedGashapon {
+    inner: Arc<Mutex<Gashapon>>,
+}
+
+impl SharedGashapon {
+    pub fn new(gashapon: Gashapon) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(gashapon)),
+        }
+    }
+
+    /// Lock the machine. If a player panicked while holding the lock, keep
+    /// serving the others only if the machine was left consistent.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the machine was left half updated.
+    fn lock(&self) -> MutexGuard<'_, Gashapon> {
+        self.inner.lock().unwrap_or_else(|poisoned| {
+            let mut gashapon = poisoned.into_inner();
+            if let Err(err) = gashapon.check_invariants() {
+                panic!("A draw panicked and left the machine inconsistent: {err}");
+            }
+            gashapon.observers.take_deferred();
+            self.inner.clear_poison();
+            gashapon
+        })
+    }
+
+    /// Run `f` under the lock, then pass the events it raised on to
+    /// subscribers.
+    fn locked<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut Gashapon) -> R,
+    {
+        let (result, events, observers) = {
+            let mut gashapon = self.lock();
+            gashapon.observers.defer();
+            let result = f(&mut gashapon);
+            let events = gashapon.observers.take_deferred();
+            (result, events, gashapon.observers.clone())
+        };
+        for event in &events {
+            observers.notify(event);
+        }
+        result
+    }
+
+    /// Draw one prize.
+    pub fn draw(&self) -> Result<PrizeItem, MachineError> {
+        self.locked(Gashapon::try_draw)
+    }
+
+    /// Draw up to `times` prizes in one atomic batch.
+    pub fn draw_with_times(&self, times: u64) -> Vec<PrizeItem> {
+        self.locked(|gashapon| gashapon.draw_with_times(times))
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.lock().remaining()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    /// Run `f` with exclusive access to the machine. `f` must not use this
+    /// `SharedGashapon` itself, or it deadlocks.
+    pub fn with<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut Gashapon) -> R,
+    {
+        self.locked(f)
+    }
+
+    /// A copy of the machine's current state.
+    pub fn snapshot(&self) -> Gashapon {
+        self.lock().clone()
+    }
+}
+
+impl From<Gashapon> for SharedGashapon {
+    fn from(gashapon: Gashapon) -> Self {
+        Self::new(gashapon)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::thread;
+
+    use super::*;
+    use crate::{Event, GashaponItem};
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_shared_gashapon_is_send_sync() {
+        assert_send_sync::<Gashapon>();
+        assert_send_sync::<SharedGashapon>();
+    }
+
+    #[test]
+    fn test_shared_gashapon_concurrent_draws() {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("S")).with_quantity(5),
+                GashaponItem::new(PrizeItem::new("A")).with_quantity(50),
+                GashaponItem::new(PrizeItem::new("B")).with_quantity(500),
+                GashaponItem::new(PrizeItem::new("C")).with_quantity(2000),
+            ])
+            .unwrap()
+            .with_seed(12345)
+            .unwrap()
+            .build()
+            .unwrap();
+        let shared = SharedGashapon::new(gashapon);
+
+        let workers = (0..16)
+            .map(|i| {
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    let mut drawn = Vec::new();
+                    loop {
+                        let batch = if i % 2 == 0 {
+                            shared.draw().into_iter().collect::<Vec<_>>()
+                        } else {
+                            shared.draw_with_times(3)
+                        };
+                        if batch.is_empty() {
+                            break;
+                        }
+                        drawn.extend(batch);
+                    }
+                    drawn
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut counts = HashMap::<String, u64>::new();
+        for worker in workers {
+            for prize in worker.join().unwrap() {
+                *counts.entry(prize.name).or_default() += 1;
+            }
+        }
+
+        assert_eq!(counts["S"], 5);
+        assert_eq!(counts["A"], 50);
+        assert_eq!(counts["B"], 500);
+        assert_eq!(counts["C"], 2000);
+        assert!(shared.is_empty());
+        assert_eq!(shared.draw().unwrap_err(), MachineError::Empty);
+        shared.with(|gashapon| {
+            assert!(gashapon.items.values().all(|item| item.quantity == 0));
+            assert!(gashapon.prizes.randomized_items.iter().all(Option::is_none));
+            assert_eq!(gashapon.check_invariants(), Ok(()));
+        });
+    }
+
+    #[test]
+    fn test_shared_gashapon_subscribers_can_use_it() {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_item(GashaponItem::new(PrizeItem::new("S")).with_quantity(3))
+            .unwrap()
+            .with_seed(12345)
+            .unwrap()
+            .build()
+            .unwrap();
+        let shared = SharedGashapon::new(gashapon);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let (inner, sink) = (shared.clone(), seen.clone());
+        shared.with(|gashapon| {
+            gashapon.subscribe(move |event| {
+                if let Event::Drawn(_) = event {
+                    sink.lock().unwrap().push(inner.remaining());
+                }
+            })
+        });
+
+        shared.draw().unwrap();
+        shared.draw_with_times(2);
+        // A batch is announced once it is over
+        assert_eq!(*seen.lock().unwrap(), vec![2, 0, 0]);
+    }
+
+    #[test]
+    fn test_shared_gashapon_recovers_from_panics() {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_item(GashaponItem::new(PrizeItem::new("S")).with_quantity(2))
+            .unwrap()
+            .with_seed(12345)
+            .unwrap()
+            .build()
+            .unwrap();
+        let shared = SharedGashapon::new(gashapon);
+
+        let inner = shared.clone();
+        let panicked = thread::spawn(move || {
+            inner.with(|gashapon| {
+                gashapon.draw();
+                panic!("Player left mid-draw");
+            })
+        })
+        .join();
+        assert!(panicked.is_err());
+        assert_eq!(shared.remaining(), 1);
+
+        let inner = shared.clone();
+        let panicked = thread::spawn(move || {
+            inner.with(|gashapon| {
+                gashapon.prizes.idx_box.clear();
+                panic!("Player left mid-draw");
+            })
+        })
+        .join();
+        assert!(panicked.is_err());
+        assert!(thread::spawn(move || shared.remaining()).join().is_err());
+    }
+}
@@ -0,0 +1,463 @@
+//! Monte Carlo simulation of a machine configuration.
+//!
+//! Every session runs on a fresh copy of the machine, rebuilt with its own
+//! seed derived from [`SimulationConfig::with_seed`], so a report is fully
+//! reproducible no matter how many threads were used to produce it.
+
+use std::collections::{HashMap, HashSet};
+use std::thread;
+
+use crate::{DrawMode, Gashapon, GetPrizeItemId, MachineState, PrizeId, PrizeItem};
+
+/// How a simulated player spends their money.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Strategy {
+    /// Keep drawing until the given prize comes out or the box is empty.
+    UntilItem(PrizeId),
+    /// Keep drawing until one of each given prize has come out.
+    CompleteSet(Vec<PrizeId>),
+    /// Draw as many times as the budget allows at the machine's unit price.
+    /// A free machine is drawn as many times as there are capsules.
+    FixedBudget(u64),
+    /// Draw every capsule in the box. Weighted machines never run empty,
+    /// so this draws as many times as there are capsules instead. Unlimited
+    /// items are drawn along the way but never run out.
+    DrawToEmpty,
+}
+
+#[derive(Debug, Clone)]
+pub struct SimulationConfig {
+    pub strategy: Strategy,
+    pub sessions: u64,
+    pub threads: usize,
+    seed: usize,
+}
+
+impl SimulationConfig {
+    pub fn new(strategy: Strategy) -> Self {
+        Self {
+            strategy,
+            sessions: 1000,
+            threads: 1,
+            seed: 0,
+        }
+    }
+
+    pub fn with_sessions(mut self, sessions: u64) -> Self {
+        self.sessions = sessions;
+        self
+    }
+
+    /// Run sessions on up to `threads` worker threads.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Base seed; session `i` runs with `seed + i`.
+    pub fn with_seed(mut self, seed: usize) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn get_seed(&self) -> usize {
+        self.seed
+    }
+}
+
+/// What happened in a single simulated session.
+#[derive(Debug, Clone, Default)]
+pub struct SessionOutcome {
+    pub seed: usize,
+    pub draws: u64,
+    pub cost: u64,
+    /// Number of draws it took to reach the target of [`Strategy::UntilItem`]
+    /// or [`Strategy::CompleteSet`].
+    pub draws_to_target: Option<u64>,
+    /// Total value of the prizes handed out.
+    pub prize_value: u64,
+    pub prizes: HashMap<PrizeId, u64>,
+}
+
+/// A sorted sample of values with summary statistics.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Distribution {
+    values: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bucket {
+    /// Inclusive lower bound.
+    pub start: u64,
+    /// Exclusive upper bound.
+    pub end: u64,
+    pub count: usize,
+}
+
+impl Distribution {
+    pub fn new(mut values: Vec<u64>) -> Self {
+        values.sort_unstable();
+        Self { values }
+    }
+
+    pub fn values(&self) -> &[u64] {
+        &self.values
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn min(&self) -> Option<u64> {
+        self.values.first().copied()
+    }
+
+    pub fn max(&self) -> Option<u64> {
+        self.values.last().copied()
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        if self.values.is_empty() {
+            return None;
+        }
+        let sum = self.values.iter().map(|v| *v as f64).sum::<f64>();
+        Some(sum / self.values.len() as f64)
+    }
+
+    /// Nearest-rank percentile, `p` in `0.0..=100.0`.
+    pub fn percentile(&self, p: f64) -> Option<u64> {
+        if self.values.is_empty() {
+            return None;
+        }
+        let p = p.clamp(0.0, 100.0);
+        let rank = ((p / 100.0) * self.values.len() as f64).ceil() as usize;
+        Some(self.values[rank.saturating_sub(1)])
+    }
+
+    /// Split the range of values into `bins` equal-width buckets.
+    pub fn histogram(&self, bins: usize) -> Vec<Bucket> {
+        let (Some(min), Some(max)) = (self.min(), self.max()) else {
+            return Vec::new();
+        };
+        let bins = bins.max(1) as u64;
+        let width = ((max - min) / bins + 1).max(1);
+        let mut buckets = (0..bins)
+            .map(|i| Bucket {
+                start: min + i * width,
+                end: min + (i + 1) * width,
+                count: 0,
+            })
+            .collect::<Vec<_>>();
+        let last = buckets.len() - 1;
+        for value in &self.values {
+            let idx = ((value - min) / width) as usize;
+            buckets[idx.min(last)].count += 1;
+        }
+        buckets
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SimulationReport {
+    pub sessions: Vec<SessionOutcome>,
+    /// Number of capsules in a full box.
+    pub box_size: u64,
+    prizes: HashMap<PrizeId, PrizeItem>,
+}
+
+impl SimulationReport {
+    pub fn cost(&self) -> Distribution {
+        Distribution::new(self.sessions.iter().map(|s| s.cost).collect())
+    }
+
+    pub fn draws(&self) -> Distribution {
+        Distribution::new(self.sessions.iter().map(|s| s.draws).collect())
+    }
+
+    /// Draws needed to reach the target, only over sessions that reached it.
+    pub fn draws_to_target(&self) -> Distribution {
+        Distribution::new(
+            self.sessions
+                .iter()
+                .filter_map(|s| s.draws_to_target)
+                .collect(),
+        )
+    }
+
+    /// Share of sessions that reached the target.
+    pub fn target_hit_rate(&self) -> f64 {
+        if self.sessions.is_empty() {
+            return 0.0;
+        }
+        self.draws_to_target().len() as f64 / self.sessions.len() as f64
+    }
+
+    pub fn prize_value(&self) -> Distribution {
+        Distribution::new(self.sessions.iter().map(|s| s.prize_value).collect())
+    }
+
+    /// Average revenue collected per full box of capsules drawn.
+    pub fn revenue_per_box(&self) -> f64 {
+        let draws = self.sessions.iter().map(|s| s.draws as f64).sum::<f64>();
+        if draws == 0.0 || self.box_size == 0 {
+            return 0.0;
+        }
+        let revenue = self.sessions.iter().map(|s| s.cost as f64).sum::<f64>();
+        revenue / (draws / self.box_size as f64)
+    }
+
+    /// Total number of each prize handed out over all sessions.
+    pub fn prize_outflow(&self) -> Vec<(PrizeItem, u64)> {
+        let mut outflow = HashMap::<PrizeId, u64>::new();
+        for session in &self.sessions {
+            for (id, count) in &session.prizes {
+                *outflow.entry(id.clone()).or_default() += count;
+            }
+        }
+        outflow
+            .into_iter()
+            .filter_map(|(id, count)| Some((self.prizes.get(&id)?.clone(), count)))
+            .collect()
+    }
+}
+
+/// Run `config.sessions` sessions against copies of `gashapon`.
+pub fn simulate(gashapon: &Gashapon, config: &SimulationConfig) -> SimulationReport {
+    let seeds = (0..config.sessions)
+        .map(|i| config.seed.wrapping_add(i as usize))
+        .collect::<Vec<_>>();
+    let chunk_size = seeds.len().div_ceil(config.threads.max(1)).max(1);
+
+    let sessions = thread::scope(|scope| {
+        let workers = seeds
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|seed| run_session(gashapon, &config.strategy, *seed))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("Simulation worker panicked"))
+            .collect::<Vec<_>>()
+    });
+
+    SimulationReport {
+        sessions,
+        box_size: u64::try_from(gashapon.capsule_count()).unwrap_or(u64::MAX),
+        prizes: gashapon
+            .items
+            .iter()
+            .map(|(id, item)| (id.clone(), item.prize.clone()))
+            .collect(),
+    }
+}
+
+fn run_session(gashapon: &Gashapon, strategy: &Strategy, seed: usize) -> SessionOutcome {
+    let mut gashapon = gashapon.clone();
+    // Simulated sessions should not reach the real machine's subscribers
+    // or journal, and can be run on a paused or closed machine's current
+    // stock. A box that refills itself would never be drawn out.
+    gashapon.journal = None;
+    gashapon.state = MachineState::Draft;
+    gashapon.reset_policies.clear();
+    gashapon
+        .clear_subscribers()
+        .erase_history()
+        .set_seed(seed)
+        .rebuild();
+
+    let mut outcome = SessionOutcome {
+        seed,
+        ..Default::default()
+    };
+    let mut missing = match strategy {
+        Strategy::UntilItem(target) => HashSet::from([target.clone()]),
+        Strategy::CompleteSet(targets) => targets.iter().cloned().collect(),
+        _ => HashSet::new(),
+    };
+    // Targets that can never come out would keep a weighted machine drawing forever
+    if missing
+        .iter()
+        .any(|id| gashapon.items.get(id).is_none_or(|item| item.quantity == 0))
+    {
+        return outcome;
+    }
+
+    let max_draws = match (strategy, gashapon.mode) {
+        (Strategy::FixedBudget(budget), _) => budget
+            .checked_div(gashapon.unit_price)
+            .unwrap_or(gashapon.prizes.quantity() as u64),
+        (Strategy::DrawToEmpty, DrawMode::Weighted) => gashapon.prizes.quantity() as u64,
+        _ => u64::MAX,
+    };
+
+    // Unlimited items keep a box from ever being empty
+    let drawn_out = |gashapon: &Gashapon| match (strategy, gashapon.mode) {
+        (Strategy::DrawToEmpty, DrawMode::Box) => gashapon.remaining() == 0,
+        _ => gashapon.is_empty(),
+    };
+    while outcome.draws < max_draws && !drawn_out(&gashapon) {
+        let prize = gashapon.draw();
+        outcome.draws += 1;
+        outcome.cost = outcome.cost.saturating_add(gashapon.unit_price);
+        outcome.prize_value = outcome.prize_value.saturating_add(prize.value);
+        *outcome.prizes.entry(prize.get_id()).or_default() += 1;
+
+        if missing.remove(&prize.get_id()) && missing.is_empty() {
+            outcome.draws_to_target = Some(outcome.draws);
+            break;
+        }
+    }
+    outcome
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GashaponItem, ResetPolicy};
+
+    fn gashapon() -> Gashapon {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("S").with_value(500)).with_quantity(1),
+                GashaponItem::new(PrizeItem::new("A").with_value(100)).with_quantity(4),
+                GashaponItem::new(PrizeItem::new("B").with_value(10)).with_quantity(15),
+            ])
+            .unwrap()
+            .with_unit_price(50)
+            .unwrap();
+        gashapon
+    }
+
+    #[test]
+    fn test_distribution() {
+        let distribution = Distribution::new(vec![5, 1, 4, 2, 3]);
+        assert_eq!(distribution.min(), Some(1));
+        assert_eq!(distribution.max(), Some(5));
+        assert_eq!(distribution.mean(), Some(3.0));
+        assert_eq!(distribution.percentile(50.0), Some(3));
+        assert_eq!(distribution.percentile(100.0), Some(5));
+
+        let histogram = distribution.histogram(2);
+        assert_eq!(histogram.iter().map(|b| b.count).sum::<usize>(), 5);
+        assert!(Distribution::default().histogram(4).is_empty());
+    }
+
+    #[test]
+    fn test_simulate_draw_to_empty() {
+        let config = SimulationConfig::new(Strategy::DrawToEmpty)
+            .with_sessions(20)
+            .with_seed(12345);
+        let report = simulate(&gashapon(), &config);
+
+        assert_eq!(report.sessions.len(), 20);
+        assert_eq!(report.draws().min(), Some(20));
+        assert_eq!(report.cost().max(), Some(1000));
+        assert_eq!(report.prize_value().min(), Some(1050));
+        assert_eq!(report.revenue_per_box(), 1000.0);
+        let outflow = report.prize_outflow();
+        assert!(
+            outflow
+                .iter()
+                .any(|(prize, count)| prize.name == "S" && *count == 20)
+        );
+    }
+
+    #[test]
+    fn test_simulate_until_item() {
+        let config = SimulationConfig::new(Strategy::UntilItem(PrizeId::new("S")))
+            .with_sessions(50)
+            .with_seed(12345);
+        let report = simulate(&gashapon(), &config);
+
+        assert_eq!(report.target_hit_rate(), 1.0);
+        assert!(report.draws_to_target().max().unwrap() <= 20);
+    }
+
+    #[test]
+    fn test_simulate_fixed_budget() {
+        let config = SimulationConfig::new(Strategy::FixedBudget(175))
+            .with_sessions(10)
+            .with_seed(12345);
+        let report = simulate(&gashapon(), &config);
+
+        assert!(
+            report
+                .sessions
+                .iter()
+                .all(|s| s.draws == 3 && s.cost == 150)
+        );
+    }
+
+    #[test]
+    fn test_simulate_free_weighted_machine() {
+        let mut gashapon = gashapon();
+        gashapon
+            .with_mode(DrawMode::Weighted)
+            .unwrap()
+            .with_unit_price(0)
+            .unwrap();
+
+        let config = SimulationConfig::new(Strategy::FixedBudget(100))
+            .with_sessions(5)
+            .with_seed(12345);
+        let report = simulate(&gashapon, &config);
+        assert!(report.sessions.iter().all(|s| s.draws == 20 && s.cost == 0));
+    }
+
+    #[test]
+    fn test_simulate_ignores_reset_policies() {
+        let mut gashapon = gashapon();
+        gashapon
+            .with_reset_policy(ResetPolicy::RemainingBelow(5))
+            .unwrap();
+
+        let config = SimulationConfig::new(Strategy::DrawToEmpty)
+            .with_sessions(5)
+            .with_seed(12345);
+        let report = simulate(&gashapon, &config);
+        assert!(report.sessions.iter().all(|s| s.draws == 20));
+    }
+
+    #[test]
+    fn test_simulate_weighted() {
+        let mut gashapon = gashapon();
+        gashapon.with_mode(DrawMode::Weighted).unwrap();
+
+        let config = SimulationConfig::new(Strategy::DrawToEmpty)
+            .with_sessions(10)
+            .with_seed(12345);
+        let report = simulate(&gashapon, &config);
+        assert!(report.sessions.iter().all(|s| s.draws == 20));
+
+        let config = SimulationConfig::new(Strategy::CompleteSet(vec![
+            PrizeId::new("S"),
+            PrizeId::new("A"),
+        ]))
+        .with_sessions(10)
+        .with_seed(12345);
+        let report = simulate(&gashapon, &config);
+        assert_eq!(report.target_hit_rate(), 1.0);
+    }
+
+    #[test]
+    fn test_simulate_parallel_is_deterministic() {
+        let config = SimulationConfig::new(Strategy::UntilItem(PrizeId::new("S")))
+            .with_sessions(40)
+            .with_seed(7);
+        let serial = simulate(&gashapon(), &config);
+        let parallel = simulate(&gashapon(), &config.clone().with_threads(4));
+
+        assert_eq!(serial.draws_to_target(), parallel.draws_to_target());
+    }
+}
@@ -0,0 +1,308 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::{DrawMode, Event, Gashapon, MachineEvent};
+
+/// Where a [`Gashapon`] is in its lifecycle.
+///
+/// A machine starts as a [`Draft`](MachineState::Draft) whose prize table
+/// can be edited, and goes [`Live`](MachineState::Live) when it is opened or
+/// its first capsule is drawn. Restocking or clearing a machine puts it back
+/// into draft.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum MachineState {
+    /// Being set up; items can be added, removed and restocked.
+    #[default]
+    Draft,
+    /// Open for draws; the prize table is locked.
+    Live,
+    /// Temporarily closed for draws; the prize table stays locked.
+    Paused,
+    /// Permanently closed.
+    Closed,
+}
+
+impl MachineState {
+    pub fn is_editable(&self) -> bool {
+        *self == MachineState::Draft
+    }
+
+    pub fn can_draw(&self) -> bool {
+        matches!(self, MachineState::Draft | MachineState::Live)
+    }
+}
+
+impl fmt::Display for MachineState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MachineState::Draft => write!(f, "draft"),
+            MachineState::Live => write!(f, "live"),
+            MachineState::Paused => write!(f, "paused"),
+            MachineState::Closed => write!(f, "closed"),
+        }
+    }
+}
+
+/// An operation the machine refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MachineError {
+    /// The prize table cannot be changed in this state.
+    NotEditable(MachineState),
+    /// Draws are not allowed in this state.
+    NotDrawable(MachineState),
+    InvalidTransition {
+        from: MachineState,
+        to: MachineState,
+    },
+    /// There is nothing left to draw.
+    Empty,
+    /// The board has no such slot.
+    SlotOutOfRange {
+        slot: usize,
+        slots: usize,
+    },
+    /// The slot's capsule has already been drawn.
+    SlotOpened(usize),
+    /// Slots can only be picked from a [`DrawMode::Box`] machine.
+    NoBoard(DrawMode),
+    SlotReserved(usize),
+    /// Every unopened slot is reserved, so none can be drawn at random.
+    AllReserved,
+    /// The machine's stock belongs to an [`Arcade`](crate::Arcade) and only
+    /// moves through it.
+    SharedStock,
+}
+
+impl fmt::Display for MachineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MachineError::NotEditable(state) => {
+                write!(
+                    f,
+                    "the prize table cannot be edited while the machine is {state}"
+                )
+            }
+            MachineError::NotDrawable(state) => {
+                write!(f, "cannot draw while the machine is {state}")
+            }
+            MachineError::InvalidTransition { from, to } => {
+                write!(f, "cannot go from {from} to {to}")
+            }
+            MachineError::Empty => write!(f, "no more items to draw"),
+            MachineError::SlotOutOfRange { slot, slots } => {
+                write!(f, "slot {slot} is out of range for a board of {slots}")
+            }
+            MachineError::SlotOpened(slot) => write!(f, "slot {slot} has already been opened"),
+            MachineError::NoBoard(mode) => write!(f, "a {mode} machine has no board"),
+            MachineError::SlotReserved(slot) => write!(f, "slot {slot} is already reserved"),
+            MachineError::AllReserved => write!(f, "every remaining slot is reserved"),
+            MachineError::SharedStock => {
+                write!(f, "the machine's stock is managed by an arcade")
+            }
+        }
+    }
+}
+
+impl Error for MachineError {}
+
+impl Gashapon {
+    pub fn state(&self) -> MachineState {
+        self.state
+    }
+
+    /// Whether the prize table can still be changed.
+    pub fn is_editable(&self) -> bool {
+        self.state.is_editable()
+    }
+
+    /// Lock the prize table and start accepting draws.
+    pub fn open(&mut self) -> Result<&mut Self, MachineError> {
+        self.transition(MachineState::Draft, MachineState::Live)
+    }
+
+    pub fn pause(&mut self) -> Result<&mut Self, MachineError> {
+        self.transition(MachineState::Live, MachineState::Paused)
+    }
+
+    pub fn resume(&mut self) -> Result<&mut Self, MachineError> {
+        self.transition(MachineState::Paused, MachineState::Live)
+    }
+
+    /// Close the machine for good. Nothing can be drawn or edited afterwards.
+    pub fn close(&mut self) -> Result<&mut Self, MachineError> {
+        if self.state == MachineState::Closed {
+            return Err(MachineError::InvalidTransition {
+                from: self.state,
+                to: MachineState::Closed,
+            });
+        }
+        self.set_state(MachineState::Closed);
+        Ok(self)
+    }
+
+    fn transition(
+        &mut self,
+        from: MachineState,
+        to: MachineState,
+    ) -> Result<&mut Self, MachineError> {
+        if self.state != from {
+            return Err(MachineError::InvalidTransition {
+                from: self.state,
+                to,
+            });
+        }
+        self.set_state(to);
+        Ok(self)
+    }
+
+    pub(crate) fn set_state(&mut self, state: MachineState) {
+        let from = std::mem::replace(&mut self.state, state);
+        if from != state {
+            self.record(MachineEvent::StateChanged(state));
+            self.observers.emit(Event::StateChanged { from, to: state });
+        }
+    }
+
+    pub(crate) fn ensure_editable(&self) -> Result<(), MachineError> {
+        match self.state {
+            MachineState::Draft => Ok(()),
+            state => Err(MachineError::NotEditable(state)),
+        }
+    }
+
+    /// Check that the machine's stock is its own to change.
+    pub(crate) fn ensure_own_stock(&self) -> Result<(), MachineError> {
+        match self.shared_stock {
+            true => Err(MachineError::SharedStock),
+            false => Ok(()),
+        }
+    }
+
+    pub(crate) fn check_drawable(&self) -> Result<(), MachineError> {
+        if !self.state.can_draw() {
+            return Err(MachineError::NotDrawable(self.state));
+        }
+        if self.is_empty() {
+            return Err(MachineError::Empty);
+        }
+        Ok(())
+    }
+
+    /// Check that a capsule can be drawn now, going live on the first draw.
+    pub(crate) fn start_draw(&mut self) -> Result<(), MachineError> {
+        self.check_drawable()?;
+        self.set_state(MachineState::Live);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GashaponItem, PrizeId, PrizeItem};
+
+    fn gashapon() -> Gashapon {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("Item1")).with_quantity(2),
+                GashaponItem::new(PrizeItem::new("Item2")).with_quantity(3),
+            ])
+            .unwrap()
+            .with_seed(12345)
+            .unwrap()
+            .build()
+            .unwrap();
+        gashapon
+    }
+
+    #[test]
+    fn test_first_draw_locks_machine() {
+        let mut gashapon = gashapon();
+        assert_eq!(gashapon.state(), MachineState::Draft);
+        gashapon
+            .update_item_quantity(PrizeId::new("Item1"), 3)
+            .unwrap();
+
+        gashapon.draw();
+        assert_eq!(gashapon.state(), MachineState::Live);
+        assert_eq!(
+            gashapon
+                .add_item(GashaponItem::new(PrizeItem::new("Item3")))
+                .unwrap_err(),
+            MachineError::NotEditable(MachineState::Live)
+        );
+        assert!(gashapon.remove_item(PrizeId::new("Item1")).is_err());
+        assert_eq!(gashapon.items().len(), 2);
+
+        gashapon.restore_items().unwrap();
+        assert!(gashapon.is_editable());
+    }
+
+    #[test]
+    fn test_settings_locked_once_drawing() {
+        let mut gashapon = gashapon();
+        gashapon.draw();
+        gashapon.pause().unwrap();
+        for result in [
+            gashapon.with_seed(54321).map(|_| ()),
+            gashapon.with_unit_price(100).map(|_| ()),
+            gashapon.with_mode(crate::DrawMode::Weighted).map(|_| ()),
+            gashapon.with_duplicate_protection(true).map(|_| ()),
+            gashapon
+                .with_guarantee(crate::Guarantee::new(2, ["S"]))
+                .map(|_| ()),
+            gashapon.remove_guarantee().map(|_| ()),
+            gashapon
+                .with_reset_policy(crate::ResetPolicy::RemainingBelow(1))
+                .map(|_| ()),
+            gashapon.clear_reset_policies().map(|_| ()),
+            gashapon
+                .with_exchange_rates(crate::ExchangeRates::new())
+                .map(|_| ()),
+            gashapon.clear_history().map(|_| ()),
+            gashapon.build().map(|_| ()),
+        ] {
+            assert_eq!(result, Err(MachineError::NotEditable(MachineState::Paused)));
+        }
+        assert_eq!(gashapon.remaining(), 4);
+        assert_eq!(gashapon.history().len(), 1);
+    }
+
+    #[test]
+    fn test_pause_and_close() {
+        let mut gashapon = gashapon();
+        assert_eq!(
+            gashapon.pause().unwrap_err(),
+            MachineError::InvalidTransition {
+                from: MachineState::Draft,
+                to: MachineState::Paused,
+            }
+        );
+
+        gashapon.open().unwrap().pause().unwrap();
+        assert_eq!(
+            gashapon.try_draw().unwrap_err(),
+            MachineError::NotDrawable(MachineState::Paused)
+        );
+        assert!(gashapon.draw_with_times(3).is_empty());
+        assert_eq!(gashapon.remaining(), 5);
+
+        gashapon.resume().unwrap();
+        assert_eq!(gashapon.draw_with_times(5).len(), 5);
+        assert_eq!(gashapon.try_draw().unwrap_err(), MachineError::Empty);
+
+        gashapon.close().unwrap();
+        assert!(gashapon.restore_items().is_err());
+        assert!(gashapon.resume().is_err());
+        assert_eq!(
+            gashapon.close().unwrap_err().to_string(),
+            "cannot go from closed to closed"
+        );
+    }
+}
@@ -0,0 +1,440 @@
+//! Loading and saving machines, their histories and players.
+//!
+//! A machine is stored as a [`MachineRecord`]: its [`PrizeTable`], what is
+//! left in stock, its lifecycle state and its box, so a loaded machine
+//! carries on drawing where it left off. Reset policies are kept with the
+//! table.
+//!
+//! [`MemoryStore`] keeps everything in memory, [`JsonFileStore`] in a single
+//! JSON file and, with the `sqlite` feature, [`SqliteStore`] in an SQLite
+//! database.
+
+#[cfg(test)]
+mod conformance;
+#[cfg(feature = "json")]
+mod json;
+mod memory;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::error::Error;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::PrizeTable;
+use crate::registry::Registered;
+use crate::{DrawRecord, Gashapon, Issue, MachineId, MachineState, Player, Registry, ResetRecord};
+
+#[cfg(feature = "json")]
+pub use json::JsonFileStore;
+pub use memory::MemoryStore;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+
+/// One machine as kept by a [`GashaponStore`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MachineRecord {
+    pub id: MachineId,
+    pub name: String,
+    #[serde(default)]
+    pub archived: bool,
+    #[serde(default)]
+    pub state: MachineState,
+    pub table: PrizeTable,
+    /// Quantity left of each item, by name.
+    #[serde(default)]
+    pub stock: BTreeMap<String, u64>,
+    /// The box as it was saved. Without it, the box is reshuffled from the
+    /// table's seed.
+    #[serde(default)]
+    pub contents: Option<BoxRecord>,
+    /// Automatic resets since the history was last cleared.
+    #[serde(default)]
+    pub resets: Vec<ResetRecord>,
+}
+
+/// A machine's box: its pool, board and random state.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BoxRecord {
+    /// Name of the prize in each capsule, in pool order.
+    pub pool: Vec<String>,
+    /// Board slots still to be drawn, in the order they were shuffled.
+    pub queued: Vec<usize>,
+    /// Pool index of the capsule in each unopened slot.
+    pub slots: Vec<Option<usize>>,
+    /// Pool index of the capsule each opened slot held.
+    pub opened: Vec<Option<usize>>,
+    #[serde(default)]
+    pub reserved: BTreeSet<usize>,
+    /// State of the random number generator.
+    pub rng: usize,
+    /// When the first capsule was drawn since the box was last built.
+    #[serde(default)]
+    pub opened_at: Option<u64>,
+    /// The seed the box was last shuffled with.
+    #[serde(default)]
+    pub seed: Option<usize>,
+    /// The seed the box was first shuffled with.
+    #[serde(default)]
+    pub first_seed: Option<usize>,
+}
+
+impl MachineRecord {
+    pub fn from_gashapon<T>(id: MachineId, name: T, gashapon: &Gashapon) -> Self
+    where
+        T: ToString,
+    {
+        Self {
+            id,
+            name: name.to_string(),
+            archived: false,
+            state: gashapon.state(),
+            table: PrizeTable::from_gashapon(gashapon),
+            stock: gashapon
+                .items()
+                .values()
+                .map(|item| (item.prize.name.clone(), item.quantity))
+                .collect(),
+            contents: Some(BoxRecord::from_gashapon(gashapon)),
+            resets: gashapon.resets().to_vec(),
+        }
+    }
+
+    /// Rebuild the machine, with its stock, box and state but no history.
+    /// Fails if its table is invalid or the box does not match the stock.
+    pub fn to_gashapon(&self) -> Result<Gashapon, StoreError> {
+        let invalid =
+            |message: String| StoreError::Format(format!("machine {}: {message}", self.id));
+        let builder = self.table.builder();
+        let validation = builder.validate();
+        // A machine saved before any prize was added has nothing to draw yet
+        if validation
+            .errors
+            .iter()
+            .any(|issue| *issue != Issue::EmptyPool)
+        {
+            return Err(invalid(validation.to_string()));
+        }
+        let mut gashapon = builder.assemble().expect("A new machine is editable");
+        for item in gashapon.items.values_mut() {
+            if let Some(quantity) = self.stock.get(&item.prize.name) {
+                item.quantity = *quantity;
+            }
+        }
+        gashapon.rebuild();
+        if let Some(contents) = &self.contents {
+            contents.restore(&mut gashapon).map_err(invalid)?;
+        }
+        gashapon.resets = self.resets.clone();
+        gashapon.state = self.state;
+        Ok(gashapon)
+    }
+}
+
+impl BoxRecord {
+    pub fn from_gashapon(gashapon: &Gashapon) -> Self {
+        let prizes = &gashapon.prizes;
+        Self {
+            pool: prizes
+                .items
+                .iter()
+                .map(|prize| prize.name.clone())
+                .collect(),
+            queued: prizes.idx_box.clone(),
+            slots: prizes.randomized_items.clone(),
+            opened: prizes.opened.clone(),
+            reserved: prizes.reserved.clone(),
+            rng: prizes.state,
+            opened_at: gashapon.opened_at,
+            seed: gashapon.shuffled_with,
+            first_seed: gashapon.first_seed,
+        }
+    }
+
+    /// Put the box back into a machine already holding the stock it was
+    /// saved with.
+    fn restore(&self, gashapon: &mut Gashapon) -> Result<(), String> {
+        let pool = self
+            .pool
+            .iter()
+            .map(|name| {
+                gashapon
+                    .items
+                    .values()
+                    .find(|item| item.prize.name == *name)
+                    .map(|item| item.prize.clone())
+                    .ok_or_else(|| format!("the box holds \"{name}\" but the table does not"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if self.opened.len() != self.slots.len() {
+            return Err(format!(
+                "the box has {} slots but {} opened entries",
+                self.slots.len(),
+                self.opened.len()
+            ));
+        }
+        let prizes = &mut gashapon.prizes;
+        prizes.items = pool;
+        prizes.idx_box = self.queued.clone();
+        prizes.randomized_items = self.slots.clone();
+        prizes.opened = self.opened.clone();
+        prizes.reserved = self.reserved.clone();
+        prizes.state = self.rng;
+        gashapon.opened_at = self.opened_at;
+        // Boxes saved without their seeds keep the ones just shuffled with
+        gashapon.shuffled_with = self.seed.or(gashapon.shuffled_with);
+        gashapon.first_seed = self.first_seed.or(gashapon.first_seed);
+        gashapon.check_invariants()
+    }
+}
+
+/// A load or save that failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoreError {
+    /// Stored data that cannot be read back, or records that contradict
+    /// each other, such as two machines with the same name.
+    Format(String),
+    /// The underlying file could not be read or written.
+    Io(String),
+    /// The database refused an operation.
+    Backend(String),
+    /// A history was saved for a machine that is not stored.
+    UnknownMachine(MachineId),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Format(message) => write!(f, "invalid stored data: {message}"),
+            StoreError::Io(message) => write!(f, "cannot access the store: {message}"),
+            StoreError::Backend(message) => write!(f, "store error: {message}"),
+            StoreError::UnknownMachine(id) => write!(f, "no stored machine with id {id}"),
+        }
+    }
+}
+
+impl Error for StoreError {}
+
+/// Where machines, their histories and players are loaded from and saved to.
+pub trait GashaponStore {
+    /// Insert the record, or replace the one with the same id.
+    fn save_machine(&mut self, record: &MachineRecord) -> Result<(), StoreError>;
+
+    fn load_machine(&self, id: MachineId) -> Result<Option<MachineRecord>, StoreError>;
+
+    /// Delete a machine and its history. Returns `false` if there was no
+    /// such machine.
+    fn delete_machine(&mut self, id: MachineId) -> Result<bool, StoreError>;
+
+    /// Ids of every stored machine, in ascending order.
+    fn machine_ids(&self) -> Result<Vec<MachineId>, StoreError>;
+
+    /// Replace the history of a stored machine.
+    fn save_history(&mut self, id: MachineId, history: &[DrawRecord]) -> Result<(), StoreError>;
+
+    /// History of a machine, oldest draw first. Empty if none was saved.
+    fn load_history(&self, id: MachineId) -> Result<Vec<DrawRecord>, StoreError>;
+
+    /// Insert the player, or replace the one with the same name.
+    fn save_player(&mut self, player: &Player) -> Result<(), StoreError>;
+
+    fn load_player(&self, name: &str) -> Result<Option<Player>, StoreError>;
+
+    /// Returns `false` if there was no such player.
+    fn delete_player(&mut self, name: &str) -> Result<bool, StoreError>;
+
+    /// Names of every stored player, in ascending order.
+    fn player_names(&self) -> Result<Vec<String>, StoreError>;
+}
+
+impl Registry {
+    /// Save every machine and its history, and delete stored machines that
+    /// are no longer registered.
+    pub fn save<S>(&self, store: &mut S) -> Result<(), StoreError>
+    where
+        S: GashaponStore,
+    {
+        for (id, entry) in &self.machines {
+            let mut record = MachineRecord::from_gashapon(*id, &entry.name, &entry.gashapon);
+            record.archived = entry.archived;
+            store.save_machine(&record)?;
+            store.save_history(*id, entry.gashapon.history())?;
+        }
+        for id in store.machine_ids()? {
+            if !self.machines.contains_key(&id) {
+                store.delete_machine(id)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Load every stored machine, each carrying on from the box it was saved
+    /// with. New ids continue after the highest stored one, so ids of
+    /// machines deleted before the last save may be reused.
+    pub fn load<S>(store: &S) -> Result<Self, StoreError>
+    where
+        S: GashaponStore,
+    {
+        let mut registry = Registry::new();
+        let mut names = HashSet::new();
+        for id in store.machine_ids()? {
+            let record = store
+                .load_machine(id)?
+                .ok_or_else(|| StoreError::Format(format!("machine {id} is listed but missing")))?;
+            if !names.insert(record.name.clone()) {
+                return Err(StoreError::Format(format!(
+                    "more than one machine is named \"{}\"",
+                    record.name
+                )));
+            }
+            let mut gashapon = record.to_gashapon()?;
+            gashapon.history = store.load_history(id)?;
+            registry.next_id = registry.next_id.max(id.get_id() + 1);
+            registry.machines.insert(
+                id,
+                Registered {
+                    gashapon,
+                    name: record.name,
+                    archived: record.archived,
+                },
+            );
+        }
+        Ok(registry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GashaponItem, PrizeItem, ResetPolicy};
+
+    fn gashapon() -> Gashapon {
+        let mut gashapon = Gashapon::default();
+        gashapon
+            .add_items(vec![
+                GashaponItem::new(PrizeItem::new("Item1")).with_quantity(2),
+                GashaponItem::new(PrizeItem::new("Item2")).with_quantity(3),
+            ])
+            .unwrap()
+            .with_seed(12345)
+            .unwrap()
+            .build()
+            .unwrap();
+        gashapon
+    }
+
+    #[test]
+    fn test_registry_round_trip() {
+        let mut registry = Registry::new();
+        let north = registry.create("North", gashapon()).unwrap();
+        let mut resetting = gashapon();
+        resetting
+            .with_reset_policy(ResetPolicy::RemainingBelow(2))
+            .unwrap();
+        let south = registry.create("South", resetting).unwrap();
+        let gone = registry.create("Gone", gashapon()).unwrap();
+        registry.get_mut(north).unwrap().draw_with_times(2);
+        registry.archive(south).unwrap();
+
+        let mut store = MemoryStore::new();
+        registry.save(&mut store).unwrap();
+        registry.delete(gone).unwrap();
+        registry.save(&mut store).unwrap();
+        assert_eq!(store.machine_ids().unwrap(), vec![north, south]);
+
+        let mut loaded = Registry::load(&store).unwrap();
+        let machine = loaded.get(north).unwrap();
+        let original = registry.get(north).unwrap();
+        assert_eq!(machine.remaining(), 3);
+        assert_eq!(machine.history().len(), 2);
+        assert_eq!(machine.state(), MachineState::Live);
+        assert_eq!(machine.check_invariants(), Ok(()));
+        assert_eq!(machine.prizes().idx_box(), original.prizes().idx_box());
+        assert_eq!(machine.shuffled_with, original.shuffled_with);
+        assert_eq!(machine.first_seed, original.first_seed);
+        assert_eq!(
+            machine.prizes().randomized_items(),
+            original.prizes().randomized_items()
+        );
+
+        // The loaded machine draws what the saved one would have
+        let drawn = |gashapon: &mut Gashapon| {
+            gashapon
+                .draw_with_times(3)
+                .into_iter()
+                .map(|prize| prize.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            drawn(loaded.get_mut(north).unwrap()),
+            drawn(registry.get_mut(north).unwrap())
+        );
+        assert!(loaded.is_archived(south));
+        assert_eq!(loaded.name(south), Some("South"));
+        assert_eq!(
+            loaded.get(south).unwrap().reset_policies(),
+            [ResetPolicy::RemainingBelow(2)]
+        );
+
+        let next = loaded.create("Next", gashapon()).unwrap();
+        assert!(next > south);
+    }
+
+    #[test]
+    fn test_load_rejects_duplicate_names() {
+        let mut store = MemoryStore::new();
+        for id in 0..2 {
+            let record = MachineRecord::from_gashapon(MachineId::from(id), "Same", &gashapon());
+            store.save_machine(&record).unwrap();
+        }
+        assert!(matches!(
+            Registry::load(&store).unwrap_err(),
+            StoreError::Format(_)
+        ));
+    }
+
+    #[test]
+    fn test_load_validates_tables() {
+        let mut store = MemoryStore::new();
+        let empty = MachineRecord::from_gashapon(MachineId::from(0), "Empty", &Gashapon::default());
+        store.save_machine(&empty).unwrap();
+        let loaded = Registry::load(&store).unwrap();
+        assert!(loaded.get(MachineId::from(0)).unwrap().is_empty());
+
+        let mut record = MachineRecord::from_gashapon(MachineId::from(1), "Broken", &gashapon());
+        record.table.items[1].name = "Item1".to_string();
+        store.save_machine(&record).unwrap();
+        assert_eq!(
+            Registry::load(&store).unwrap_err().to_string(),
+            "invalid stored data: machine 1: invalid machine: duplicate item name \"Item1\""
+        );
+    }
+
+    #[test]
+    fn test_load_checks_the_box() {
+        let mut gashapon = gashapon();
+        gashapon.draw();
+        let mut record = MachineRecord::from_gashapon(MachineId::from(0), "North", &gashapon);
+        *record.stock.get_mut("Item1").unwrap() += 1;
+        assert!(matches!(
+            record.to_gashapon().unwrap_err(),
+            StoreError::Format(message) if message.contains("in stock but")
+        ));
+
+        let mut record = MachineRecord::from_gashapon(MachineId::from(0), "North", &gashapon);
+        record.contents.as_mut().unwrap().pool[0] = "Item3".to_string();
+        assert_eq!(
+            record.to_gashapon().unwrap_err().to_string(),
+            "invalid stored data: machine 0: the box holds \"Item3\" but the table does not"
+        );
+
+        // Records saved without a box are reshuffled from the seed
+        let mut record = MachineRecord::from_gashapon(MachineId::from(0), "North", &gashapon);
+        record.contents = None;
+        let loaded = record.to_gashapon().unwrap();
+        assert_eq!(loaded.remaining(), 4);
+        assert_eq!(loaded.check_invariants(), Ok(()));
+    }
+}
@@ -0,0 +1,130 @@
+//! Behaviour every [`GashaponStore`] must share, run by each backend's tests.
+
+use super::{GashaponStore, MachineRecord, StoreError};
+use crate::{
+    DrawCause, DrawRecord, Gashapon, GashaponItem, GetPrizeItemId, MachineId, Player, PrizeId,
+    PrizeItem,
+};
+
+fn gashapon() -> Gashapon {
+    let mut gashapon = Gashapon::default();
+    gashapon
+        .add_items(vec![
+            GashaponItem::new(PrizeItem::new("Item1").with_tier("S")).with_quantity(2),
+            GashaponItem::new(PrizeItem::new("Item2")).with_quantity(3),
+        ])
+        .unwrap()
+        .with_seed(12345)
+        .unwrap()
+        .build()
+        .unwrap();
+    gashapon
+}
+
+pub(crate) fn check<S>(mut store: S)
+where
+    S: GashaponStore,
+{
+    machines(&mut store);
+    histories(&mut store);
+    players(&mut store);
+}
+
+fn machines<S>(store: &mut S)
+where
+    S: GashaponStore,
+{
+    let first = MachineId::from(1);
+    let second = MachineId::from(2);
+    assert_eq!(store.load_machine(first).unwrap(), None);
+
+    let mut gashapon = gashapon();
+    store
+        .save_machine(&MachineRecord::from_gashapon(second, "Second", &gashapon))
+        .unwrap();
+    store
+        .save_machine(&MachineRecord::from_gashapon(first, "First", &gashapon))
+        .unwrap();
+    assert_eq!(store.machine_ids().unwrap(), vec![first, second]);
+
+    gashapon.draw();
+    let record = MachineRecord::from_gashapon(first, "First", &gashapon);
+    store.save_machine(&record).unwrap();
+    assert_eq!(store.load_machine(first).unwrap(), Some(record));
+    assert_eq!(store.machine_ids().unwrap().len(), 2);
+
+    assert!(store.delete_machine(second).unwrap());
+    assert!(!store.delete_machine(second).unwrap());
+    assert_eq!(store.machine_ids().unwrap(), vec![first]);
+}
+
+fn histories<S>(store: &mut S)
+where
+    S: GashaponStore,
+{
+    let id = MachineId::from(10);
+    let mut gashapon = gashapon();
+    store
+        .save_machine(&MachineRecord::from_gashapon(id, "History", &gashapon))
+        .unwrap();
+    assert!(store.load_history(id).unwrap().is_empty());
+
+    gashapon.draw_with_times(3);
+    gashapon.history[1].cause = DrawCause::FreeDraw(PrizeId::new("Item1"));
+    store.save_history(id, gashapon.history()).unwrap();
+    let summary = |history: &[DrawRecord]| {
+        history
+            .iter()
+            .map(|record| {
+                (
+                    record.sequence,
+                    record.slot,
+                    record.prize.get_id(),
+                    record.cause.clone(),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    let history = store.load_history(id).unwrap();
+    assert_eq!(summary(&history), summary(gashapon.history()));
+
+    store.save_history(id, &gashapon.history()[..1]).unwrap();
+    assert_eq!(store.load_history(id).unwrap().len(), 1);
+
+    let unknown = MachineId::from(11);
+    assert_eq!(
+        store.save_history(unknown, gashapon.history()).unwrap_err(),
+        StoreError::UnknownMachine(unknown)
+    );
+
+    store.delete_machine(id).unwrap();
+    assert!(store.load_history(id).unwrap().is_empty());
+}
+
+fn players<S>(store: &mut S)
+where
+    S: GashaponStore,
+{
+    assert!(store.load_player("Bob").unwrap().is_none());
+
+    let mut bob = Player::new("Bob");
+    bob.add_prizes(&[PrizeItem::new("Item1"), PrizeItem::new("Item1")]);
+    bob.convert(PrizeId::new("Item1"), 1, 10).unwrap();
+    store.save_player(&bob).unwrap();
+    store.save_player(&Player::new("Alice")).unwrap();
+    assert_eq!(
+        store.player_names().unwrap(),
+        vec!["Alice".to_string(), "Bob".to_string()]
+    );
+
+    bob.add_prize(&PrizeItem::new("Item2"));
+    store.save_player(&bob).unwrap();
+    let loaded = store.load_player("Bob").unwrap().unwrap();
+    assert_eq!(loaded.inventory(), bob.inventory());
+    assert_eq!(loaded.points(), 10);
+    assert_eq!(loaded.ledger(), bob.ledger());
+
+    assert!(store.delete_player("Alice").unwrap());
+    assert!(!store.delete_player("Alice").unwrap());
+    assert_eq!(store.player_names().unwrap(), vec!["Bob".to_string()]);
+}
@@ -0,0 +1,138 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use super::{GashaponStore, MachineRecord, MemoryStore, StoreError};
+use crate::{DrawRecord, MachineId, Player};
+
+/// Keeps everything in a single JSON file, for the demo and the desktop app.
+///
+/// The whole file is read when the store is opened and rewritten on every
+/// change, through a temporary file so a failed write never leaves it half
+/// written.
+#[derive(Debug, Clone)]
+pub struct JsonFileStore {
+    path: PathBuf,
+    data: MemoryStore,
+}
+
+impl JsonFileStore {
+    /// Open the store at `path`. A missing file is an empty store, created on
+    /// the first change.
+    pub fn open<P>(path: P) -> Result<Self, StoreError>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref().to_path_buf();
+        let data = match fs::read_to_string(&path) {
+            Ok(source) => serde_json::from_str(&source)
+                .map_err(|err| StoreError::Format(format!("{}: {err}", path.display())))?,
+            Err(err) if err.kind() == ErrorKind::NotFound => MemoryStore::new(),
+            Err(err) => return Err(StoreError::Io(format!("{}: {err}", path.display()))),
+        };
+        Ok(Self { path, data })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Apply a change to a copy of the data and keep it once it is written.
+    fn update<R, F>(&mut self, change: F) -> Result<R, StoreError>
+    where
+        F: FnOnce(&mut MemoryStore) -> Result<R, StoreError>,
+    {
+        let mut data = self.data.clone();
+        let result = change(&mut data)?;
+        let json = serde_json::to_string_pretty(&data)
+            .map_err(|err| StoreError::Format(err.to_string()))?;
+        let temp = self.path.with_extension("tmp");
+        fs::write(&temp, json)
+            .and_then(|()| fs::rename(&temp, &self.path))
+            .map_err(|err| StoreError::Io(format!("{}: {err}", self.path.display())))?;
+        self.data = data;
+        Ok(result)
+    }
+}
+
+impl GashaponStore for JsonFileStore {
+    fn save_machine(&mut self, record: &MachineRecord) -> Result<(), StoreError> {
+        self.update(|data| data.save_machine(record))
+    }
+
+    fn load_machine(&self, id: MachineId) -> Result<Option<MachineRecord>, StoreError> {
+        self.data.load_machine(id)
+    }
+
+    fn delete_machine(&mut self, id: MachineId) -> Result<bool, StoreError> {
+        self.update(|data| data.delete_machine(id))
+    }
+
+    fn machine_ids(&self) -> Result<Vec<MachineId>, StoreError> {
+        self.data.machine_ids()
+    }
+
+    fn save_history(&mut self, id: MachineId, history: &[DrawRecord]) -> Result<(), StoreError> {
+        self.update(|data| data.save_history(id, history))
+    }
+
+    fn load_history(&self, id: MachineId) -> Result<Vec<DrawRecord>, StoreError> {
+        self.data.load_history(id)
+    }
+
+    fn save_player(&mut self, player: &Player) -> Result<(), StoreError> {
+        self.update(|data| data.save_player(player))
+    }
+
+    fn load_player(&self, name: &str) -> Result<Option<Player>, StoreError> {
+        self.data.load_player(name)
+    }
+
+    fn delete_player(&mut self, name: &str) -> Result<bool, StoreError> {
+        self.update(|data| data.delete_player(name))
+    }
+
+    fn player_names(&self) -> Result<Vec<String>, StoreError> {
+        self.data.player_names()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+    use crate::store::conformance;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("gashapon-{}-{name}.json", process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_conformance() {
+        let path = temp_path("conformance");
+        conformance::check(JsonFileStore::open(&path).unwrap());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_reopen() {
+        let path = temp_path("reopen");
+        let mut store = JsonFileStore::open(&path).unwrap();
+        store.save_player(&Player::new("Alice")).unwrap();
+        drop(store);
+
+        let store = JsonFileStore::open(&path).unwrap();
+        assert_eq!(store.player_names().unwrap(), vec!["Alice".to_string()]);
+
+        fs::write(&path, "{ not json").unwrap();
+        assert!(matches!(
+            JsonFileStore::open(&path).unwrap_err(),
+            StoreError::Format(_)
+        ));
+        fs::remove_file(path).unwrap();
+    }
+}
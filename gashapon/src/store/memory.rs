@@ -0,0 +1,83 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{GashaponStore, MachineRecord, StoreError};
+use crate::{DrawRecord, MachineId, Player};
+
+/// Keeps everything in memory, for tests and short-lived sessions.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MemoryStore {
+    #[serde(default)]
+    machines: BTreeMap<MachineId, MachineRecord>,
+    #[serde(default)]
+    histories: BTreeMap<MachineId, Vec<DrawRecord>>,
+    #[serde(default)]
+    players: BTreeMap<String, Player>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl GashaponStore for MemoryStore {
+    fn save_machine(&mut self, record: &MachineRecord) -> Result<(), StoreError> {
+        self.machines.insert(record.id, record.clone());
+        Ok(())
+    }
+
+    fn load_machine(&self, id: MachineId) -> Result<Option<MachineRecord>, StoreError> {
+        Ok(self.machines.get(&id).cloned())
+    }
+
+    fn delete_machine(&mut self, id: MachineId) -> Result<bool, StoreError> {
+        self.histories.remove(&id);
+        Ok(self.machines.remove(&id).is_some())
+    }
+
+    fn machine_ids(&self) -> Result<Vec<MachineId>, StoreError> {
+        Ok(self.machines.keys().copied().collect())
+    }
+
+    fn save_history(&mut self, id: MachineId, history: &[DrawRecord]) -> Result<(), StoreError> {
+        if !self.machines.contains_key(&id) {
+            return Err(StoreError::UnknownMachine(id));
+        }
+        self.histories.insert(id, history.to_vec());
+        Ok(())
+    }
+
+    fn load_history(&self, id: MachineId) -> Result<Vec<DrawRecord>, StoreError> {
+        Ok(self.histories.get(&id).cloned().unwrap_or_default())
+    }
+
+    fn save_player(&mut self, player: &Player) -> Result<(), StoreError> {
+        self.players.insert(player.name.clone(), player.clone());
+        Ok(())
+    }
+
+    fn load_player(&self, name: &str) -> Result<Option<Player>, StoreError> {
+        Ok(self.players.get(name).cloned())
+    }
+
+    fn delete_player(&mut self, name: &str) -> Result<bool, StoreError> {
+        Ok(self.players.remove(name).is_some())
+    }
+
+    fn player_names(&self) -> Result<Vec<String>, StoreError> {
+        Ok(self.players.keys().cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::conformance;
+
+    #[test]
+    fn test_conformance() {
+        conformance::check(MemoryStore::new());
+    }
+}
@@ -0,0 +1,203 @@
+use std::path::Path;
+
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use super::{GashaponStore, MachineRecord, StoreError};
+use crate::{DrawRecord, MachineId, Player};
+
+const SCHEMA: &str = "
+    PRAGMA foreign_keys = ON;
+    CREATE TABLE IF NOT EXISTS machines (
+        id INTEGER PRIMARY KEY,
+        record TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS history (
+        machine INTEGER NOT NULL REFERENCES machines (id) ON DELETE CASCADE,
+        sequence INTEGER NOT NULL,
+        record TEXT NOT NULL,
+        PRIMARY KEY (machine, sequence)
+    );
+    CREATE TABLE IF NOT EXISTS players (
+        name TEXT PRIMARY KEY,
+        player TEXT NOT NULL
+    );
+";
+
+/// Keeps everything in an SQLite database, one row per machine, draw and
+/// player. Rows hold their record as JSON.
+#[derive(Debug)]
+pub struct SqliteStore {
+    connection: Connection,
+}
+
+impl SqliteStore {
+    /// Open the database at `path`, creating it and its tables if needed.
+    pub fn open<P>(path: P) -> Result<Self, StoreError>
+    where
+        P: AsRef<Path>,
+    {
+        Self::with_connection(Connection::open(path).map_err(backend)?)
+    }
+
+    /// A database that lives as long as the store.
+    pub fn open_in_memory() -> Result<Self, StoreError> {
+        Self::with_connection(Connection::open_in_memory().map_err(backend)?)
+    }
+
+    fn with_connection(connection: Connection) -> Result<Self, StoreError> {
+        connection.execute_batch(SCHEMA).map_err(backend)?;
+        Ok(Self { connection })
+    }
+
+    fn load<T>(&self, sql: &str, key: impl rusqlite::ToSql) -> Result<Option<T>, StoreError>
+    where
+        T: DeserializeOwned,
+    {
+        self.connection
+            .query_row(sql, [key], |row| row.get::<_, String>(0))
+            .optional()
+            .map_err(backend)?
+            .map(|json| from_json(&json))
+            .transpose()
+    }
+}
+
+impl GashaponStore for SqliteStore {
+    fn save_machine(&mut self, record: &MachineRecord) -> Result<(), StoreError> {
+        self.connection
+            .execute(
+                "INSERT INTO machines (id, record) VALUES (?1, ?2)
+                 ON CONFLICT (id) DO UPDATE SET record = excluded.record",
+                params![record.id.get_id() as i64, to_json(record)?],
+            )
+            .map_err(backend)?;
+        Ok(())
+    }
+
+    fn load_machine(&self, id: MachineId) -> Result<Option<MachineRecord>, StoreError> {
+        self.load(
+            "SELECT record FROM machines WHERE id = ?1",
+            id.get_id() as i64,
+        )
+    }
+
+    fn delete_machine(&mut self, id: MachineId) -> Result<bool, StoreError> {
+        let deleted = self
+            .connection
+            .execute("DELETE FROM machines WHERE id = ?1", [id.get_id() as i64])
+            .map_err(backend)?;
+        Ok(deleted > 0)
+    }
+
+    fn machine_ids(&self) -> Result<Vec<MachineId>, StoreError> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT id FROM machines ORDER BY id")
+            .map_err(backend)?;
+        statement
+            .query_map([], |row| row.get::<_, i64>(0))
+            .map_err(backend)?
+            .map(|id| id.map(|id| MachineId::from(id as u64)).map_err(backend))
+            .collect()
+    }
+
+    fn save_history(&mut self, id: MachineId, history: &[DrawRecord]) -> Result<(), StoreError> {
+        if self.load_machine(id)?.is_none() {
+            return Err(StoreError::UnknownMachine(id));
+        }
+        let transaction = self.connection.transaction().map_err(backend)?;
+        transaction
+            .execute(
+                "DELETE FROM history WHERE machine = ?1",
+                [id.get_id() as i64],
+            )
+            .map_err(backend)?;
+        for record in history {
+            transaction
+                .execute(
+                    "INSERT INTO history (machine, sequence, record) VALUES (?1, ?2, ?3)",
+                    params![id.get_id() as i64, record.sequence as i64, to_json(record)?],
+                )
+                .map_err(backend)?;
+        }
+        transaction.commit().map_err(backend)
+    }
+
+    fn load_history(&self, id: MachineId) -> Result<Vec<DrawRecord>, StoreError> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT record FROM history WHERE machine = ?1 ORDER BY sequence")
+            .map_err(backend)?;
+        statement
+            .query_map([id.get_id() as i64], |row| row.get::<_, String>(0))
+            .map_err(backend)?
+            .map(|json| from_json(&json.map_err(backend)?))
+            .collect()
+    }
+
+    fn save_player(&mut self, player: &Player) -> Result<(), StoreError> {
+        self.connection
+            .execute(
+                "INSERT INTO players (name, player) VALUES (?1, ?2)
+                 ON CONFLICT (name) DO UPDATE SET player = excluded.player",
+                params![player.name, to_json(player)?],
+            )
+            .map_err(backend)?;
+        Ok(())
+    }
+
+    fn load_player(&self, name: &str) -> Result<Option<Player>, StoreError> {
+        self.load("SELECT player FROM players WHERE name = ?1", name)
+    }
+
+    fn delete_player(&mut self, name: &str) -> Result<bool, StoreError> {
+        let deleted = self
+            .connection
+            .execute("DELETE FROM players WHERE name = ?1", [name])
+            .map_err(backend)?;
+        Ok(deleted > 0)
+    }
+
+    fn player_names(&self) -> Result<Vec<String>, StoreError> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT name FROM players ORDER BY name")
+            .map_err(backend)?;
+        statement
+            .query_map([], |row| row.get::<_, String>(0))
+            .map_err(backend)?
+            .map(|name| name.map_err(backend))
+            .collect()
+    }
+}
+
+fn backend(err: rusqlite::Error) -> StoreError {
+    StoreError::Backend(err.to_string())
+}
+
+fn to_json<T>(value: &T) -> Result<String, StoreError>
+where
+    T: Serialize,
+{
+    serde_json::to_string(value).map_err(|err| StoreError::Format(err.to_string()))
+}
+
+fn from_json<T>(json: &str) -> Result<T, StoreError>
+where
+    T: DeserializeOwned,
+{
+    serde_json::from_str(json).map_err(|err| StoreError::Format(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::conformance;
+
+    #[test]
+    fn test_conformance() {
+        conformance::check(SqliteStore::open_in_memory().unwrap());
+    }
+}
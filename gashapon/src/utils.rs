@@ -3,6 +3,18 @@ pub fn rng(seed: &mut usize) -> usize {
     (*seed >> 16) & 0x7FFF
 }
 
+/// Random number below `bound`, from as many 15-bit draws as it takes to
+/// cover it so large pools are not cut short.
+pub fn rng_below(seed: &mut usize, bound: u64) -> u64 {
+    let mut value = rng(seed) as u128;
+    let mut range = 1u128 << 15;
+    while range < bound as u128 {
+        value = (value << 15) | rng(seed) as u128;
+        range <<= 15;
+    }
+    (value % bound as u128) as u64
+}
+
 pub fn randomize<T>(data: Vec<T>, seed: &mut usize) -> Vec<T>
 where
     T: Clone,
@@ -15,6 +27,13 @@ where
     item.clone()
 }
 
+pub fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -26,6 +45,24 @@ mod tests {
         assert_eq!(random_number, 21468);
     }
 
+    #[test]
+    fn test_rng_below() {
+        let mut seed = 12345;
+        assert_eq!(rng_below(&mut seed, 32768), 21468);
+
+        let mut seed = 12345;
+        let mut high = 0;
+        for _ in 0..1000 {
+            let r = rng_below(&mut seed, 80000);
+            assert!(r < 80000);
+            high += (r >= 40000) as u32;
+        }
+        assert!(
+            (400..600).contains(&high),
+            "{high} of 1000 in the upper half"
+        );
+    }
+
     #[test]
     fn test_randomize() {
         let mut seed = 12345;
@@ -34,4 +71,11 @@ mod tests {
         assert_ne!(randomized_data, data);
         assert_eq!(randomized_data.len(), data.len());
     }
+
+    #[test]
+    fn test_gcd() {
+        assert_eq!(gcd(12, 18), 6);
+        assert_eq!(gcd(7, 0), 7);
+        assert_eq!(gcd(0, 0), 0);
+    }
 }
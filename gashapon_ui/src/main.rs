@@ -1,9 +1,46 @@
 use dioxus::{logger::tracing, prelude::*};
-use gashapon::{Gashapon, GashaponItem, PrizeItem};
+#[cfg(not(target_arch = "wasm32"))]
+use gashapon::store::JsonFileStore;
+use gashapon::{
+    BoardSlot, BuildError, Gashapon, GashaponBuilder, GashaponItem, MachineId, PrizeItem, Registry,
+};
 
 const MAIN_CSS: Asset = asset!("/assets/main.css");
 
-#[derive(Debug, Clone)]
+/// Where native builds keep their machines between runs.
+#[cfg(not(target_arch = "wasm32"))]
+const STORE_PATH: &str = "gashapon.json";
+
+/// Machines saved by a previous run, if there are any.
+#[cfg(not(target_arch = "wasm32"))]
+fn load_registry() -> Option<Registry> {
+    match JsonFileStore::open(STORE_PATH).and_then(|store| Registry::load(&store)) {
+        Ok(registry) if !registry.is_empty() => Some(registry),
+        Ok(_) => None,
+        Err(err) => {
+            tracing::warn!("Cannot load machines: {}", err);
+            None
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn load_registry() -> Option<Registry> {
+    None
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn save_registry(registry: &Registry) {
+    let result = JsonFileStore::open(STORE_PATH).and_then(|mut store| registry.save(&mut store));
+    if let Err(err) = result {
+        tracing::warn!("Cannot save machines: {}", err);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn save_registry(_registry: &Registry) {}
+
+#[derive(Debug, Clone, Default)]
 pub struct Prizes {
     pub temp_prize: String,
     pub temp_count: u64,
@@ -11,61 +48,137 @@ pub struct Prizes {
     pub drawed_items: Vec<PrizeItem>,
 }
 
-impl Default for Prizes {
-    fn default() -> Self {
-        Self {
-            temp_prize: String::new(),
-            temp_count: 0,
-            draw_rate: Vec::new(),
-            drawed_items: Vec::new(),
-        }
-    }
-}
-
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Copy, Debug)]
 pub struct Data {
     pub unit_price: Signal<u64>,
     pub prizes: Signal<Prizes>,
     pub draw_times: Signal<u64>,
-    pub gashapon: Signal<Gashapon>,
+    pub registry: Signal<Registry>,
+    /// The machine being shown and edited.
+    pub current: Signal<MachineId>,
     pub is_locked: Signal<bool>,
-    pub prize_pool: Signal<Vec<Option<PrizeItem>>>,
+    pub prize_pool: Signal<Vec<BoardSlot>>,
     pub total_price_in_pool: Signal<u64>,
     pub current_cost: Signal<u64>,
 }
 
+/// Starts with the machines saved by a previous run, or a single empty one.
+impl Default for Data {
+    fn default() -> Self {
+        let mut registry = load_registry().unwrap_or_default();
+        let loaded = registry
+            .active()
+            .chain(registry.machines())
+            .next()
+            .map(|(id, _, _)| id);
+        let current = match loaded {
+            Some(id) => id,
+            None => registry
+                .create("Machine 1", Gashapon::default())
+                .expect("An empty registry accepts any name"),
+        };
+        let gashapon = registry
+            .get(current)
+            .expect("Current machine is registered");
+        let prizes = Prizes {
+            draw_rate: gashapon
+                .calculate_draw_rate()
+                .into_iter()
+                .map(|(i, r)| (i.clone().prize, r))
+                .collect(),
+            drawed_items: gashapon
+                .history()
+                .iter()
+                .map(|record| record.prize.clone())
+                .collect(),
+            ..Prizes::default()
+        };
+        let is_locked = !gashapon.is_editable();
+        let prize_pool = gashapon.board();
+        Self {
+            unit_price: Signal::new(0),
+            prizes: Signal::new(prizes),
+            draw_times: Signal::new(0),
+            registry: Signal::new(registry),
+            current: Signal::new(current),
+            is_locked: Signal::new(is_locked),
+            prize_pool: Signal::new(prize_pool),
+            total_price_in_pool: Signal::new(0),
+            current_cost: Signal::new(0),
+        }
+    }
+}
+
 impl Data {
+    pub fn with_machine<R>(&self, f: impl FnOnce(&Gashapon) -> R) -> R {
+        let registry = self.registry.read();
+        f(registry
+            .get(*self.current.read())
+            .expect("Current machine is registered"))
+    }
+
+    pub fn with_machine_mut<R>(&mut self, f: impl FnOnce(&mut Gashapon) -> R) -> R {
+        let current = *self.current.read();
+        let mut registry = self.registry.write();
+        f(registry
+            .get_mut(current)
+            .expect("Current machine is registered"))
+    }
+
+    /// Show another machine, with the prizes drawn from it so far.
+    pub fn select(&mut self, id: MachineId) {
+        self.current.set(id);
+        self.update_drawn();
+        self.update_prizes();
+        self.update_price();
+    }
+
+    /// List the prizes in the current machine's history.
+    pub fn update_drawn(&mut self) {
+        let drawn = self.with_machine(|gashapon| {
+            gashapon
+                .history()
+                .iter()
+                .map(|record| record.prize.clone())
+                .collect()
+        });
+        self.prizes.write().drawed_items = drawn;
+    }
+
     pub fn update_prizes(&mut self) {
-        self.prizes.write().draw_rate = self
-            .gashapon
-            .read()
-            .calculate_draw_rate()
-            .into_iter()
-            .map(|(i, r)| (i.clone().prize, r))
-            .collect::<Vec<_>>();
-        let new_prize = self
-            .gashapon
-            .read()
-            .prizes
-            .get_randomized_items()
-            .into_iter()
-            .map(|x| x.cloned())
-            .collect::<Vec<_>>();
-        self.prize_pool.set(new_prize);
+        self.prizes.write().draw_rate = self.with_machine(|gashapon| {
+            gashapon
+                .calculate_draw_rate()
+                .into_iter()
+                .map(|(i, r)| (i.clone().prize, r))
+                .collect::<Vec<_>>()
+        });
+        let board = self.with_machine(Gashapon::board);
+        self.prize_pool.set(board);
+        self.is_locked
+            .set(!self.with_machine(Gashapon::is_editable));
+    }
+
+    /// Refresh everything that depends on a prize having just been drawn.
+    pub fn on_drawn(&mut self, prize: PrizeItem) {
+        tracing::debug!("Drawn prize: {:?}", prize);
+        self.prizes.write().drawed_items.push(prize);
+        self.update_prizes();
+        self.update_price();
     }
 
     pub fn update_price(&mut self) {
-        self.total_price_in_pool.set(
-            self.gashapon
-                .read()
-                .items
-                .iter()
-                .map(|(_, i)| i.quantity * self.unit_price.read().clone())
-                .sum::<u64>(),
-        );
+        let unit_price = *self.unit_price.read();
+        self.total_price_in_pool.set(self.with_machine(|gashapon| {
+            gashapon
+                .items()
+                .values()
+                .map(|i| i.quantity.saturating_mul(unit_price))
+                .fold(0, u64::saturating_add)
+        }));
 
         self.current_cost
-            .set((self.prizes.read().drawed_items.len() as u64) * self.unit_price.read().clone());
+            .set((self.prizes.read().drawed_items.len() as u64) * *self.unit_price.read());
     }
 }
 
@@ -76,13 +189,16 @@ fn main() {
 #[component]
 fn App() -> Element {
     // Initialize the Gashapon with default items
-    use_context_provider(|| Data::default());
+    let data = use_context_provider(Data::default);
+    // Save the machines whenever one of them changes
+    use_effect(move || save_registry(&data.registry.read()));
 
     rsx! {
         document::Link { rel: "stylesheet", href: MAIN_CSS }
         document::Title { "Gashapon Simulator" }
         AppLayout {
             h1 { "Gashapon Simulator" }
+            Machines {}
             UnitPrice {}
             PrizeList {}
             Pool {}
@@ -112,6 +228,106 @@ pub fn AppLayout(children: Element) -> Element {
     }
 }
 
+#[component]
+pub fn Machines() -> Element {
+    let mut data = use_context::<Data>();
+    let mut new_name = use_signal(String::new);
+    let current = *data.current.read();
+    let machines = {
+        let registry = data.registry.read();
+        registry
+            .machines()
+            .map(|(id, name, _)| (id, name.to_string(), registry.is_archived(id)))
+            .collect::<Vec<_>>()
+    };
+
+    rsx! {
+        div { id: "machines",
+            label { r#for: "machine-select", "Machine: " }
+            select {
+                id: "machine-select",
+                onchange: move |e| {
+                    if let Ok(id) = e.value().parse::<u64>() {
+                        data.select(MachineId::from(id));
+                    }
+                },
+                for (id , name , archived) in machines.iter() {
+                    option {
+                        value: "{id}",
+                        selected: *id == current,
+                        if *archived {
+                            "{name} (archived)"
+                        } else {
+                            "{name}"
+                        }
+                    }
+                }
+            }
+            input {
+                id: "machine-name-input",
+                placeholder: "Machine Name",
+                value: "{new_name}",
+                oninput: move |e| new_name.set(e.value()),
+            }
+            button {
+                class: "mr-5",
+                onclick: move |_| {
+                    let result = data.registry.write().create(new_name.read().clone(), Gashapon::default());
+                    match result {
+                        Ok(id) => {
+                            new_name.set(String::new());
+                            data.select(id);
+                        }
+                        Err(err) => tracing::warn!("Cannot create machine: {}", err),
+                    }
+                },
+                "New"
+            }
+            button {
+                class: "mr-5",
+                onclick: move |_| {
+                    let result = data.registry.write().clone_machine(current, new_name.read().clone());
+                    match result {
+                        Ok(id) => {
+                            new_name.set(String::new());
+                            data.select(id);
+                        }
+                        Err(err) => tracing::warn!("Cannot clone machine: {}", err),
+                    }
+                },
+                "Clone"
+            }
+            button {
+                class: "mr-5",
+                disabled: data.registry.read().is_archived(current),
+                onclick: move |_| {
+                    if let Err(err) = data.registry.write().archive(current) {
+                        tracing::warn!("Cannot archive machine: {}", err);
+                        return;
+                    }
+                    data.update_prizes();
+                },
+                "Archive"
+            }
+            button {
+                class: "mr-5 btn-error",
+                disabled: machines.len() < 2,
+                onclick: move |_| {
+                    if let Err(err) = data.registry.write().delete(current) {
+                        tracing::warn!("Cannot delete machine: {}", err);
+                        return;
+                    }
+                    let next = data.registry.read().machines().next().map(|(id, _, _)| id);
+                    if let Some(id) = next {
+                        data.select(id);
+                    }
+                },
+                "Delete"
+            }
+        }
+    }
+}
+
 #[component]
 pub fn UnitPrice() -> Element {
     let mut data = use_context::<Data>();
@@ -153,6 +369,7 @@ pub fn PrizeList() -> Element {
                 input {
                     id: "prize-name-input",
                     placeholder: "Prize Name",
+                    disabled: *data.is_locked.read(),
                     value: "{data.prizes.read().temp_prize}",
                     oninput: move |e| {
                         data.prizes.write().temp_prize = e.value();
@@ -162,23 +379,38 @@ pub fn PrizeList() -> Element {
                 input {
                     id: "prize-count-input",
                     r#type: "number",
+                    disabled: *data.is_locked.read(),
                     value: "{data.prizes.read().temp_count}",
                     oninput: move |e| {
                         data.prizes.write().temp_count = e.value().parse().unwrap_or(0);
                     },
                 }
                 button {
+                    disabled: *data.is_locked.read(),
                     onclick: move |_| {
                         let mut data = use_context::<Data>();
                         let prize_name = data.prizes.read().temp_prize.clone();
-                        let prize_count = data.prizes.read().temp_count.clone();
+                        let prize_count = data.prizes.read().temp_count;
                         tracing::debug!("Adding prize: {}, Count: {}", prize_name, prize_count);
                         if !prize_name.is_empty() && prize_count > 0 {
                             let prize_item = PrizeItem::new(prize_name.clone());
                             let gashapon_item = GashaponItem::new(prize_item)
-                                .with_quantity(prize_count.clone());
-                            data.gashapon.write().add_item(gashapon_item);
-                            data.gashapon.write().build();
+                                .with_quantity(prize_count);
+                            let result = data
+                                .with_machine_mut(|gashapon| {
+                                    // Check the prize fits before adding it
+                                    let validation = GashaponBuilder::from(&*gashapon)
+                                        .add_item(gashapon_item.clone())
+                                        .validate();
+                                    if !validation.is_ok() {
+                                        return Err(BuildError::Invalid(validation));
+                                    }
+                                    gashapon.add_item(gashapon_item)?.try_build().map(|_| ())
+                                });
+                            if let Err(err) = result {
+                                tracing::warn!("Cannot add prize: {}", err);
+                                return;
+                            }
                             data.prizes.write().temp_prize = String::new();
                             data.prizes.write().temp_count = 0;
                             data.update_prizes();
@@ -187,15 +419,20 @@ pub fn PrizeList() -> Element {
                         } else {
                             tracing::warn!("Prize name or count is invalid.");
                         }
-                        tracing::debug!("Current prize pool: {:?}", data.gashapon.read());
+                        data.with_machine(|gashapon| tracing::debug!("Current prize pool: {:?}", gashapon));
                     },
                     "Add"
                 }
+                if *data.is_locked.read() {
+                    p { class: "locked-note",
+                        "Prizes are locked once drawing has started. Restore or clear the machine to edit them."
+                    }
+                }
             }
             div { id: "prize-items",
                 h3 { "Current Prizes" }
                 ul { class: "prize-items",
-                    for (_ , item) in data.gashapon.read().items.iter() {
+                    for item in data.with_machine(|gashapon| gashapon.items().values().cloned().collect::<Vec<_>>()) {
                         li { "{item.prize.name} ({item.quantity})" }
                     }
                 }
@@ -215,20 +452,20 @@ pub fn PrizeList() -> Element {
 
 #[component]
 pub fn Pool() -> Element {
-    let data = use_context::<Data>();
+    let mut data = use_context::<Data>();
     let mut display_prize_pool = use_signal(|| false);
     rsx! {
         div { id: "pool-items",
             h3 {
                 onclick: move |_| {
-                    let x = display_prize_pool.read().clone();
+                    let x = *display_prize_pool.read();
                     display_prize_pool.set(!x);
                 },
                 "Pool "
                 span {
                     class: "toggle-icon",
                     style: "cursor: pointer;font-size: 0.75em;",
-                    if display_prize_pool.read().clone() {
+                    if *display_prize_pool.read() {
                         "▲"
                     } else {
                         "▼"
@@ -236,21 +473,39 @@ pub fn Pool() -> Element {
                 }
             }
             span {
-                "Total Items in Pool: {data.prize_pool.read().len() - data.prizes.read().drawed_items.len()}"
+                "Total Items in Pool: {data.with_machine(Gashapon::remaining)}"
             }
             div { id: "show-prize-pool",
-                if display_prize_pool.read().clone() {
-                    ul { class: "prize-items",
-                        for item in data.prize_pool
+                if *display_prize_pool.read() {
+                    div { class: "pool-grid",
+                        for (slot , label , hidden) in data.prize_pool
                             .read()
                             .iter()
-                            .map(|i| match i {
-                                Some(prize) => prize.name.clone(),
-                                None => "-".to_string(),
+                            .enumerate()
+                            .map(|(slot, board_slot)| {
+                                let label = match board_slot {
+                                    BoardSlot::Hidden => format!("#{}", slot + 1),
+                                    BoardSlot::Reserved => "Reserved".to_string(),
+                                    BoardSlot::Opened(prize) => prize.name.clone(),
+                                    BoardSlot::Withdrawn => "Withdrawn".to_string(),
+                                };
+                                (slot, label, board_slot.is_hidden())
                             })
                             .collect::<Vec<_>>()
                         {
-                            li { "{item}" }
+                            button {
+                                class: if hidden { "pool-slot" } else { "pool-slot opened" },
+                                title: "Slot {slot + 1}",
+                                disabled: !hidden,
+                                onclick: move |_| {
+                                    let result = data.with_machine_mut(|gashapon| gashapon.draw_at(slot));
+                                    match result {
+                                        Ok(prize) => data.on_drawn(prize),
+                                        Err(err) => tracing::warn!("Cannot open slot: {}", err),
+                                    }
+                                },
+                                "{label}"
+                            }
                         }
                     }
                 }
@@ -267,15 +522,11 @@ pub fn DrawButton() -> Element {
         button {
             class: "mr-5",
             onclick: move |_| {
-                if data.gashapon.read().prizes.idx_box.is_empty() {
-                    tracing::warn!("No more items left to draw.");
-                    return;
+                let result = data.with_machine_mut(Gashapon::try_draw);
+                match result {
+                    Ok(prize) => data.on_drawn(prize),
+                    Err(err) => tracing::warn!("Cannot draw: {}", err),
                 }
-                let my_prize = data.gashapon.write().draw();
-                data.prizes.write().drawed_items.push(my_prize.clone());
-                tracing::debug!("Drawn prize: {:?}", my_prize);
-                data.update_prizes();
-                data.update_price();
             },
             "Draw Prize"
         }
@@ -289,8 +540,12 @@ pub fn RestoreButton() -> Element {
         button {
             class: "mr-5",
             onclick: move |_| {
-                data.gashapon.write().restore_items();
-                data.prizes.write().drawed_items.clear();
+                if let Err(err) = data.with_machine_mut(|gashapon| gashapon.restore_items().map(|_| ())) {
+                    tracing::warn!("Cannot restore items: {}", err);
+                    return;
+                }
+                // Restoring keeps the draw history
+                data.update_drawn();
                 data.update_prizes();
                 data.update_price();
                 tracing::debug!("Items restored to original state.");
@@ -307,9 +562,11 @@ pub fn ClearButton() -> Element {
         button {
             class: "mr-5 btn-error",
             onclick: move |_| {
-                data.gashapon.write().items.clear();
-                data.gashapon.write().prizes.items.clear();
-                data.gashapon.write().prizes.randomized_items.clear();
+                if let Err(err) = data.with_machine_mut(|gashapon| gashapon.clear().map(|_| ())) {
+                    tracing::warn!("Cannot clear items: {}", err);
+                    return;
+                }
+                data.is_locked.set(false);
                 data.prizes.write().drawed_items.clear();
                 data.prize_pool.write().clear();
                 data.prizes.write().draw_rate.clear();